@@ -0,0 +1,428 @@
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{imageops, AnimationDecoder, Frame, Frames};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use crate::storage;
+
+/// Longest side in pixels of the animated preview
+pub const PREVIEW_SIZE: u32 = 256;
+
+/// Encoder speed of the preview, from 1 (best colors) to 30 (fastest)
+const PREVIEW_SPEED: i32 = 10;
+
+/// Browsers play frames shorter than 20ms at 100ms
+const MIN_GIF_DELAY_MS: u64 = 20;
+const DEFAULT_GIF_DELAY_MS: u64 = 100;
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+    pub frame_count: u32,
+    /// Loop count stored in the file, 0 loops forever
+    pub loop_count: u32,
+    /// Total duration of one loop in milliseconds
+    pub duration: u64,
+}
+
+/// Animation of a GIF, APNG or WebP file, `None` for still images
+pub fn read_animation(file_path: &Path) -> io::Result<Option<Animation>> {
+    let bytes = fs::read(file_path)?;
+
+    let animation = if bytes.starts_with(b"GIF8") {
+        gif_animation(&bytes)
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        apng_animation(&bytes)
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        webp_animation(&bytes)
+    } else {
+        None
+    };
+
+    Ok(animation.filter(|animation| animation.frame_count > 1))
+}
+
+/// Write a downscaled, looping GIF of an animated image to `path`
+pub fn generate_preview(media_path: &Path, path: &Path) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut bytes, PREVIEW_SPEED);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(io::Error::other)?;
+
+        // Frames are decoded, resized and encoded one at a time to bound memory
+        for (index, frame) in decode_frames(media_path)?.enumerate() {
+            let frame = match frame {
+                Ok(frame) => frame,
+                // Truncated files keep the frames decoded before the cut
+                Err(_) if index >= 2 => break,
+                Err(err) => return Err(io::Error::other(err)),
+            };
+            let delay = frame.delay();
+            let buffer = frame.into_buffer();
+
+            let scale = PREVIEW_SIZE as f32 / buffer.width().max(buffer.height()) as f32;
+            let buffer = if scale < 1.0 {
+                let width = ((buffer.width() as f32 * scale).round() as u32).max(1);
+                let height = ((buffer.height() as f32 * scale).round() as u32).max(1);
+                imageops::resize(&buffer, width, height, imageops::FilterType::Triangle)
+            } else {
+                buffer
+            };
+
+            encoder
+                .encode_frame(Frame::from_parts(buffer, 0, 0, delay))
+                .map_err(io::Error::other)?;
+        }
+    }
+
+    storage::write_atomic(path, bytes)
+}
+
+fn decode_frames(media_path: &Path) -> io::Result<Frames<'static>> {
+    let reader = || File::open(media_path).map(BufReader::new);
+    let mut head = Vec::new();
+    File::open(media_path)?.take(12).read_to_end(&mut head)?;
+
+    let frames = if head.starts_with(b"GIF8") {
+        GifDecoder::new(reader()?).map(|decoder| decoder.into_frames())
+    } else if head.starts_with(b"\x89PNG") {
+        PngDecoder::new(reader()?).map(|decoder| decoder.apng().into_frames())
+    } else if head.starts_with(b"RIFF") {
+        WebPDecoder::new(reader()?).map(|decoder| decoder.into_frames())
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Only GIF, APNG and WebP animations are supported",
+        ));
+    };
+
+    frames.map_err(io::Error::other)
+}
+
+fn gif_animation(bytes: &[u8]) -> Option<Animation> {
+    let mut animation = Animation {
+        // Without a NETSCAPE extension the animation plays once
+        loop_count: 1,
+        ..Default::default()
+    };
+
+    // Header and logical screen descriptor, then the global color table
+    let packed = *bytes.get(10)?;
+    let mut pos = 13 + color_table_size(packed);
+    let mut delay = None;
+
+    loop {
+        match bytes.get(pos) {
+            Some(0x21 | 0x2C) | None => {}
+            // Trailer
+            Some(0x3B) => break,
+            Some(_) => return None,
+        }
+
+        match gif_block(bytes, pos, &mut delay, &mut animation) {
+            Some(next) => pos = next,
+            // Browsers still play truncated files up to their last complete frame
+            None => return (animation.frame_count >= 2).then_some(animation),
+        }
+    }
+
+    Some(animation)
+}
+
+/// Read the extension or image block at `pos`, `None` if it is truncated
+fn gif_block(
+    bytes: &[u8],
+    pos: usize,
+    delay: &mut Option<u64>,
+    animation: &mut Animation,
+) -> Option<usize> {
+    if *bytes.get(pos)? == 0x21 {
+        let label = *bytes.get(pos + 1)?;
+        let block = bytes.get(pos + 3..pos + 3 + *bytes.get(pos + 2)? as usize)?;
+        match label {
+            0xF9 if block.len() >= 3 => {
+                *delay = Some(u16::from_le_bytes([block[1], block[2]]) as u64 * 10);
+            }
+            0xFF if block.starts_with(b"NETSCAPE2.0") => {
+                let loops = bytes.get(pos + 3 + block.len()..pos + 3 + block.len() + 4)?;
+                if loops[0] >= 3 && loops[1] == 1 {
+                    animation.loop_count = u16::from_le_bytes([loops[2], loops[3]]) as u32;
+                }
+            }
+            _ => {}
+        }
+        return skip_sub_blocks(bytes, pos + 2);
+    }
+
+    // Image descriptor, its local color table and image data
+    let packed = *bytes.get(pos + 9)?;
+    let next = skip_sub_blocks(bytes, pos + 10 + color_table_size(packed) + 1)?;
+
+    animation.frame_count += 1;
+    animation.duration += match delay.take() {
+        Some(delay) if delay >= MIN_GIF_DELAY_MS => delay,
+        _ => DEFAULT_GIF_DELAY_MS,
+    };
+    Some(next)
+}
+
+fn color_table_size(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        0
+    } else {
+        3 * (1 << ((packed & 0x07) + 1))
+    }
+}
+
+/// Position after the sub-blocks starting at `pos`
+fn skip_sub_blocks(bytes: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let size = *bytes.get(pos)? as usize;
+        pos += 1 + size;
+        if size == 0 {
+            return Some(pos);
+        }
+    }
+}
+
+fn apng_animation(bytes: &[u8]) -> Option<Animation> {
+    let mut animation = None;
+    let mut duration = 0;
+    let mut pos = 8;
+
+    while let Some(header) = bytes.get(pos..pos + 8) {
+        let length = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+        let data = bytes.get(pos + 8..pos + 8 + length)?;
+
+        match &header[4..] {
+            b"acTL" if data.len() >= 8 => {
+                animation = Some(Animation {
+                    frame_count: u32::from_be_bytes(data[..4].try_into().ok()?),
+                    loop_count: u32::from_be_bytes(data[4..8].try_into().ok()?),
+                    duration: 0,
+                });
+            }
+            b"fcTL" if data.len() >= 24 => {
+                let numerator = u16::from_be_bytes([data[20], data[21]]) as u64;
+                let denominator = match u16::from_be_bytes([data[22], data[23]]) {
+                    0 => 100,
+                    denominator => denominator as u64,
+                };
+                duration += numerator * 1000 / denominator;
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+
+        // Data is followed by its CRC
+        pos += 12 + length;
+    }
+
+    animation.map(|animation| Animation {
+        duration,
+        ..animation
+    })
+}
+
+fn webp_animation(bytes: &[u8]) -> Option<Animation> {
+    let mut animation: Option<Animation> = None;
+    let mut pos = 12;
+
+    while let Some(header) = bytes.get(pos..pos + 8) {
+        let length = u32::from_le_bytes(header[4..].try_into().ok()?) as usize;
+        let data = bytes.get(pos + 8..pos + 8 + length)?;
+
+        match &header[..4] {
+            b"ANIM" if data.len() >= 6 => {
+                animation = Some(Animation {
+                    loop_count: u16::from_le_bytes([data[4], data[5]]) as u32,
+                    ..Default::default()
+                });
+            }
+            b"ANMF" if data.len() >= 16 => {
+                let animation = animation.as_mut()?;
+                animation.frame_count += 1;
+                animation.duration += u32::from_le_bytes([data[12], data[13], data[14], 0]) as u64;
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even size
+        pos += 8 + length + (length & 1);
+    }
+
+    animation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Delay, Rgba, RgbaImage};
+
+    fn frame(color: [u8; 4], delay_ms: u32) -> Frame {
+        Frame::from_parts(
+            RgbaImage::from_pixel(600, 300, Rgba(color)),
+            0,
+            0,
+            Delay::from_numer_denom_ms(delay_ms, 1),
+        )
+    }
+
+    #[test]
+    fn test_gif_animation() {
+        let base_path = Path::new("test_gif_animation");
+        fs::create_dir_all(base_path).unwrap();
+        let gif_path = base_path.join("animated.gif");
+
+        {
+            let file = File::create(&gif_path).unwrap();
+            let mut encoder = GifEncoder::new_with_speed(file, 30);
+            encoder.set_repeat(Repeat::Finite(3)).unwrap();
+            encoder
+                .encode_frames([
+                    frame([255, 0, 0, 255], 200),
+                    frame([0, 255, 0, 255], 300),
+                    frame([0, 0, 255, 255], 0),
+                ])
+                .unwrap();
+        }
+
+        let animation = read_animation(&gif_path).unwrap().unwrap();
+        assert_eq!(
+            animation,
+            Animation {
+                frame_count: 3,
+                loop_count: 3,
+                // The last frame has no delay so it plays like browsers do
+                duration: 200 + 300 + DEFAULT_GIF_DELAY_MS,
+            }
+        );
+
+        let preview_path = base_path.join("preview.gif");
+        generate_preview(&gif_path, &preview_path).unwrap();
+        let preview = read_animation(&preview_path).unwrap().unwrap();
+        assert_eq!(preview.frame_count, 3);
+        assert_eq!(preview.loop_count, 0);
+        assert_eq!(image::image_dimensions(&preview_path).unwrap(), (256, 128));
+
+        // Still images have no animation
+        assert_eq!(
+            read_animation(Path::new("resources/test_image.png")).unwrap(),
+            None
+        );
+
+        fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_gif_animation() {
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new_with_speed(&mut gif, 30);
+            encoder
+                .encode_frames([
+                    frame([255, 0, 0, 255], 200),
+                    frame([0, 255, 0, 255], 300),
+                    frame([0, 0, 255, 255], 400),
+                ])
+                .unwrap();
+        }
+        assert_eq!(gif.pop(), Some(0x3B));
+
+        // Without its trailer every frame is still complete
+        let animation = gif_animation(&gif).unwrap();
+        assert_eq!(animation.frame_count, 3);
+        assert_eq!(animation.duration, 900);
+
+        // A cut in the last frame keeps the frames before it
+        let animation = gif_animation(&gif[..gif.len() - 4]).unwrap();
+        assert_eq!(animation.frame_count, 2);
+        assert_eq!(animation.duration, 500);
+
+        // A single complete frame is a still
+        let second_frame = gif
+            .windows(2)
+            .rposition(|block| block == [0x21, 0xF9])
+            .and_then(|last| {
+                gif[..last]
+                    .windows(2)
+                    .rposition(|block| block == [0x21, 0xF9])
+            })
+            .unwrap();
+        assert_eq!(gif_animation(&gif[..second_frame + 4]), None);
+
+        let base_path = Path::new("test_truncated_gif_animation");
+        fs::create_dir_all(base_path).unwrap();
+        let gif_path = base_path.join("truncated.gif");
+        fs::write(&gif_path, &gif).unwrap();
+        let preview_path = base_path.join("preview.gif");
+        generate_preview(&gif_path, &preview_path).unwrap();
+        assert_eq!(
+            read_animation(&preview_path).unwrap().unwrap().frame_count,
+            3
+        );
+
+        fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[test]
+    fn test_apng_and_webp_animation() {
+        let chunk = |kind: &[u8; 4], data: &[u8]| -> Vec<u8> {
+            let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+            bytes.extend_from_slice(kind);
+            bytes.extend_from_slice(data);
+            bytes.extend_from_slice(&[0; 4]);
+            bytes
+        };
+        let frame_control = |numerator: u16, denominator: u16| -> Vec<u8> {
+            let mut data = vec![0; 20];
+            data.extend_from_slice(&numerator.to_be_bytes());
+            data.extend_from_slice(&denominator.to_be_bytes());
+            data.extend_from_slice(&[0, 0]);
+            chunk(b"fcTL", &data)
+        };
+
+        let mut apng = b"\x89PNG\r\n\x1a\n".to_vec();
+        apng.extend(chunk(b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]));
+        apng.extend(frame_control(1, 4));
+        apng.extend(frame_control(50, 0));
+        apng.extend(chunk(b"IEND", &[]));
+        assert_eq!(
+            apng_animation(&apng),
+            Some(Animation {
+                frame_count: 2,
+                loop_count: 0,
+                duration: 750,
+            })
+        );
+
+        let webp_chunk = |kind: &[u8; 4], data: &[u8]| -> Vec<u8> {
+            let mut bytes = kind.to_vec();
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(data);
+            bytes
+        };
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend(webp_chunk(b"VP8X", &[0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        webp.extend(webp_chunk(b"ANIM", &[0, 0, 0, 0, 2, 0]));
+        for duration in [40u32, 60] {
+            let mut data = vec![0; 12];
+            data.extend_from_slice(&duration.to_le_bytes()[..3]);
+            data.push(0);
+            webp.extend(webp_chunk(b"ANMF", &data));
+        }
+        assert_eq!(
+            webp_animation(&webp),
+            Some(Animation {
+                frame_count: 2,
+                loop_count: 2,
+                duration: 100,
+            })
+        );
+    }
+}
@@ -0,0 +1,1119 @@
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::thumbnail::{self, Thumbnail, ThumbnailFormat};
+use crate::{bmff, video};
+
+/// Largest tag or header read into memory, cover art included
+const MAX_TAG_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Bytes searched after the ID3 tag for the first MPEG frame
+const MAX_SYNC_SEARCH: usize = 64 * 1024;
+
+/// Bytes read at the end of an Ogg file to find its last page
+const OGG_TAIL_SIZE: u64 = 64 * 1024;
+
+/// Picture type of front covers in ID3 and FLAC pictures
+const FRONT_COVER: u8 = 3;
+
+/// ID3v1 genres, also used by numeric ID3v2 and MP4 genres
+const ID3_GENRES: [&str; 80] = [
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "Alternative Rock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychedelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+];
+
+/// Tags and stream details read from an audio file
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct AudioInfo {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    pub bpm: Option<f64>,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+}
+
+/// Everything read from an audio file
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct AudioTags {
+    pub info: AudioInfo,
+    /// Embedded cover art, as stored in the file
+    pub cover: Option<Vec<u8>>,
+    /// Picture type of the cover, front covers replace any other picture
+    cover_type: Option<u8>,
+}
+
+/// Read the tags of an MP3, FLAC, Ogg, Opus, WAV, AAC or M4A file
+pub fn read_audio(file_path: &Path) -> io::Result<AudioTags> {
+    let mut file = File::open(file_path)?;
+    let file_size = file.metadata()?.len();
+
+    let mut head = [0u8; 12];
+    let read = file.read(&mut head)?;
+    let head = &head[..read];
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut tags = AudioTags::default();
+
+    if head.starts_with(b"ID3") || is_mpeg_frame(head) {
+        read_mpeg(&mut file, file_size, &mut tags)?;
+    } else if head.starts_with(b"fLaC") {
+        read_flac(&mut file, &mut tags)?;
+    } else if head.starts_with(b"OggS") {
+        read_ogg(&mut file, file_size, &mut tags)?;
+    } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WAVE") {
+        read_wav(&mut file, file_size, &mut tags)?;
+    } else if head.get(4..8) == Some(b"ftyp") {
+        read_m4a(&mut file, file_size, &mut tags)?;
+    } else if is_adts_frame(head) {
+        read_adts(file_path, &mut tags)?;
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Only MP3, FLAC, Ogg, Opus, WAV, AAC and M4A files are supported",
+        ));
+    }
+
+    Ok(tags)
+}
+
+/// Generate the thumbnails of an audio file from its cover art into `<ref_dir>/thumbnails`.
+///
+/// Files without cover art get no thumbnail.
+pub fn generate_cover(
+    media_path: &Path,
+    ref_dir: &Path,
+    format: ThumbnailFormat,
+) -> io::Result<Vec<Thumbnail>> {
+    let Some(cover) = read_audio(media_path)?.cover else {
+        return Ok(Vec::new());
+    };
+
+    let extension = image::guess_format(&cover)
+        .map_err(io::Error::other)?
+        .extensions_str()
+        .first()
+        .copied()
+        .unwrap_or("img");
+
+    // The cover is kept out of the ref folder, where it would be taken for the media file
+    let cover_path = std::env::temp_dir().join(format!(
+        "cover-{}.{}",
+        ref_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("audio"),
+        extension
+    ));
+
+    fs::write(&cover_path, cover)?;
+    let thumbnails = thumbnail::generate_thumbnails(&cover_path, ref_dir, format);
+    let _ = fs::remove_file(&cover_path);
+
+    thumbnails
+}
+
+impl AudioTags {
+    fn set_text(field: &mut Option<String>, value: &str) {
+        let value = value.trim();
+        if field.is_none() && !value.is_empty() {
+            *field = Some(value.to_string());
+        }
+    }
+
+    fn add_genre(&mut self, value: &str) {
+        for genre in parse_genre(value) {
+            if !self.info.genres.contains(&genre) {
+                self.info.genres.push(genre);
+            }
+        }
+    }
+
+    fn set_bpm(&mut self, value: &str) {
+        if self.info.bpm.is_none() {
+            self.info.bpm = value.trim().parse::<f64>().ok().filter(|bpm| *bpm > 0.0);
+        }
+    }
+
+    /// Keep a picture, a front cover replaces any other kind
+    fn set_cover(&mut self, picture_type: u8, data: &[u8]) {
+        if data.is_empty() || self.cover_type == Some(FRONT_COVER) {
+            return;
+        }
+        if self.cover.is_none() || picture_type == FRONT_COVER {
+            self.cover = Some(data.to_vec());
+            self.cover_type = Some(picture_type);
+        }
+    }
+
+    /// Apply a `KEY=value` Vorbis comment
+    fn add_comment(&mut self, comment: &str) {
+        let Some((key, value)) = comment.split_once('=') else {
+            return;
+        };
+
+        match key.to_ascii_uppercase().as_str() {
+            "TITLE" => Self::set_text(&mut self.info.title, value),
+            "ARTIST" => Self::set_text(&mut self.info.artist, value),
+            "ALBUM" => Self::set_text(&mut self.info.album, value),
+            "GENRE" => self.add_genre(value),
+            "BPM" | "TEMPO" => self.set_bpm(value),
+            "METADATA_BLOCK_PICTURE" => {
+                let picture = base64::engine::general_purpose::STANDARD
+                    .decode(value.trim())
+                    .ok();
+                if let Some((picture_type, data)) = picture.as_deref().and_then(flac_picture) {
+                    self.set_cover(picture_type, data);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Genres of an ID3 or Vorbis genre field, numeric ID3 genres get their name
+fn parse_genre(value: &str) -> Vec<String> {
+    let mut genres = Vec::new();
+    let mut rest = value.trim();
+
+    // ID3v2.3 references: "(17)", "(17)Rock", "(RX)"
+    while let Some(reference) = rest.strip_prefix('(').and_then(|r| r.split_once(')')) {
+        let (code, after) = reference;
+        match code {
+            "RX" => genres.push("Remix".to_string()),
+            "CR" => genres.push("Cover".to_string()),
+            _ => genres.extend(id3_genre(code)),
+        }
+        rest = after.trim_start();
+    }
+
+    if !rest.is_empty() {
+        genres.push(id3_genre(rest).unwrap_or_else(|| rest.to_string()));
+    }
+
+    genres.dedup();
+    genres
+}
+
+fn id3_genre(code: &str) -> Option<String> {
+    let index: usize = code.trim().parse().ok()?;
+    ID3_GENRES.get(index).map(|genre| genre.to_string())
+}
+
+fn read_mpeg(file: &mut File, file_size: u64, tags: &mut AudioTags) -> io::Result<()> {
+    let mut header = [0u8; 10];
+    file.read_exact(&mut header)?;
+
+    let mut audio_start = 0;
+    if header.starts_with(b"ID3") {
+        let size =
+            syncsafe(&header[6..10]) as u64 + 10 + if header[5] & 0x10 != 0 { 10 } else { 0 };
+        let mut tag = vec![0; size.min(MAX_TAG_SIZE) as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut tag)?;
+        parse_id3(&tag, tags);
+        audio_start = size;
+    }
+
+    // A FLAC file behind an ID3 tag keeps its own stream details
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(audio_start))?;
+    file.take(MAX_SYNC_SEARCH as u64).read_to_end(&mut data)?;
+    if data.starts_with(b"fLaC") {
+        file.seek(SeekFrom::Start(audio_start))?;
+        return read_flac(file, tags);
+    }
+
+    if let Some(offset) = (0..data.len()).find(|offset| is_mpeg_frame(&data[*offset..])) {
+        let frame = &data[offset..];
+        let audio_size = file_size.saturating_sub(audio_start + offset as u64);
+        mpeg_stream(frame, audio_size, &mut tags.info);
+    }
+
+    // ID3v1 only fills what ID3v2 did not
+    if file_size >= 128 {
+        let mut v1 = [0u8; 128];
+        file.seek(SeekFrom::Start(file_size - 128))?;
+        file.read_exact(&mut v1)?;
+        if v1.starts_with(b"TAG") {
+            let text = |range: std::ops::Range<usize>| latin1(&v1[range]);
+            AudioTags::set_text(&mut tags.info.title, &text(3..33));
+            AudioTags::set_text(&mut tags.info.artist, &text(33..63));
+            AudioTags::set_text(&mut tags.info.album, &text(63..93));
+            if tags.info.genres.is_empty() {
+                tags.info.genres.extend(id3_genre(&v1[127].to_string()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn is_mpeg_frame(data: &[u8]) -> bool {
+    let [sync, b1, b2, _, ..] = *data else {
+        return false;
+    };
+    sync == 0xFF
+        && b1 & 0xE0 == 0xE0
+        // Version 1 is reserved, layer 0 is ADTS
+        && (b1 >> 3) & 3 != 1
+        && (b1 >> 1) & 3 != 0
+        && !matches!(b2 >> 4, 0 | 15)
+        && (b2 >> 2) & 3 != 3
+}
+
+/// Sample rate, channels and duration from the first MPEG frame
+fn mpeg_stream(frame: &[u8], audio_size: u64, info: &mut AudioInfo) {
+    const BITRATES: [[u32; 15]; 5] = [
+        [
+            0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+        ],
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+        ],
+        [
+            0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+        ],
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+        ],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ];
+
+    let version = (frame[1] >> 3) & 3;
+    let layer = 4 - ((frame[1] >> 1) & 3);
+    let mpeg1 = version == 3;
+    let mono = frame[3] >> 6 == 3;
+
+    let sample_rate = [44100, 48000, 32000][((frame[2] >> 2) & 3) as usize]
+        >> match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        };
+    let table = match (mpeg1, layer) {
+        (true, layer) => layer as usize - 1,
+        (false, 1) => 3,
+        (false, _) => 4,
+    };
+    let bitrate = BITRATES[table][(frame[2] >> 4) as usize] * 1000;
+    let samples_per_frame = match (layer, mpeg1) {
+        (1, _) => 384,
+        (3, false) => 576,
+        _ => 1152,
+    };
+
+    info.sample_rate = Some(sample_rate);
+    info.channels = Some(if mono { 1 } else { 2 });
+
+    // VBR files count their frames in a Xing, Info or VBRI header
+    let xing_offset = 4 + match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let frames = match frame.get(xing_offset..xing_offset + 4) {
+        Some(b"Xing") | Some(b"Info")
+            if read_be(frame, xing_offset + 4, 4).is_some_and(|flags| flags & 1 != 0) =>
+        {
+            read_be(frame, xing_offset + 8, 4)
+        }
+        _ if frame.get(36..40) == Some(b"VBRI") => read_be(frame, 50, 4),
+        _ => None,
+    };
+
+    // Without a frame count the length in the tag beats an estimate from the bitrate
+    match frames {
+        Some(frames) => {
+            info.duration = Some(frames as f64 * samples_per_frame as f64 / sample_rate as f64)
+        }
+        None if bitrate > 0 && info.duration.is_none() => {
+            info.duration = Some(audio_size as f64 * 8.0 / bitrate as f64)
+        }
+        None => {}
+    }
+}
+
+/// Parse an ID3v2 tag, header included
+fn parse_id3(tag: &[u8], tags: &mut AudioTags) {
+    let Some(&[version, _, flags]) = tag.get(3..6) else {
+        return;
+    };
+    let mut body = tag.get(10..).unwrap_or_default().to_vec();
+    if version < 4 && flags & 0x80 != 0 {
+        body = resynchronise(&body);
+    }
+
+    let mut pos = 0;
+    if flags & 0x40 != 0 {
+        pos = match version {
+            3 => read_be(&body, 0, 4).map_or(body.len(), |size| size as usize + 4),
+            _ => body
+                .get(0..4)
+                .map_or(body.len(), |size| syncsafe(size) as usize),
+        };
+    }
+
+    let header_size = if version == 2 { 6 } else { 10 };
+    while let Some(header) = body.get(pos..pos + header_size) {
+        if header[0] == 0 {
+            break;
+        }
+
+        let (id, size, frame_flags) = match version {
+            2 => (&header[..3], read_be(header, 3, 3).unwrap_or(0), 0),
+            3 => (&header[..4], read_be(header, 4, 4).unwrap_or(0), header[9]),
+            _ => (&header[..4], syncsafe(&header[4..8]), header[9]),
+        };
+        let Some(frame) = body.get(pos + header_size..pos + header_size + size as usize) else {
+            break;
+        };
+        pos += header_size + size as usize;
+
+        // Compressed and encrypted frames are skipped
+        let (compressed, encrypted) = match version {
+            3 => (0x80, 0x40),
+            _ => (0x08, 0x04),
+        };
+        if version > 2 && frame_flags & (compressed | encrypted) != 0 {
+            continue;
+        }
+        let frame = match version {
+            4 if frame_flags & 0x02 != 0 => resynchronise(frame),
+            _ => frame.to_vec(),
+        };
+        let frame = match version {
+            4 if frame_flags & 0x01 != 0 => frame.get(4..).unwrap_or_default(),
+            _ => &frame[..],
+        };
+
+        match id {
+            b"TIT2" | b"TT2" => {
+                AudioTags::set_text(&mut tags.info.title, &id3_text(frame).join("/"))
+            }
+            b"TPE1" | b"TP1" => {
+                AudioTags::set_text(&mut tags.info.artist, &id3_text(frame).join("/"))
+            }
+            b"TALB" | b"TAL" => {
+                AudioTags::set_text(&mut tags.info.album, &id3_text(frame).join("/"))
+            }
+            b"TCON" | b"TCO" => {
+                for genre in id3_text(frame) {
+                    tags.add_genre(&genre);
+                }
+            }
+            b"TBPM" | b"TBP" => tags.set_bpm(&id3_text(frame).concat()),
+            b"TLEN" | b"TLE" if tags.info.duration.is_none() => {
+                tags.info.duration = id3_text(frame)
+                    .concat()
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|milliseconds| *milliseconds > 0.0)
+                    .map(|milliseconds| milliseconds / 1000.0);
+            }
+            b"APIC" | b"PIC" => {
+                if let Some((picture_type, data)) = id3_picture(frame, version == 2) {
+                    tags.set_cover(picture_type, data);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Values of an ID3 text frame, v2.4 separates them with a terminator
+fn id3_text(frame: &[u8]) -> Vec<String> {
+    let Some((&encoding, text)) = frame.split_first() else {
+        return Vec::new();
+    };
+
+    let mut values = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let (value, after) = split_terminated(rest, encoding);
+        let value = decode_text(value, encoding);
+        if !value.trim().is_empty() {
+            values.push(value.trim().to_string());
+        }
+        rest = after;
+    }
+    values
+}
+
+/// Picture type and data of an APIC frame, or a PIC frame for ID3v2.2
+fn id3_picture(frame: &[u8], v2: bool) -> Option<(u8, &[u8])> {
+    let (&encoding, rest) = frame.split_first()?;
+    let rest = if v2 {
+        rest.get(3..)?
+    } else {
+        let mime_end = rest.iter().position(|byte| *byte == 0)?;
+        rest.get(mime_end + 1..)?
+    };
+    let (&picture_type, rest) = rest.split_first()?;
+    let (_, data) = split_terminated(rest, encoding);
+    Some((picture_type, data))
+}
+
+/// Split a string in an ID3 encoding at its terminator
+fn split_terminated(data: &[u8], encoding: u8) -> (&[u8], &[u8]) {
+    if matches!(encoding, 1 | 2) {
+        let end = data
+            .chunks_exact(2)
+            .position(|pair| pair == [0, 0])
+            .map(|pair| pair * 2);
+        match end {
+            Some(end) => (&data[..end], &data[end + 2..]),
+            None => (data, &[]),
+        }
+    } else {
+        match data.iter().position(|byte| *byte == 0) {
+            Some(end) => (&data[..end], &data[end + 1..]),
+            None => (data, &[]),
+        }
+    }
+}
+
+fn decode_text(data: &[u8], encoding: u8) -> String {
+    match encoding {
+        0 => latin1(data),
+        1 | 2 => {
+            let (little_endian, data) = match data {
+                [0xFF, 0xFE, rest @ ..] => (true, rest),
+                [0xFE, 0xFF, rest @ ..] => (false, rest),
+                _ => (false, data),
+            };
+            let units: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|pair| match little_endian {
+                    true => u16::from_le_bytes([pair[0], pair[1]]),
+                    false => u16::from_be_bytes([pair[0], pair[1]]),
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(data).to_string(),
+    }
+}
+
+fn latin1(data: &[u8]) -> String {
+    data.iter()
+        .take_while(|byte| **byte != 0)
+        .map(|byte| *byte as char)
+        .collect()
+}
+
+/// Undo the unsynchronisation scheme, which inserts a zero after every 0xFF
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut previous = 0;
+    for &byte in data {
+        if !(previous == 0xFF && byte == 0) {
+            output.push(byte);
+        }
+        previous = byte;
+    }
+    output
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 7) | (*byte & 0x7F) as u32)
+}
+
+fn read_flac(file: &mut File, tags: &mut AudioTags) -> io::Result<()> {
+    let mut reader = BufReader::new(file);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    loop {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header)?;
+        let length = read_be(&header, 1, 3).unwrap_or(0) as u64;
+
+        match header[0] & 0x7F {
+            // STREAMINFO, VORBIS_COMMENT and PICTURE
+            kind @ (0 | 4 | 6) if length <= MAX_TAG_SIZE => {
+                let mut block = vec![0; length as usize];
+                reader.read_exact(&mut block)?;
+                match kind {
+                    0 => flac_stream_info(&block, &mut tags.info),
+                    4 => vorbis_comments(&block, tags),
+                    _ => {
+                        if let Some((picture_type, data)) = flac_picture(&block) {
+                            tags.set_cover(picture_type, data);
+                        }
+                    }
+                }
+            }
+            _ => reader.seek_relative(length as i64)?,
+        }
+
+        if header[0] & 0x80 != 0 {
+            return Ok(());
+        }
+    }
+}
+
+fn flac_stream_info(block: &[u8], info: &mut AudioInfo) {
+    let Some(fields) = block.get(10..18) else {
+        return;
+    };
+    let sample_rate = read_be(fields, 0, 3).unwrap_or(0) >> 4;
+    let channels = ((fields[2] >> 1) & 0x07) as u16 + 1;
+    let samples = ((fields[3] & 0x0F) as u64) << 32 | read_be(fields, 4, 4).unwrap_or(0) as u64;
+
+    if sample_rate > 0 {
+        info.sample_rate = Some(sample_rate);
+        info.channels = Some(channels);
+        if samples > 0 {
+            info.duration = Some(samples as f64 / sample_rate as f64);
+        }
+    }
+}
+
+/// Picture type and data of a FLAC picture block
+fn flac_picture(block: &[u8]) -> Option<(u8, &[u8])> {
+    let picture_type = read_be(block, 0, 4)?;
+    let mime_length = read_be(block, 4, 4)? as usize;
+    let description = 8 + mime_length;
+    let description_length = read_be(block, description, 4)? as usize;
+    // Width, height, depth and color count come before the data
+    let data_length = description + 4 + description_length + 16;
+    let length = read_be(block, data_length, 4)? as usize;
+    let data = block.get(data_length + 4..data_length + 4 + length)?;
+    Some((u8::try_from(picture_type).ok()?, data))
+}
+
+/// Parse a Vorbis comment block, used by FLAC, Ogg Vorbis and Opus
+fn vorbis_comments(block: &[u8], tags: &mut AudioTags) {
+    let Some(vendor_length) = read_le(block, 0) else {
+        return;
+    };
+    let mut pos = 4 + vendor_length as usize;
+    let Some(count) = read_le(block, pos) else {
+        return;
+    };
+    pos += 4;
+
+    for _ in 0..count {
+        let Some(length) = read_le(block, pos) else {
+            return;
+        };
+        let Some(comment) = block.get(pos + 4..pos + 4 + length as usize) else {
+            return;
+        };
+        tags.add_comment(&String::from_utf8_lossy(comment));
+        pos += 4 + length as usize;
+    }
+}
+
+fn read_ogg(file: &mut File, file_size: u64, tags: &mut AudioTags) -> io::Result<()> {
+    let mut reader = BufReader::new(&mut *file);
+    let mut packets: Vec<Vec<u8>> = vec![Vec::new()];
+    let mut read = 0;
+
+    // The identification and comment headers are the first two packets
+    while packets.len() < 3 && read < MAX_TAG_SIZE {
+        let mut header = [0u8; 27];
+        if reader.read_exact(&mut header).is_err() || !header.starts_with(b"OggS") {
+            break;
+        }
+        let mut segments = vec![0u8; header[26] as usize];
+        reader.read_exact(&mut segments)?;
+
+        for length in segments {
+            let mut segment = vec![0; length as usize];
+            reader.read_exact(&mut segment)?;
+            packets.last_mut().unwrap().extend_from_slice(&segment);
+            if length < 255 {
+                packets.push(Vec::new());
+            }
+            read += length as u64;
+        }
+    }
+
+    let identification = packets.first().map(Vec::as_slice).unwrap_or_default();
+    let comments = packets.get(1).map(Vec::as_slice).unwrap_or_default();
+
+    // Opus always plays at 48kHz, granule positions count 48kHz samples
+    let (granule_rate, pre_skip) = if identification.starts_with(b"OpusHead") {
+        tags.info.channels = identification.get(9).map(|channels| *channels as u16);
+        tags.info.sample_rate = read_le(identification, 12).filter(|rate| *rate > 0);
+        if let Some(comments) = comments.strip_prefix(b"OpusTags") {
+            vorbis_comments(comments, tags);
+        }
+        let pre_skip = identification
+            .get(10..12)
+            .map_or(0, |bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as u64);
+        (48000, pre_skip)
+    } else if identification.starts_with(b"\x01vorbis") {
+        tags.info.channels = identification.get(11).map(|channels| *channels as u16);
+        tags.info.sample_rate = read_le(identification, 12).filter(|rate| *rate > 0);
+        if let Some(comments) = comments.strip_prefix(b"\x03vorbis") {
+            vorbis_comments(comments, tags);
+        }
+        (tags.info.sample_rate.unwrap_or(0), 0)
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Only Vorbis and Opus streams are supported",
+        ));
+    };
+
+    // The granule position of the last page is the sample count
+    let tail_start = file_size.saturating_sub(OGG_TAIL_SIZE);
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(tail_start))?;
+    file.read_to_end(&mut tail)?;
+    let last_page = tail.windows(4).rposition(|window| window == b"OggS");
+    let granule = last_page
+        .and_then(|page| tail.get(page + 6..page + 14))
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+
+    if let Some(granule) = granule.filter(|_| granule_rate > 0) {
+        tags.info.duration = Some(granule.saturating_sub(pre_skip) as f64 / granule_rate as f64);
+    }
+
+    Ok(())
+}
+
+fn read_wav(file: &mut File, file_size: u64, tags: &mut AudioTags) -> io::Result<()> {
+    let mut reader = BufReader::new(file);
+    let mut position = 12;
+    let mut byte_rate = 0;
+    let mut data_size = None;
+    reader.seek(SeekFrom::Start(position))?;
+
+    while position + 8 <= file_size {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let size = read_le(&header, 4).unwrap_or(0) as u64;
+        let body_start = position + 8;
+
+        match &header[..4] {
+            b"fmt " | b"LIST" | b"id3 " | b"ID3 " if size <= MAX_TAG_SIZE => {
+                let mut body = vec![0; size as usize];
+                reader.read_exact(&mut body)?;
+                match &header[..4] {
+                    b"fmt " => {
+                        tags.info.channels = body
+                            .get(2..4)
+                            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+                        tags.info.sample_rate = read_le(&body, 4);
+                        byte_rate = read_le(&body, 8).unwrap_or(0);
+                    }
+                    b"LIST" if body.starts_with(b"INFO") => riff_info(&body[4..], tags),
+                    b"LIST" => {}
+                    _ => parse_id3(&body, tags),
+                }
+            }
+            b"data" => data_size = Some(size.min(file_size - body_start)),
+            _ => {}
+        }
+
+        // Chunks are padded to an even size
+        position = body_start + size + (size & 1);
+        reader.seek(SeekFrom::Start(position))?;
+    }
+
+    if let Some(data_size) = data_size.filter(|_| byte_rate > 0) {
+        tags.info.duration = Some(data_size as f64 / byte_rate as f64);
+    }
+
+    Ok(())
+}
+
+/// Parse the subchunks of a RIFF INFO list
+fn riff_info(mut data: &[u8], tags: &mut AudioTags) {
+    while let Some(header) = data.get(..8) {
+        let size = read_le(header, 4).unwrap_or(0) as usize;
+        let Some(value) = data.get(8..8 + size) else {
+            return;
+        };
+        let value = String::from_utf8_lossy(value);
+        let value = value.trim_end_matches('\0');
+
+        match &header[..4] {
+            b"INAM" => AudioTags::set_text(&mut tags.info.title, value),
+            b"IART" => AudioTags::set_text(&mut tags.info.artist, value),
+            b"IPRD" => AudioTags::set_text(&mut tags.info.album, value),
+            b"IGNR" => tags.add_genre(value),
+            _ => {}
+        }
+
+        data = data.get(8 + size + (size & 1)..).unwrap_or_default();
+    }
+}
+
+fn read_m4a(file: &mut File, file_size: u64, tags: &mut AudioTags) -> io::Result<()> {
+    let moov = video::read_moov(file, file_size)?;
+    tags.info.duration = video::parse_moov(&moov).duration;
+
+    for (kind, trak) in bmff::boxes(&moov) {
+        if kind != b"trak" {
+            continue;
+        }
+        let stsd = bmff::find_box(trak, b"mdia")
+            .filter(|mdia| {
+                bmff::find_box(mdia, b"hdlr").and_then(|hdlr| hdlr.get(8..12)) == Some(b"soun")
+            })
+            .and_then(|mdia| bmff::find_box(mdia, b"minf"))
+            .and_then(|minf| bmff::find_box(minf, b"stbl"))
+            .and_then(|stbl| bmff::find_box(stbl, b"stsd"));
+
+        // Audio sample entry: counts, entry header, reserved fields then channels and rate
+        if let Some(stsd) = stsd {
+            tags.info.channels = read_be(stsd, 32, 2).map(|channels| channels as u16);
+            tags.info.sample_rate = read_be(stsd, 40, 4).map(|rate| rate >> 16);
+            break;
+        }
+    }
+
+    let Some(meta) = bmff::find_box(&moov, b"udta").and_then(|udta| bmff::find_box(udta, b"meta"))
+    else {
+        return Ok(());
+    };
+    // MP4 meta boxes carry a version, QuickTime ones start with their children
+    let meta = if meta.get(4..8) == Some(b"hdlr") {
+        meta
+    } else {
+        meta.get(4..).unwrap_or_default()
+    };
+    let Some(items) = bmff::find_box(meta, b"ilst") else {
+        return Ok(());
+    };
+
+    for (kind, item) in bmff::boxes(items) {
+        // Type indicator and locale come before the value
+        let Some(value) = bmff::find_box(item, b"data").and_then(|data| data.get(8..)) else {
+            continue;
+        };
+        let text = || String::from_utf8_lossy(value).to_string();
+
+        match kind {
+            b"\xA9nam" => AudioTags::set_text(&mut tags.info.title, &text()),
+            b"\xA9ART" => AudioTags::set_text(&mut tags.info.artist, &text()),
+            b"\xA9alb" => AudioTags::set_text(&mut tags.info.album, &text()),
+            b"\xA9gen" => tags.add_genre(&text()),
+            // ID3v1 genre index plus one
+            b"gnre" => {
+                if let Some(genre) = read_be(value, 0, 2).filter(|index| *index > 0) {
+                    tags.add_genre(&format!("({})", genre - 1));
+                }
+            }
+            b"tmpo" => tags.set_bpm(&read_be(value, 0, 2).unwrap_or(0).to_string()),
+            b"covr" => tags.set_cover(FRONT_COVER, value),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn is_adts_frame(data: &[u8]) -> bool {
+    matches!(data, [0xFF, b1, ..] if b1 & 0xF6 == 0xF0)
+}
+
+/// Stream details of a raw AAC file, the duration comes from its frame count
+fn read_adts(file_path: &Path, tags: &mut AudioTags) -> io::Result<()> {
+    const SAMPLE_RATES: [u32; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+
+    let data = fs::read(file_path)?;
+    if data.len() < 7 {
+        return Ok(());
+    }
+    let Some(sample_rate) = SAMPLE_RATES.get(((data[2] >> 2) & 0x0F) as usize) else {
+        return Ok(());
+    };
+    tags.info.sample_rate = Some(*sample_rate);
+    tags.info.channels = Some((((data[2] & 1) << 2) | (data[3] >> 6)) as u16);
+
+    let mut frames = 0u64;
+    let mut pos = 0;
+    while let Some(header) = data
+        .get(pos..pos + 7)
+        .filter(|header| is_adts_frame(header))
+    {
+        let length = ((header[3] as usize & 0x03) << 11)
+            | ((header[4] as usize) << 3)
+            | (header[5] as usize >> 5);
+        if length < 7 {
+            break;
+        }
+        // Every raw data block holds 1024 samples
+        frames += (header[6] & 0x03) as u64 + 1;
+        pos += length;
+    }
+    tags.info.duration = Some(frames as f64 * 1024.0 / *sample_rate as f64);
+
+    Ok(())
+}
+
+/// Big endian integer of up to 4 bytes
+fn read_be(data: &[u8], offset: usize, length: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + length)?;
+    Some(
+        bytes
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as u32),
+    )
+}
+
+fn read_le(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ID3v2.3 frame with a Latin-1 text value
+    fn text_frame(id: &[u8; 4], text: &str) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0]);
+        frame.extend_from_slice(text.as_bytes());
+        frame
+    }
+
+    fn png() -> Vec<u8> {
+        fs::read("resources/test_image.png").unwrap()
+    }
+
+    fn flac_picture_block(picture_type: u32, data: &[u8]) -> Vec<u8> {
+        let mut block = picture_type.to_be_bytes().to_vec();
+        block.extend_from_slice(&9u32.to_be_bytes());
+        block.extend_from_slice(b"image/png");
+        block.extend_from_slice(&0u32.to_be_bytes());
+        block.extend_from_slice(&[0; 16]);
+        block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        block.extend_from_slice(data);
+        block
+    }
+
+    #[test]
+    fn test_read_mp3() {
+        let cover = png();
+        let mut picture = vec![0u8];
+        picture.extend_from_slice(b"image/png\0");
+        picture.push(FRONT_COVER);
+        picture.extend_from_slice(b"Cover\0");
+        picture.extend_from_slice(&cover);
+
+        let mut frames = [
+            text_frame(b"TIT2", "Night Drive"),
+            text_frame(b"TPE1", "Synth Band"),
+            text_frame(b"TALB", "Highways"),
+            text_frame(b"TCON", "(52)Synthwave"),
+            text_frame(b"TBPM", "118"),
+        ]
+        .concat();
+        frames.extend_from_slice(b"APIC");
+        frames.extend_from_slice(&(picture.len() as u32).to_be_bytes());
+        frames.extend_from_slice(&[0, 0]);
+        frames.extend_from_slice(&picture);
+
+        let size = frames.len() as u32;
+        let mut mp3 = b"ID3\x03\x00\x00".to_vec();
+        mp3.extend([21, 14, 7, 0].map(|shift| ((size >> shift) & 0x7F) as u8));
+        mp3.extend(frames);
+
+        // 100 frames of MPEG-1 layer III, 128kbps, 44.1kHz, joint stereo
+        for _ in 0..100 {
+            mp3.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x40]);
+            mp3.extend(std::iter::repeat_n(0, 413));
+        }
+
+        let path = Path::new("test_read_mp3.mp3");
+        fs::write(path, &mp3).unwrap();
+        let tags = read_audio(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(tags.info.title.as_deref(), Some("Night Drive"));
+        assert_eq!(tags.info.artist.as_deref(), Some("Synth Band"));
+        assert_eq!(tags.info.album.as_deref(), Some("Highways"));
+        assert_eq!(tags.info.genres, vec!["Electronic", "Synthwave"]);
+        assert_eq!(tags.info.bpm, Some(118.0));
+        assert_eq!(tags.info.sample_rate, Some(44100));
+        assert_eq!(tags.info.channels, Some(2));
+        // 41700 bytes at 128kbps
+        assert!((tags.info.duration.unwrap() - 2.606).abs() < 0.01);
+        assert_eq!(tags.cover, Some(cover));
+    }
+
+    #[test]
+    fn test_read_truncated() {
+        let path = Path::new("test_read_truncated.mp3");
+
+        // An ID3 header directly followed by 3 bytes of a frame header
+        fs::write(path, b"ID3\x04\0\0\0\0\0\0\xFF\xFB\x90").unwrap();
+        let tags = read_audio(path).unwrap();
+        assert_eq!(tags.info.sample_rate, None);
+
+        // Every cut of a tagged file reads without panicking
+        let mut mp3 = b"ID3\x03\0\0\0\0\0\x0e".to_vec();
+        mp3.extend(text_frame(b"TIT2", "Cut"));
+        mp3.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x40]);
+        mp3.extend_from_slice(&[0; 36]);
+        mp3.extend_from_slice(b"Xing\0\0\0\x01\0\0\0\x64");
+        for length in 0..mp3.len() {
+            fs::write(path, &mp3[..length]).unwrap();
+            let _ = read_audio(path);
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_flac() {
+        let comment = |text: &str| -> Vec<u8> {
+            let mut bytes = (text.len() as u32).to_le_bytes().to_vec();
+            bytes.extend_from_slice(text.as_bytes());
+            bytes
+        };
+        let block = |kind: u8, body: &[u8]| -> Vec<u8> {
+            let mut bytes = vec![kind];
+            bytes.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            bytes.extend_from_slice(body);
+            bytes
+        };
+
+        // 48kHz, stereo, 16 bits, 480000 samples
+        let mut stream_info = vec![0u8; 10];
+        stream_info.extend_from_slice(&[0x0B, 0xB8, 0x02, 0xF0, 0x00, 0x07, 0x53, 0x00]);
+        stream_info.extend_from_slice(&[0; 16]);
+
+        let mut comments = comment("reference");
+        comments.extend_from_slice(&3u32.to_le_bytes());
+        comments.extend(comment("title=Field Recording"));
+        comments.extend(comment("GENRE=Ambient"));
+        comments.extend(comment("BPM=92.5"));
+
+        let cover = png();
+        let mut flac = b"fLaC".to_vec();
+        flac.extend(block(0, &stream_info));
+        flac.extend(block(4, &comments));
+        flac.extend(block(6, &flac_picture_block(4, b"back")));
+        flac.extend(block(0x80 | 6, &flac_picture_block(3, &cover)));
+
+        let path = Path::new("test_read_flac.flac");
+        fs::write(path, &flac).unwrap();
+        let tags = read_audio(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            tags.info,
+            AudioInfo {
+                title: Some("Field Recording".to_string()),
+                genres: vec!["Ambient".to_string()],
+                bpm: Some(92.5),
+                duration: Some(10.0),
+                sample_rate: Some(48000),
+                channels: Some(2),
+                ..Default::default()
+            }
+        );
+        // The front cover wins over the back cover stored before it
+        assert_eq!(tags.cover, Some(cover));
+    }
+
+    #[test]
+    fn test_parse_genre() {
+        assert_eq!(parse_genre("17"), vec!["Rock"]);
+        assert_eq!(parse_genre("(17)(RX)Indie"), vec!["Rock", "Remix", "Indie"]);
+        assert_eq!(parse_genre("Lo-Fi"), vec!["Lo-Fi"]);
+        assert!(parse_genre("  ").is_empty());
+
+        assert!(read_audio(Path::new("resources/test_image.png")).is_err());
+    }
+}
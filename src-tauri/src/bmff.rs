@@ -0,0 +1,74 @@
+/// ISO base media boxes of a buffer as (type, body)
+pub fn boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = read_u32(data, 0)? as u64;
+        let kind: &[u8; 4] = data.get(4..8)?.try_into().ok()?;
+        let (header, size) = match size {
+            0 => (8, data.len() as u64),
+            1 => (16, read_u64(data, 8)?),
+            _ => (8, size),
+        };
+        let size = usize::try_from(size).ok()?;
+        let body = data.get(header..size)?;
+        data = &data[size..];
+        Some((kind, body))
+    })
+}
+
+/// Body of the first box of a kind
+pub fn find_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|(found, _)| *found == kind)
+        .map(|(_, body)| body)
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boxes() {
+        let mut data = b"\0\0\0\x0cfree\x01\x02\x03\x04".to_vec();
+        // 64 bit size, then a last box running to the end of the buffer
+        data.extend_from_slice(b"\0\0\0\x01mdat\0\0\0\0\0\0\0\x12\xaa\xbb");
+        data.extend_from_slice(b"\0\0\0\0moov\x05\x06");
+
+        let found: Vec<(&[u8; 4], &[u8])> = boxes(&data).collect();
+        assert_eq!(
+            found,
+            vec![
+                (b"free", &[1, 2, 3, 4][..]),
+                (b"mdat", &[0xaa, 0xbb][..]),
+                (b"moov", &[5, 6][..]),
+            ]
+        );
+        assert_eq!(find_box(&data, b"moov"), Some(&[5, 6][..]));
+        assert_eq!(find_box(&data, b"trak"), None);
+
+        // Truncated boxes end the iteration
+        assert_eq!(boxes(&data[..10]).count(), 0);
+
+        // So do sizes past the end of the buffer
+        let mut data = b"\0\0\0\x01mdat".to_vec();
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(boxes(&data).count(), 0);
+    }
+}
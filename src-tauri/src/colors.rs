@@ -0,0 +1,613 @@
+use fxhash::FxHashMap;
+use image::RgbaImage;
+use kmeans_colors::{get_kmeans_hamerly, Kmeans, Sort};
+use palette::color_difference::Ciede2000;
+use palette::{white_point::D65, FromColor, GetHue, Lab, Srgb, Srgba};
+use serde::{Deserialize, Serialize};
+
+use crate::state::Ref;
+use crate::utils::cached_srgba_to_lab;
+
+/// Channels at or above this value count as near-white
+const NEAR_WHITE: u8 = 235;
+/// Channels at or below this value count as near-black
+const NEAR_BLACK: u8 = 20;
+/// Pixels with a lower alpha count as transparent
+const MIN_ALPHA: u8 = 128;
+
+/// Deepest octree level, 6 bits per channel is plenty for a palette
+const OCTREE_DEPTH: u32 = 6;
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaletteAlgorithm {
+    /// Hamerly k-means on Lab, the slowest but closest to what the eye sees
+    #[default]
+    Kmeans,
+    MedianCut,
+    Octree,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PaletteOptions {
+    /// Maximum amount of colors in a palette
+    pub color_count: usize,
+    pub algorithm: PaletteAlgorithm,
+    pub ignore_white: bool,
+    pub ignore_black: bool,
+    pub ignore_transparent: bool,
+}
+
+impl Default for PaletteOptions {
+    fn default() -> Self {
+        Self {
+            color_count: 8,
+            algorithm: PaletteAlgorithm::default(),
+            ignore_white: false,
+            ignore_black: false,
+            ignore_transparent: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct PaletteColor {
+    pub hex: String,
+    /// Share of the image covered by the color, from 0 to 100
+    pub percentage: f32,
+}
+
+impl PaletteColor {
+    fn new(rgb: [u8; 3], percentage: f32) -> Self {
+        Self {
+            hex: format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]),
+            percentage,
+        }
+    }
+
+    /// Color of `count` pixels out of `total`
+    fn from_share(rgb: [u8; 3], count: usize, total: usize) -> Self {
+        Self::new(rgb, count as f32 * 100.0 / total.max(1) as f32)
+    }
+}
+
+/// Palette of an image, most used color first
+pub fn extract_palette(image: &RgbaImage, options: &PaletteOptions) -> Vec<PaletteColor> {
+    let pixels = filter_pixels(image, options);
+    let color_count = options.color_count.clamp(1, u8::MAX as usize);
+
+    if pixels.is_empty() {
+        return Vec::new();
+    }
+
+    let mut palette = match options.algorithm {
+        PaletteAlgorithm::Kmeans => kmeans(&pixels, color_count),
+        PaletteAlgorithm::MedianCut => median_cut(&pixels, color_count),
+        PaletteAlgorithm::Octree => octree(&pixels, color_count),
+    };
+    palette.retain(|color| color.percentage > 0.0);
+    palette.sort_by(|a, b| b.percentage.total_cmp(&a.percentage));
+
+    palette
+}
+
+/// Opaque pixels kept for the palette, every pixel when the options would drop them all
+fn filter_pixels(image: &RgbaImage, options: &PaletteOptions) -> Vec<[u8; 3]> {
+    let all = || image.pixels().map(|pixel| [pixel[0], pixel[1], pixel[2]]);
+
+    let kept: Vec<[u8; 3]> = image
+        .pixels()
+        .filter(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            !(options.ignore_transparent && a < MIN_ALPHA
+                || options.ignore_white && r.min(g).min(b) >= NEAR_WHITE
+                || options.ignore_black && r.max(g).max(b) <= NEAR_BLACK)
+        })
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+
+    if kept.is_empty() {
+        all().collect()
+    } else {
+        kept
+    }
+}
+
+fn kmeans(pixels: &[[u8; 3]], color_count: usize) -> Vec<PaletteColor> {
+    let srgba: Vec<Srgba<u8>> = pixels
+        .iter()
+        .map(|&[r, g, b]| Srgba::new(r, g, b, u8::MAX))
+        .collect();
+
+    let mut lab_cache = FxHashMap::default();
+    let mut lab_pixels: Vec<Lab<D65, f32>> = Vec::new();
+    cached_srgba_to_lab(srgba.iter(), &mut lab_cache, &mut lab_pixels);
+
+    let mut result = Kmeans::new();
+    let max_iterations = 20;
+    let converge = 10.0;
+    let verbose = false;
+    let run = 3;
+    let seed: u64 = 0;
+
+    for i in 0..run {
+        let run_result = get_kmeans_hamerly(
+            color_count,
+            max_iterations,
+            converge,
+            verbose,
+            &lab_pixels,
+            seed + i,
+        );
+        if run_result.score < result.score {
+            result = run_result;
+        }
+    }
+
+    Lab::<D65, f32>::sort_indexed_colors(&result.centroids, &result.indices)
+        .iter()
+        .map(|color| {
+            let c: Srgb<u8> = Srgb::from_color(color.centroid).into_format();
+            PaletteColor::new([c.red, c.green, c.blue], color.percentage * 100.0)
+        })
+        .collect()
+}
+
+/// Split the box with the widest channel range at its median until there are enough boxes
+fn median_cut(pixels: &[[u8; 3]], color_count: usize) -> Vec<PaletteColor> {
+    let total = pixels.len();
+    let mut boxes = vec![pixels.to_vec()];
+
+    while boxes.len() < color_count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, pixels)| pixels.len() > 1)
+            .map(|(i, pixels)| (i, widest_channel(pixels)))
+            .filter(|(_, (_, range))| *range > 0)
+            .max_by_key(|(_, (_, range))| *range);
+
+        let Some((i, (channel, _))) = widest else {
+            break;
+        };
+
+        let mut pixels = boxes.swap_remove(i);
+        pixels.sort_unstable_by_key(|pixel| pixel[channel]);
+
+        // Cut next to the median value so pixels of the same shade stay in one box
+        let median = pixels[pixels.len() / 2][channel];
+        let below = pixels.partition_point(|pixel| pixel[channel] < median);
+        let cut = if below > 0 {
+            below
+        } else {
+            pixels.partition_point(|pixel| pixel[channel] <= median)
+        };
+        let upper = pixels.split_off(cut);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|pixels| {
+            let mut sum = [0u64; 3];
+            for pixel in pixels {
+                accumulate(&mut sum, pixel);
+            }
+            PaletteColor::from_share(average(sum, pixels.len()), pixels.len(), total)
+        })
+        .collect()
+}
+
+/// Channel with the largest spread in a box and that spread
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let (min, max) = pixels.iter().fold((u8::MAX, u8::MIN), |(min, max), pixel| {
+                (min.min(pixel[channel]), max.max(pixel[channel]))
+            });
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+#[derive(Clone, Copy)]
+struct OctreeLeaf {
+    count: usize,
+    sum: [u64; 3],
+}
+
+/// Bucket the pixels in an octree then fold the least used branches, deepest first
+fn octree(pixels: &[[u8; 3]], color_count: usize) -> Vec<PaletteColor> {
+    let mut leaves: FxHashMap<u32, OctreeLeaf> = FxHashMap::default();
+    for pixel in pixels {
+        let leaf = leaves
+            .entry(octree_key(pixel, OCTREE_DEPTH))
+            .or_insert(OctreeLeaf {
+                count: 0,
+                sum: [0; 3],
+            });
+        leaf.count += 1;
+        accumulate(&mut leaf.sum, pixel);
+    }
+
+    // Every leaf sits at the same depth, so folding a level only merges siblings
+    let mut depth = OCTREE_DEPTH;
+    while leaves.len() > color_count && depth > 0 {
+        let mut parents: FxHashMap<u32, Vec<u32>> = FxHashMap::default();
+        for &key in leaves.keys() {
+            parents.entry(key >> 3).or_default().push(key);
+        }
+
+        let mut branches: Vec<(usize, Vec<u32>)> = parents
+            .into_values()
+            .map(|children| (children.iter().map(|key| leaves[key].count).sum(), children))
+            .collect();
+        branches.sort_unstable_by_key(|(count, children)| (*count, children[0]));
+
+        let mut remaining = leaves.len();
+        let mut folded = FxHashMap::default();
+        for (_, children) in branches {
+            let fold = remaining > color_count;
+            if fold {
+                remaining -= children.len() - 1;
+            }
+
+            for key in children {
+                let leaf = leaves[&key];
+                let target = if fold { key >> 3 } else { key };
+                let merged = folded.entry((fold, target)).or_insert(OctreeLeaf {
+                    count: 0,
+                    sum: [0; 3],
+                });
+                merged.count += leaf.count;
+                for (total, value) in merged.sum.iter_mut().zip(leaf.sum) {
+                    *total += value;
+                }
+            }
+        }
+
+        if remaining > color_count {
+            // The whole level was folded, keep going one level up
+            leaves = folded
+                .into_iter()
+                .map(|((_, key), leaf)| (key, leaf))
+                .collect();
+            depth -= 1;
+        } else {
+            return octree_palette(folded.into_values(), pixels.len());
+        }
+    }
+
+    octree_palette(leaves.into_values(), pixels.len())
+}
+
+fn octree_palette(leaves: impl Iterator<Item = OctreeLeaf>, total: usize) -> Vec<PaletteColor> {
+    leaves
+        .map(|leaf| PaletteColor::from_share(average(leaf.sum, leaf.count), leaf.count, total))
+        .collect()
+}
+
+/// Octree path of a color, 3 bits per level taken from the top bits of each channel
+fn octree_key(pixel: &[u8; 3], depth: u32) -> u32 {
+    (0..depth).fold(0, |key, level| {
+        let bit = 7 - level;
+        let branch = (0..3).fold(0, |branch, channel| {
+            (branch << 1) | ((pixel[channel] >> bit) & 1) as u32
+        });
+        (key << 3) | branch
+    })
+}
+
+fn accumulate(sum: &mut [u64; 3], pixel: &[u8; 3]) {
+    for (total, value) in sum.iter_mut().zip(pixel) {
+        *total += *value as u64;
+    }
+}
+
+fn average(sum: [u64; 3], count: usize) -> [u8; 3] {
+    let count = count.max(1) as u64;
+    sum.map(|channel| (channel / count) as u8)
+}
+
+/// Default CIEDE2000 distance under which two colors are considered alike
+pub const DEFAULT_COLOR_TOLERANCE: f32 = 20.0;
+
+/// Chroma under which a color counts as a gray, grays have no meaningful hue
+const GRAY_CHROMA: f32 = 10.0;
+
+/// Image ref matching a color search
+#[derive(Serialize, Debug, Clone)]
+pub struct ColorMatch {
+    #[serde(rename = "ref")]
+    pub ref_data: Ref,
+    /// Distance weighted by coverage, lower is closer
+    pub distance: f32,
+    /// Share of the image within the tolerance of the query, from 0 to 100
+    pub coverage: f32,
+}
+
+/// Parse a `#rrggbb` color into Lab
+pub fn parse_hex(hex: &str) -> Option<Lab<D65, f32>> {
+    let rgb: Srgb<u8> = hex.trim().parse().ok()?;
+    Some(Lab::from_color(rgb.into_format::<f32>()))
+}
+
+/// Image, video and audio refs with a color close to `hex`, closest first
+pub fn search_by_color(
+    refs: Vec<Ref>,
+    hex: &str,
+    tolerance: f32,
+) -> Result<Vec<ColorMatch>, String> {
+    let query = parse_hex(hex).ok_or_else(|| format!("Invalid color '{}'", hex))?;
+
+    let mut matches: Vec<ColorMatch> = refs
+        .into_iter()
+        .filter_map(|ref_data| {
+            let palette = palette_lab(ref_colors(&ref_data)?);
+            let (distance, coverage) = color_distance(query, &palette, tolerance)?;
+            Some(ColorMatch {
+                ref_data,
+                distance,
+                coverage,
+            })
+        })
+        .collect();
+    matches.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+
+    Ok(matches)
+}
+
+/// Image, video and audio refs whose palette looks like the palette of `source`, closest first
+pub fn similar_palettes(refs: Vec<Ref>, source: &Ref, tolerance: f32) -> Vec<ColorMatch> {
+    let Some(source_palette) = ref_colors(source).map(palette_lab) else {
+        return Vec::new();
+    };
+
+    let mut matches: Vec<ColorMatch> = refs
+        .into_iter()
+        .filter(|ref_data| ref_data.get_id() != source.get_id())
+        .filter_map(|ref_data| {
+            let palette = palette_lab(ref_colors(&ref_data)?);
+            let distance = palette_distance(&source_palette, &palette)?;
+            let coverage = source_palette
+                .iter()
+                .filter(|(color, _)| {
+                    palette
+                        .iter()
+                        .any(|(other, _)| color.difference(*other) <= tolerance)
+                })
+                .map(|(_, share)| share)
+                .sum();
+            (distance <= tolerance).then_some(ColorMatch {
+                ref_data,
+                distance,
+                coverage,
+            })
+        })
+        .collect();
+    matches.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+
+    matches
+}
+
+/// Image, video and audio refs ordered along the hue wheel of their dominant color, grays last from dark to light
+pub fn sort_by_hue(refs: Vec<Ref>) -> Vec<Ref> {
+    let mut keyed: Vec<((bool, f32), Ref)> = refs
+        .into_iter()
+        .filter_map(|ref_data| {
+            let (dominant, _) = *palette_lab(ref_colors(&ref_data)?).first()?;
+            Some((hue_key(dominant), ref_data))
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+
+    keyed.into_iter().map(|(_, ref_data)| ref_data).collect()
+}
+
+/// Best distance to the query and coverage of the colors within `tolerance`.
+///
+/// Every color counts for its share, colors further than `tolerance` count as `tolerance`,
+/// so a query covering most of an image ranks before a small exact match.
+fn color_distance(
+    query: Lab<D65, f32>,
+    palette: &[(Lab<D65, f32>, f32)],
+    tolerance: f32,
+) -> Option<(f32, f32)> {
+    let distances: Vec<(f32, f32)> = palette
+        .iter()
+        .map(|(color, share)| (query.difference(*color), *share))
+        .collect();
+
+    let coverage: f32 = distances
+        .iter()
+        .filter(|(distance, _)| *distance <= tolerance)
+        .map(|(_, share)| share)
+        .sum();
+    if coverage == 0.0 {
+        return None;
+    }
+
+    let total: f32 = distances.iter().map(|(_, share)| share).sum();
+    let weighted: f32 = distances
+        .iter()
+        .map(|(distance, share)| distance.min(tolerance) * share)
+        .sum();
+
+    Some((weighted / total, coverage))
+}
+
+/// Symmetric distance between two palettes, each color weighted by its share
+fn palette_distance(a: &[(Lab<D65, f32>, f32)], b: &[(Lab<D65, f32>, f32)]) -> Option<f32> {
+    let one_way = |from: &[(Lab<D65, f32>, f32)], to: &[(Lab<D65, f32>, f32)]| {
+        let total: f32 = from.iter().map(|(_, share)| share).sum();
+        let weighted: f32 = from
+            .iter()
+            .map(|(color, share)| {
+                let closest = to
+                    .iter()
+                    .map(|(other, _)| color.difference(*other))
+                    .fold(f32::MAX, f32::min);
+                closest * share
+            })
+            .sum();
+        (total > 0.0).then(|| weighted / total)
+    };
+
+    Some((one_way(a, b)? + one_way(b, a)?) / 2.0)
+}
+
+/// Sort key of a color, colored hues in degrees first then grays by lightness
+fn hue_key(color: Lab<D65, f32>) -> (bool, f32) {
+    let chroma = (color.a * color.a + color.b * color.b).sqrt();
+    if chroma < GRAY_CHROMA {
+        (true, color.l)
+    } else {
+        (false, color.get_hue().into_positive_degrees())
+    }
+}
+
+fn ref_colors(ref_data: &Ref) -> Option<&[PaletteColor]> {
+    match ref_data {
+        Ref::Image(image_ref) => Some(&image_ref.metadata.as_ref()?.colors),
+        Ref::Video(video_ref) => Some(&video_ref.metadata.as_ref()?.colors),
+        Ref::Audio(audio_ref) => Some(&audio_ref.metadata.as_ref()?.colors),
+        _ => None,
+    }
+}
+
+/// Colors of a palette in Lab with their share, unreadable colors are skipped
+fn palette_lab(palette: &[PaletteColor]) -> Vec<(Lab<D65, f32>, f32)> {
+    palette
+        .iter()
+        .filter_map(|color| Some((parse_hex(&color.hex)?, color.percentage)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{ImageMetadata, ImageRef};
+    use image::Rgba;
+
+    /// Three quarters red, one quarter blue, with a transparent and a white column
+    fn test_image() -> RgbaImage {
+        RgbaImage::from_fn(10, 10, |x, _| match x {
+            0 => Rgba([0, 0, 0, 0]),
+            1 => Rgba([255, 255, 255, 255]),
+            2..=7 => Rgba([220, 20, 30, 255]),
+            _ => Rgba([20, 40, 200, 255]),
+        })
+    }
+
+    #[test]
+    fn test_extract_palette_algorithms() {
+        for algorithm in [
+            PaletteAlgorithm::Kmeans,
+            PaletteAlgorithm::MedianCut,
+            PaletteAlgorithm::Octree,
+        ] {
+            let options = PaletteOptions {
+                color_count: 2,
+                algorithm,
+                ignore_white: true,
+                ..Default::default()
+            };
+            let palette = extract_palette(&test_image(), &options);
+
+            assert_eq!(palette.len(), 2, "{:?}", algorithm);
+            assert!(
+                (palette[0].percentage - 75.0).abs() < 0.1,
+                "{:?}",
+                algorithm
+            );
+            assert!(
+                (palette[1].percentage - 25.0).abs() < 0.1,
+                "{:?}",
+                algorithm
+            );
+            assert!(palette[0].hex.starts_with("#d"), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_extract_palette_filters() {
+        let options = PaletteOptions {
+            color_count: 4,
+            algorithm: PaletteAlgorithm::MedianCut,
+            ignore_transparent: false,
+            ..Default::default()
+        };
+        let palette = extract_palette(&test_image(), &options);
+        let total: f32 = palette.iter().map(|color| color.percentage).sum();
+        assert!((total - 100.0).abs() < 0.1);
+        assert!(palette.iter().any(|color| color.hex == "#ffffff"));
+        assert!(palette.iter().any(|color| color.hex == "#000000"));
+
+        // An image made only of ignored pixels still gets a palette
+        let white = RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 255]));
+        let options = PaletteOptions {
+            ignore_white: true,
+            ..Default::default()
+        };
+        let palette = extract_palette(&white, &options);
+        assert_eq!(palette[0].hex, "#ffffff");
+    }
+
+    fn image_ref(id: &str, colors: &[(&str, f32)]) -> Ref {
+        Ref::Image(ImageRef {
+            metadata: Some(ImageMetadata {
+                id: id.to_string(),
+                colors: colors
+                    .iter()
+                    .map(|(hex, percentage)| PaletteColor {
+                        hex: hex.to_string(),
+                        percentage: *percentage,
+                    })
+                    .collect(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn ids(refs: impl IntoIterator<Item = Ref>) -> Vec<String> {
+        refs.into_iter()
+            .map(|ref_data| ref_data.get_id().to_string())
+            .collect()
+    }
+
+    fn library() -> Vec<Ref> {
+        vec![
+            image_ref("MOSTLYRED", &[("#e01010", 80.0), ("#2040d0", 20.0)]),
+            image_ref("LITTLERED", &[("#2040d0", 90.0), ("#e01010", 10.0)]),
+            image_ref("GREEN", &[("#10c020", 100.0)]),
+            image_ref("GRAY", &[("#808080", 100.0)]),
+        ]
+    }
+
+    #[test]
+    fn test_search_by_color() {
+        let matches = search_by_color(library(), "#ff0000", DEFAULT_COLOR_TOLERANCE).unwrap();
+        let found: Vec<String> = ids(matches.iter().map(|found| found.ref_data.clone()));
+        assert_eq!(found, vec!["MOSTLYRED", "LITTLERED"]);
+        assert_eq!(matches[0].coverage, 80.0);
+
+        assert!(search_by_color(library(), "not a color", 10.0).is_err());
+    }
+
+    #[test]
+    fn test_similar_palettes_and_hue_order() {
+        let library = library();
+        let matches = similar_palettes(library.clone(), &library[0], DEFAULT_COLOR_TOLERANCE);
+        let found: Vec<String> = ids(matches.into_iter().map(|found| found.ref_data));
+        assert_eq!(found, vec!["LITTLERED"]);
+
+        assert_eq!(
+            ids(sort_by_hue(library)),
+            vec!["MOSTLYRED", "GREEN", "LITTLERED", "GRAY"]
+        );
+    }
+}
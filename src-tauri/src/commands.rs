@@ -1,26 +1,292 @@
 use chrono::Local;
-use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::thread::sleep;
-use std::time::Duration;
-use std::{default::Default, fs, path::Path, sync::Mutex, thread};
-use tauri::{AppHandle, Manager, State, WindowBuilder, WindowUrl};
-use tauri_plugin_snapshot::{snapshot, Options, Region};
-
-use crate::config::get_collection_path;
-use crate::media;
+use std::path::{Path, PathBuf};
+use std::{default::Default, fs, sync::Mutex, thread};
+use tauri::{AppHandle, Manager, State};
+
+use crate::audio;
+use crate::colors::{self, ColorMatch, PaletteColor, DEFAULT_COLOR_TOLERANCE};
+use crate::config::{get_backup_path, get_collection_path, get_quarantine_path, get_trash_path};
+use crate::dedup::{self, SimilarGroup, DEFAULT_SIMILARITY_THRESHOLD};
+use crate::exif;
+use crate::import::{
+    self, DirectoryImportOptions, ImportCandidate, ImportFailure, ImportJobs, ImportProgress,
+    ImportReport, RefKind,
+};
+use crate::index::{DuplicateGroup, LibraryIndex, RefQuery, SyncReport};
+use crate::integrity::{self, IntegrityReport, RepairOptions, RepairReport};
+use crate::jobs::{Job, JobKind, JobQueue};
+use crate::migration::{self, MigrationReport};
 use crate::state::{
     AudioMetadata, AudioRef, DocMetadata, DocRef, ImageMetadata, ImageRef, LinkMetadata, LinkRef,
     NoteMetadata, NoteRef, Ref, Settings, VideoMetadata, VideoRef,
 };
-use crate::utils::{self, convert_file_src, mutate_note};
+use crate::storage;
+use crate::swatches::{self, PaletteFormat};
+use crate::thumbnail;
+use crate::timeline::{ClipRange, Marker, Timeline, TimelineEdit};
+use crate::trash::{self, TrashEntry};
+use crate::utils::{self, mutate_note};
+use crate::video::{self, FrameSource};
 
 #[tauri::command]
-async fn get_all_refs(state: State<'_, Mutex<Vec<Ref>>>) -> Result<Vec<Ref>, String> {
-    let state_guard = state
+async fn get_all_refs(index: State<'_, LibraryIndex>) -> Result<Vec<Ref>, String> {
+    index.all().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_ref(ref_id: &str, index: State<'_, LibraryIndex>) -> Result<Ref, String> {
+    index
+        .get(ref_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))
+}
+
+#[tauri::command]
+async fn query_refs(query: RefQuery, index: State<'_, LibraryIndex>) -> Result<Vec<Ref>, String> {
+    index.query(&query).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_all_tags(index: State<'_, LibraryIndex>) -> Result<Vec<(String, usize)>, String> {
+    index.tags().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn reindex_library(
+    index: State<'_, LibraryIndex>,
+    handle: AppHandle,
+) -> Result<SyncReport, String> {
+    index
+        .rebuild(&get_collection_path(&handle))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn migrate_library(
+    dry_run: bool,
+    index: State<'_, LibraryIndex>,
+    handle: AppHandle,
+) -> Result<MigrationReport, String> {
+    let collections_dir = get_collection_path(&handle);
+    let report = migration::migrate_library(&collections_dir, &get_backup_path(&handle), dry_run)
+        .map_err(|e| e.to_string())?;
+
+    if !dry_run {
+        index.sync(&collections_dir).map_err(|e| e.to_string())?;
+    }
+
+    Ok(report)
+}
+
+#[tauri::command]
+async fn check_library(handle: AppHandle) -> Result<IntegrityReport, String> {
+    integrity::check_library(&get_collection_path(&handle)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn repair_library(
+    options: Option<RepairOptions>,
+    index: State<'_, LibraryIndex>,
+    handle: AppHandle,
+) -> Result<RepairReport, String> {
+    let collections_dir = get_collection_path(&handle);
+    let report = integrity::check_library(&collections_dir).map_err(|e| e.to_string())?;
+    let mut options = options.unwrap_or_default();
+    if options.thumbnail_format.is_none() {
+        options.thumbnail_format = handle
+            .state::<Mutex<Settings>>()
+            .lock()
+            .map(|settings| settings.behavior.thumbnail_format)
+            .ok();
+    }
+
+    let result = integrity::repair_library(&report, &get_quarantine_path(&handle), &options);
+
+    index.sync(&collections_dir).map_err(|e| e.to_string())?;
+
+    Ok(result)
+}
+
+#[tauri::command]
+async fn find_duplicates(index: State<'_, LibraryIndex>) -> Result<Vec<DuplicateGroup>, String> {
+    dedup::fill_missing_hashes(&index).map_err(|e| e.to_string())?;
+    index.duplicates().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn find_similar_images(
+    max_distance: Option<u32>,
+    index: State<'_, LibraryIndex>,
+) -> Result<Vec<SimilarGroup>, String> {
+    dedup::fill_missing_perceptual_hashes(&index).map_err(|e| e.to_string())?;
+    let hashes = index.perceptual_hashes().map_err(|e| e.to_string())?;
+
+    Ok(dedup::cluster_similar(
+        hashes,
+        max_distance.unwrap_or(DEFAULT_SIMILARITY_THRESHOLD),
+    ))
+}
+
+#[tauri::command]
+async fn search_by_color(
+    hex: &str,
+    tolerance: Option<f32>,
+    index: State<'_, LibraryIndex>,
+) -> Result<Vec<ColorMatch>, String> {
+    colors::search_by_color(
+        colored_refs(&index)?,
+        hex,
+        tolerance.unwrap_or(DEFAULT_COLOR_TOLERANCE),
+    )
+}
+
+#[tauri::command]
+async fn find_similar_palettes(
+    ref_id: &str,
+    tolerance: Option<f32>,
+    index: State<'_, LibraryIndex>,
+) -> Result<Vec<ColorMatch>, String> {
+    let source = index
+        .get(ref_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))?;
+
+    Ok(colors::similar_palettes(
+        colored_refs(&index)?,
+        &source,
+        tolerance.unwrap_or(DEFAULT_COLOR_TOLERANCE),
+    ))
+}
+
+#[tauri::command]
+async fn sort_by_hue(index: State<'_, LibraryIndex>) -> Result<Vec<Ref>, String> {
+    Ok(colors::sort_by_hue(colored_refs(&index)?))
+}
+
+/// Merge the palettes of image refs and write them to `path`
+#[tauri::command]
+async fn export_palette(
+    ref_ids: Vec<String>,
+    format: PaletteFormat,
+    path: &str,
+    index: State<'_, LibraryIndex>,
+) -> Result<String, String> {
+    let mut names = Vec::new();
+    let mut palettes = Vec::new();
+
+    for ref_id in &ref_ids {
+        match index.get(ref_id).map_err(|e| e.to_string())? {
+            Some(Ref::Image(ImageRef {
+                metadata: Some(metadata),
+                ..
+            })) => {
+                names.push(if metadata.name.is_empty() {
+                    metadata.file_name
+                } else {
+                    metadata.name
+                });
+                palettes.push(metadata.colors);
+            }
+            Some(_) => return Err(format!("Reference '{}' is not an image", ref_id)),
+            None => return Err(format!("Reference with ID '{}' not found", ref_id)),
+        }
+    }
+
+    let palettes: Vec<&[PaletteColor]> = palettes.iter().map(Vec::as_slice).collect();
+    let colors = swatches::merge_palettes(&palettes);
+    if colors.is_empty() {
+        return Err("No colors to export".to_string());
+    }
+
+    let name = match names.as_slice() {
+        [name] => name.clone(),
+        _ => "Onlyrefs palette".to_string(),
+    };
+
+    let mut path = PathBuf::from(path);
+    if path.extension().is_none() {
+        path.set_extension(format.extension());
+    }
+
+    swatches::export_palette(&path, &name, &colors, format).map_err(|e| e.to_string())?;
+
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Refs with a palette: images, videos and audio with cover art
+fn colored_refs(index: &LibraryIndex) -> Result<Vec<Ref>, String> {
+    let mut refs = Vec::new();
+    for ref_type in ["image", "video", "audio"] {
+        let query = RefQuery {
+            ref_type: Some(ref_type.to_string()),
+            ..Default::default()
+        };
+        refs.extend(index.query(&query).map_err(|e| e.to_string())?);
+    }
+    Ok(refs)
+}
+
+/// Read the embedded metadata of the given images, every image never read when no id is given.
+///
+/// Returns the amount of refs updated.
+#[tauri::command]
+async fn backfill_exif(
+    ref_ids: Option<Vec<String>>,
+    index: State<'_, LibraryIndex>,
+    settings: State<'_, Mutex<Settings>>,
+) -> Result<usize, String> {
+    let import_keywords = settings
         .lock()
+        .map(|settings| settings.behavior.import_keywords_as_tags)
         .map_err(|_| "Failed to acquire lock on state".to_string())?;
-    Ok(state_guard.clone())
+
+    let refs = match ref_ids {
+        Some(ref_ids) => ref_ids
+            .iter()
+            .map(|ref_id| {
+                index
+                    .get(ref_id)
+                    .map_err(|e| e.to_string())?
+                    .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))
+            })
+            .collect::<Result<Vec<Ref>, String>>()?,
+        None => index.missing_exif().map_err(|e| e.to_string())?,
+    };
+
+    exif::fill_exif(&index, refs, import_keywords).map_err(|e| e.to_string())
+}
+
+/// Queue the color extraction of the given refs again, every image, video and audio when no id is given
+#[tauri::command]
+async fn reextract_colors(
+    ref_ids: Option<Vec<String>>,
+    index: State<'_, LibraryIndex>,
+    jobs: State<'_, JobQueue>,
+) -> Result<Vec<Job>, String> {
+    let refs = match ref_ids {
+        Some(ref_ids) => ref_ids
+            .iter()
+            .map(|ref_id| {
+                index
+                    .get(ref_id)
+                    .map_err(|e| e.to_string())?
+                    .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))
+            })
+            .collect::<Result<Vec<Ref>, String>>()?,
+        None => index.all().map_err(|e| e.to_string())?,
+    };
+
+    Ok(refs
+        .iter()
+        .filter_map(|ref_data| match ref_data {
+            Ref::Image(image_ref) => JobKind::extract_colors(image_ref),
+            Ref::Video(video_ref) => JobKind::extract_video_colors(video_ref),
+            Ref::Audio(audio_ref) => JobKind::extract_audio_colors(audio_ref),
+            _ => None,
+        })
+        .map(|job| jobs.enqueue(job))
+        .collect())
 }
 
 #[tauri::command]
@@ -31,133 +297,435 @@ async fn get_settings(state: State<'_, Mutex<Settings>>) -> Result<Settings, Str
     Ok(state_guard.clone())
 }
 
-#[tauri::command]
-async fn generate_image_metadata(
+fn create_image_ref(
     ref_id: String,
     collection: &str,
     file_name: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
-    handle: AppHandle,
+    index: &LibraryIndex,
+    handle: &AppHandle,
 ) -> Result<ImageRef, String> {
-    let base_path = get_collection_path(&handle).join(&ref_id);
+    let base_path = get_collection_path(handle).join(&ref_id);
     let meta_path = base_path.join("metadata.image.json");
     let media_path = base_path.join(file_name);
-    let low_res_media_path = base_path.join("lower_".to_string() + file_name);
 
-    let metadata = ImageMetadata::new(ref_id.clone(), file_name, &media_path, collection)?;
-    let json_data = serde_json::to_string_pretty(&metadata).expect("Failed to serialize metadata");
-    fs::write(meta_path.clone(), json_data).expect("Failed to write metadata file");
+    let content_hash = dedup::hash_file(&media_path).map_err(|e| e.to_string())?;
+    if let Some(Ref::Image(existing)) =
+        dedup::take_existing(index, &content_hash, "image", &ref_id, &base_path)
+            .map_err(|e| e.to_string())?
+    {
+        return Ok(existing);
+    }
 
-    let low_res_imagepath = if media::generate_image(file_name, &base_path, 500, 500, 500) {
-        convert_file_src(&low_res_media_path)
+    let behavior = handle
+        .state::<Mutex<Settings>>()
+        .lock()
+        .map(|settings| settings.behavior.clone())
+        .unwrap_or_default();
+
+    let mut metadata = ImageMetadata::new(ref_id.clone(), file_name, &media_path, collection)?;
+    metadata.content_hash = Some(content_hash);
+    if behavior.import_keywords_as_tags {
+        let keywords = metadata
+            .exif
+            .as_ref()
+            .map(|exif| exif.keywords.clone())
+            .unwrap_or_default();
+        metadata.add_keywords(&keywords);
+    }
+    storage::with_ref_lock(&meta_path, || storage::write_json(&meta_path, &metadata))
+        .map_err(|e| e.to_string())?;
+
+    let thumbnails = if thumbnail::supports_thumbnails(file_name) {
+        thumbnail::generate_thumbnails(&media_path, &base_path, behavior.thumbnail_format)
+            .unwrap_or_else(|e| {
+                eprintln!("Error generating thumbnails for {}: {}", file_name, e);
+                Vec::new()
+            })
     } else {
-        convert_file_src(&media_path)
+        Vec::new()
     };
 
-    let new_ref = ImageRef::new(&media_path, low_res_imagepath, metadata, meta_path.clone())?;
-
-    let new_rf_thread = new_ref.clone();
-    let ref_id_thread = ref_id.clone();
-
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
-
-    // Handle colors in a separate thread
-    thread::spawn(move || -> Result<(), String> {
-        // extract colors
-        let colors = media::extract_colors(&media_path);
-        let mut metadata = new_rf_thread.metadata.as_ref().unwrap().clone();
-        metadata.colors = colors.clone();
+    let new_ref = ImageRef::new(&media_path, thumbnails, metadata, meta_path.clone())?;
 
-        let json_data = serde_json::to_string_pretty(&metadata).unwrap();
-        fs::write(meta_path, json_data).expect("Failed to write metadata file");
+    index
+        .upsert(&Ref::Image(new_ref.clone()))
+        .map_err(|e| e.to_string())?;
 
-        let state_mutex = handle.state::<Mutex<Vec<Ref>>>();
-        let state = state_mutex
-            .lock()
-            .map_err(|_| "Failed to acquire lock on state".to_string())?;
-
-        // find the reference in the state and update the colors
-        state.iter().find(|ref_instance| match ref_instance {
-            Ref::Image(media_ref) => {
-                if let Some(metadata) = &media_ref.metadata {
-                    metadata.id == ref_id_thread
-                } else {
-                    false
-                }
-            }
-            _ => false,
-        });
-
-        //emit color extracted event
-        handle.emit_all("colors-added", Some(colors)).unwrap();
-
-        Ok(())
-    });
+    // Extract the colors in the background
+    if let Some(job) = JobKind::extract_colors(&new_ref) {
+        handle.state::<JobQueue>().enqueue(job);
+    }
 
-    state_guard.push(Ref::Image(new_ref.clone()));
     Ok(new_ref)
 }
 
-#[tauri::command]
-async fn generate_video_metadata(
+fn create_video_ref(
     ref_id: String,
     collection: &str,
     file_name: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
-    handle: AppHandle,
+    index: &LibraryIndex,
+    handle: &AppHandle,
 ) -> Result<VideoRef, String> {
-    let base_path = get_collection_path(&handle).join(&ref_id);
+    let base_path = get_collection_path(handle).join(&ref_id);
     let meta_path = base_path.join("metadata.video.json");
     let media_path = base_path.join(file_name);
 
-    let metadata = VideoMetadata::new(ref_id.clone(), file_name, &media_path, collection)?;
-    let json_data = serde_json::to_string_pretty(&metadata).expect("Failed to serialize metadata");
-    fs::write(meta_path.clone(), json_data).expect("Failed to write metadata file");
+    let content_hash = dedup::hash_file(&media_path).map_err(|e| e.to_string())?;
+    if let Some(Ref::Video(existing)) =
+        dedup::take_existing(index, &content_hash, "video", &ref_id, &base_path)
+            .map_err(|e| e.to_string())?
+    {
+        return Ok(existing);
+    }
 
-    let new_ref = VideoRef::new(&media_path, metadata, meta_path.clone())?;
+    let mut metadata = VideoMetadata::new(ref_id.clone(), file_name, &media_path, collection)?;
+    metadata.content_hash = Some(content_hash);
+    storage::with_ref_lock(&meta_path, || storage::write_json(&meta_path, &metadata))
+        .map_err(|e| e.to_string())?;
 
-    let mut state_guard = state
+    let thumbnail_format = handle
+        .state::<Mutex<Settings>>()
         .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
+        .map(|settings| settings.behavior.thumbnail_format)
+        .unwrap_or_default();
+    let duration = metadata.duration();
+    let thumbnails = video::generate_poster(&media_path, &base_path, duration, thumbnail_format)
+        .unwrap_or_else(|e| {
+            eprintln!("Error generating the poster of {}: {}", file_name, e);
+            Vec::new()
+        });
+
+    let new_ref = VideoRef::new(&media_path, thumbnails, metadata, meta_path.clone())?;
+
+    index
+        .upsert(&Ref::Video(new_ref.clone()))
+        .map_err(|e| e.to_string())?;
+
+    // Extract the colors of the poster in the background
+    if let Some(job) = JobKind::extract_video_colors(&new_ref) {
+        handle.state::<JobQueue>().enqueue(job);
+    }
 
-    state_guard.push(Ref::Video(new_ref.clone()));
     Ok(new_ref)
 }
 
+/// Save the frame shown at `timestamp` seconds of a video as a new image ref in its collection
 #[tauri::command]
-async fn generate_audio_metadata(
+async fn capture_video_frame(
+    ref_id: &str,
+    timestamp: f64,
+    index: State<'_, LibraryIndex>,
+    handle: AppHandle,
+) -> Result<ImageRef, String> {
+    let video_ref = match index.get(ref_id).map_err(|e| e.to_string())? {
+        Some(Ref::Video(video_ref)) => video_ref,
+        Some(_) => return Err(format!("Reference '{}' is not a video", ref_id)),
+        None => return Err(format!("Reference with ID '{}' not found", ref_id)),
+    };
+    let metadata = video_ref
+        .metadata
+        .as_ref()
+        .ok_or_else(|| format!("Reference '{}' has no metadata", ref_id))?;
+
+    let duration = metadata.duration();
+    if !timestamp.is_finite() || timestamp < 0.0 || duration.is_some_and(|d| timestamp > d) {
+        return Err(format!("Timestamp {}s is outside of the video", timestamp));
+    }
+
+    let video_path = Path::new(&video_ref.metapath).with_file_name(&metadata.file_name);
+    let (frame_id, frame_dir) =
+        import::create_ref_dir(&get_collection_path(&handle)).map_err(|e| e.to_string())?;
+    let file_name = video::frame_file_name(&metadata.file_name, timestamp);
+
+    let mut image_ref = video::extract_frame(&video_path, timestamp, &frame_dir.join(&file_name))
+        .map_err(|e| e.to_string())
+        .and_then(|()| {
+            create_image_ref(
+                frame_id.clone(),
+                &metadata.collection,
+                &file_name,
+                &index,
+                &handle,
+            )
+        })
+        .inspect_err(|_| {
+            let _ = fs::remove_dir_all(&frame_dir);
+        })?;
+
+    let image_metadata = image_ref
+        .metadata
+        .as_mut()
+        .ok_or_else(|| "Captured frame has no metadata".to_string())?;
+
+    // A frame identical to an existing image was deduplicated into it, that image may live in
+    // another collection and was never captured from this video
+    if image_metadata.id != frame_id {
+        return Err(format!(
+            "The frame at {}s is already in the library as '{}'",
+            timestamp, image_metadata.id
+        ));
+    }
+
+    let source = FrameSource {
+        video_id: ref_id.to_string(),
+        timestamp,
+    };
+    utils::set_source_video(Path::new(&image_ref.metapath), &source).map_err(|e| e.to_string())?;
+    image_metadata.source_video = Some(source.clone());
+
+    index
+        .update(&frame_id, |found_ref| {
+            if let Ref::Image(ImageRef {
+                metadata: Some(ref mut metadata),
+                ..
+            }) = found_ref
+            {
+                metadata.source_video = Some(source);
+            }
+        })
+        .map_err(|e| e.to_string())?;
+
+    Ok(image_ref)
+}
+
+fn create_audio_ref(
     ref_id: String,
     collection: &str,
     file_name: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
-    handle: AppHandle,
+    index: &LibraryIndex,
+    handle: &AppHandle,
 ) -> Result<AudioRef, String> {
-    let base_path = get_collection_path(&handle).join(&ref_id);
+    let base_path = get_collection_path(handle).join(&ref_id);
     let meta_path = base_path.join("metadata.audio.json");
     let media_path = base_path.join(file_name);
 
-    let metadata = AudioMetadata::new(ref_id.clone(), file_name, &media_path, collection)?;
-    let json_data = serde_json::to_string_pretty(&metadata).expect("Failed to serialize metadata");
-    fs::write(meta_path.clone(), json_data).expect("Failed to write metadata file");
-
-    let new_ref = AudioRef::new(&media_path, metadata, meta_path.clone())?;
+    let content_hash = dedup::hash_file(&media_path).map_err(|e| e.to_string())?;
+    if let Some(Ref::Audio(existing)) =
+        dedup::take_existing(index, &content_hash, "audio", &ref_id, &base_path)
+            .map_err(|e| e.to_string())?
+    {
+        return Ok(existing);
+    }
 
-    let mut state_guard = state
+    let behavior = handle
+        .state::<Mutex<Settings>>()
         .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
+        .map(|settings| settings.behavior.clone())
+        .unwrap_or_default();
+
+    let mut metadata = AudioMetadata::new(ref_id.clone(), file_name, &media_path, collection)?;
+    metadata.content_hash = Some(content_hash);
+    if behavior.import_genres_as_tags {
+        metadata.add_genres();
+    }
+    storage::with_ref_lock(&meta_path, || storage::write_json(&meta_path, &metadata))
+        .map_err(|e| e.to_string())?;
+
+    let thumbnails = audio::generate_cover(&media_path, &base_path, behavior.thumbnail_format)
+        .unwrap_or_else(|e| {
+            eprintln!("Error generating the cover of {}: {}", file_name, e);
+            Vec::new()
+        });
+
+    let new_ref = AudioRef::new(&media_path, thumbnails, metadata, meta_path.clone())?;
+
+    index
+        .upsert(&Ref::Audio(new_ref.clone()))
+        .map_err(|e| e.to_string())?;
+
+    // Extract the colors of the cover in the background
+    if let Some(job) = JobKind::extract_audio_colors(&new_ref) {
+        handle.state::<JobQueue>().enqueue(job);
+    }
 
-    state_guard.push(Ref::Audio(new_ref.clone()));
     Ok(new_ref)
 }
 
+/// Copy files into the library and create their refs, moving them when `move_files` is set
+#[tauri::command]
+async fn import_files(
+    paths: Vec<String>,
+    collection: &str,
+    move_files: Option<bool>,
+    index: State<'_, LibraryIndex>,
+    handle: AppHandle,
+) -> Result<ImportReport, String> {
+    let collections_dir = get_collection_path(&handle);
+    let mut report = ImportReport::default();
+
+    for path in paths {
+        let source = Path::new(&path);
+        let result = import::stage_file(&collections_dir, source)
+            .map_err(|e| e.to_string())
+            .and_then(|staged| {
+                import_staged(&staged, collection, &index, &handle).inspect_err(|_| {
+                    import::discard(&staged);
+                })
+            });
+
+        match result {
+            Ok(ref_data) => {
+                if move_files.unwrap_or(false) {
+                    if let Err(e) = fs::remove_file(source) {
+                        eprintln!("Error removing {}: {}", path, e);
+                    }
+                }
+                report.imported.push(ref_data);
+            }
+            Err(error) => report.failed.push(ImportFailure { path, error }),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Import a whole directory in the background and return the id of the import job.
+///
+/// Emits `import-progress` after every file and `import-finished` once done,
+/// every created ref is announced with `ref_added`.
+#[tauri::command]
+async fn import_directory(
+    path: String,
+    collection: String,
+    options: Option<DirectoryImportOptions>,
+    jobs: State<'_, ImportJobs>,
+    handle: AppHandle,
+) -> Result<String, String> {
+    let root = Path::new(&path).to_path_buf();
+    if !root.is_dir() {
+        return Err(format!("'{}' is not a directory", path));
+    }
+
+    let options = options.unwrap_or_default();
+    let job_id = utils::random_id(13);
+    let cancel = jobs.start(&job_id);
+    let job = job_id.clone();
+
+    thread::spawn(move || {
+        let result = match import::scan_directory(&root, &collection, &options) {
+            Ok((candidates, skipped)) => {
+                let progress = ImportProgress {
+                    job_id: job.clone(),
+                    seen: candidates.len() + skipped,
+                    skipped,
+                    ..Default::default()
+                };
+                let _ = handle.emit_all("import-progress", &progress);
+
+                let workers = options.workers.unwrap_or_else(|| {
+                    thread::available_parallelism().map_or(1, |count| count.get())
+                });
+                let index = handle.state::<LibraryIndex>();
+
+                import::run_import(
+                    candidates,
+                    workers,
+                    &cancel,
+                    progress,
+                    |candidate| {
+                        let ref_data =
+                            import_candidate(candidate, options.move_files, &index, &handle)?;
+                        let _ = handle.emit_all("ref_added", &ref_data);
+                        Ok(ref_data)
+                    },
+                    |progress| {
+                        let _ = handle.emit_all("import-progress", progress);
+                    },
+                )
+            }
+            Err(e) => import::ImportResult {
+                progress: ImportProgress {
+                    job_id: job.clone(),
+                    finished: true,
+                    ..Default::default()
+                },
+                failures: vec![ImportFailure {
+                    path: root.to_string_lossy().to_string(),
+                    error: e.to_string(),
+                }],
+            },
+        };
+
+        handle.state::<ImportJobs>().finish(&job);
+        let _ = handle.emit_all("import-finished", result);
+    });
+
+    Ok(job_id)
+}
+
+#[tauri::command]
+async fn cancel_import(job_id: &str, jobs: State<'_, ImportJobs>) -> Result<bool, String> {
+    Ok(jobs.cancel(job_id))
+}
+
+/// Copy a single file found by `import_directory` and apply its subfolder tags.
+///
+/// Files deduplicated into an existing ref leave that ref's tags alone.
+fn import_candidate(
+    candidate: &ImportCandidate,
+    move_files: bool,
+    index: &LibraryIndex,
+    handle: &AppHandle,
+) -> Result<Ref, String> {
+    let staged = import::stage_file(&get_collection_path(handle), &candidate.path)
+        .map_err(|e| e.to_string())?;
+    let mut ref_data = import_staged(&staged, &candidate.collection, index, handle)
+        .inspect_err(|_| import::discard(&staged))?;
+
+    let created = ref_data.get_id() == staged.ref_id;
+    let missing_tags: Vec<&String> = candidate
+        .tags
+        .iter()
+        .filter(|tag| created && !ref_data.get_meta().get_tags().contains(tag))
+        .collect();
+
+    if !missing_tags.is_empty() {
+        let metapath = Path::new(ref_data.get_metapath()).to_path_buf();
+        for tag in missing_tags {
+            utils::add_tag(&metapath, tag).map_err(|e| e.to_string())?;
+            ref_data.get_ref_meta()?.add_tag(tag);
+        }
+        index.upsert(&ref_data).map_err(|e| e.to_string())?;
+    }
+
+    if move_files {
+        if let Err(e) = fs::remove_file(&candidate.path) {
+            eprintln!("Error removing {}: {}", candidate.path.display(), e);
+        }
+    }
+
+    Ok(ref_data)
+}
+
+fn import_staged(
+    staged: &import::StagedFile,
+    collection: &str,
+    index: &LibraryIndex,
+    handle: &AppHandle,
+) -> Result<Ref, String> {
+    let ref_id = staged.ref_id.clone();
+    let file_name = staged.file_name.as_str();
+
+    match staged.kind {
+        RefKind::Image => {
+            create_image_ref(ref_id, collection, file_name, index, handle).map(Ref::Image)
+        }
+        RefKind::Video => {
+            create_video_ref(ref_id, collection, file_name, index, handle).map(Ref::Video)
+        }
+        RefKind::Audio => {
+            create_audio_ref(ref_id, collection, file_name, index, handle).map(Ref::Audio)
+        }
+        RefKind::Doc => create_doc_ref(ref_id, collection, file_name, index, handle).map(Ref::Doc),
+    }
+}
+
 #[tauri::command]
 fn generate_note_metadata(
     ref_id: &str,
     collection: &str,
     note_content: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
+    index: State<'_, LibraryIndex>,
     handle: AppHandle,
 ) -> Result<NoteRef, String> {
     let note_path = get_collection_path(&handle).join(ref_id).join("note.text");
@@ -166,48 +734,56 @@ fn generate_note_metadata(
         .join("metadata.note.json");
 
     let note_metadata = NoteMetadata::new(ref_id, collection)?;
-    let json_data = serde_json::to_string_pretty(&note_metadata).unwrap();
-    fs::write(&meta_path, json_data).expect("Failed to write metadata file");
-    fs::write(note_path, note_content).expect("Failed to write note content to disk");
-
-    // Store into state
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
+    storage::with_ref_lock(&meta_path, || {
+        storage::write_json(&meta_path, &note_metadata)?;
+        storage::write_atomic(&note_path, note_content)
+    })
+    .map_err(|e| e.to_string())?;
 
+    // Store into the index
     let new_note_ref = NoteRef {
         content: note_content.to_string(),
         metadata: Some(note_metadata),
         metapath: meta_path.to_str().unwrap().to_string(),
     };
 
-    state_guard.push(Ref::Note(new_note_ref.clone()));
+    index
+        .upsert(&Ref::Note(new_note_ref.clone()))
+        .map_err(|e| e.to_string())?;
+
     Ok(new_note_ref)
 }
 
-#[tauri::command]
-async fn generate_doc_metadata(
+fn create_doc_ref(
     ref_id: String,
     collection: &str,
     file_name: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
-    handle: AppHandle,
+    index: &LibraryIndex,
+    handle: &AppHandle,
 ) -> Result<DocRef, String> {
-    let base_path = get_collection_path(&handle).join(&ref_id);
+    let base_path = get_collection_path(handle).join(&ref_id);
     let meta_path = base_path.join("metadata.doc.json");
     let doc_path = base_path.join(file_name);
 
-    let metadata = DocMetadata::new(ref_id.clone(), collection)?;
-    let json_data = serde_json::to_string_pretty(&metadata).expect("Failed to serialize metadata");
-    fs::write(meta_path.clone(), json_data).expect("Failed to write metadata file");
+    let content_hash = dedup::hash_file(&doc_path).map_err(|e| e.to_string())?;
+    if let Some(Ref::Doc(existing)) =
+        dedup::take_existing(index, &content_hash, "doc", &ref_id, &base_path)
+            .map_err(|e| e.to_string())?
+    {
+        return Ok(existing);
+    }
+
+    let mut metadata = DocMetadata::new(ref_id.clone(), collection)?;
+    metadata.content_hash = Some(content_hash);
+    storage::with_ref_lock(&meta_path, || storage::write_json(&meta_path, &metadata))
+        .map_err(|e| e.to_string())?;
 
     let new_ref = DocRef::new(&doc_path, metadata, meta_path.clone())?;
 
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
+    index
+        .upsert(&Ref::Doc(new_ref.clone()))
+        .map_err(|e| e.to_string())?;
 
-    state_guard.push(Ref::Doc(new_ref.clone()));
     Ok(new_ref)
 }
 
@@ -216,7 +792,7 @@ async fn generate_link_metadata(
     ref_id: String,
     url: String,
     collection: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
+    index: State<'_, LibraryIndex>,
     handle: AppHandle,
 ) -> Result<LinkRef, String> {
     let meta_path = get_collection_path(&handle)
@@ -224,66 +800,36 @@ async fn generate_link_metadata(
         .join("metadata.link.json");
 
     let metadata = LinkMetadata::new(&ref_id, &url, collection)?;
-    let json_data = serde_json::to_string_pretty(&metadata).expect("Failed to serialize metadata");
-    fs::write(meta_path.clone(), json_data).expect("Failed to write metadata file");
+    storage::with_ref_lock(&meta_path, || storage::write_json(&meta_path, &metadata))
+        .map_err(|e| e.to_string())?;
 
     let new_ref = LinkRef::new(None, metadata, meta_path.clone())?;
 
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
-
-    state_guard.push(Ref::Link(new_ref.clone()));
-
-    std::thread::spawn(move || -> Result<(), String> {
-        let window = tauri::WindowBuilder::new(
-            &handle,
-            "capture",
-            WindowUrl::External(url.parse().unwrap()),
-        )
-        .build()
-        .unwrap();
-
-        let options = Options {
-            capture: None,
-            region: Some(Region::Document),
-            save: None,
-        };
+    index
+        .upsert(&Ref::Link(new_ref.clone()))
+        .map_err(|e| e.to_string())?;
 
-        let _ = window.hide();
+    // Take the snapshot of the page in the background
+    handle
+        .state::<JobQueue>()
+        .enqueue(JobKind::LinkSnapshot { ref_id, url });
 
-        // wait 30s then take the shoot
-        sleep(Duration::from_secs(30));
-        let img_buffer = snapshot(window.clone(), options).map_err(|error| error.to_string());
-        let _ = window.close();
-
-        // save image to disk
-        let img_path = get_collection_path(&handle)
-            .join(&ref_id)
-            .join("snapshot.png");
-        fs::write(&img_path, img_buffer.unwrap()).expect("Failed to write image to disk");
-
-        // update the image state
-
-        let state_mutex = handle.state::<Mutex<Vec<Ref>>>();
-        let mut state = state_mutex
-            .lock()
-            .map_err(|_| "Failed to acquire lock on state".to_string())?;
+    Ok(new_ref)
+}
 
-        let found_ref = state
-            .iter_mut()
-            .find(|ref_instance| ref_instance.get_id() == ref_id);
+#[tauri::command]
+async fn list_jobs(jobs: State<'_, JobQueue>) -> Result<Vec<Job>, String> {
+    Ok(jobs.list())
+}
 
-        match found_ref.unwrap() {
-            Ref::Link(ref mut link_ref) => {
-                link_ref.snapshoot = convert_file_src(&img_path);
-                Ok(())
-            }
-            _ => Err("Invalid reference type for note content".to_string()),
-        }
-    });
+#[tauri::command]
+async fn cancel_job(job_id: &str, jobs: State<'_, JobQueue>) -> Result<Job, String> {
+    jobs.cancel(job_id)
+}
 
-    Ok(new_ref)
+#[tauri::command]
+async fn retry_job(job_id: &str, jobs: State<'_, JobQueue>) -> Result<Job, String> {
+    jobs.retry(job_id)
 }
 
 #[tauri::command]
@@ -291,41 +837,65 @@ async fn rename_ref(
     ref_id: &str,
     new_name: &str,
     path: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
+    index: State<'_, LibraryIndex>,
 ) -> Result<(), String> {
     let location = Path::new(path);
     let result = utils::change_name(location, new_name);
     result.map_err(|e| e.to_string())?;
 
-    // Now update the state with the new name
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
+    // Now update the index with the new name
+    index
+        .update(ref_id, |found_ref| {
+            found_ref.get_ref_meta().map(|meta| meta.set_name(new_name))
+        })
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))?
+}
 
-    // Find reference by ID
-    let found_ref = state_guard
-        .iter_mut()
-        .find(|ref_instance| ref_instance.get_id() == ref_id);
+#[tauri::command]
+async fn remove_ref(
+    ref_id: &str,
+    index: State<'_, LibraryIndex>,
+    handle: AppHandle,
+) -> Result<(), String> {
+    trash::move_to_trash(
+        &get_collection_path(&handle),
+        &get_trash_path(&handle),
+        ref_id,
+    )
+    .map_err(|e| e.to_string())?;
 
-    // Handle missing reference
-    if found_ref.is_none() {
-        return Err(format!("Reference with ID '{}' not found", ref_id));
-    }
+    index.remove(ref_id).map_err(|e| e.to_string())?;
 
-    let metadata = found_ref.unwrap().get_ref_meta().unwrap();
-    metadata.set_name(new_name);
     Ok(())
 }
 
 #[tauri::command]
-async fn remove_ref(ref_id: &str, state: State<'_, Mutex<Vec<Ref>>>) -> Result<(), String> {
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
+async fn list_trash(handle: AppHandle) -> Result<Vec<TrashEntry>, String> {
+    trash::list(&get_trash_path(&handle)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn restore_ref(
+    ref_id: &str,
+    index: State<'_, LibraryIndex>,
+    handle: AppHandle,
+) -> Result<Ref, String> {
+    let restored = trash::restore(
+        &get_collection_path(&handle),
+        &get_trash_path(&handle),
+        ref_id,
+    )
+    .map_err(|e| e.to_string())?;
 
-    state_guard.retain(|ref_instance| ref_instance.get_id() != ref_id);
+    index.upsert(&restored).map_err(|e| e.to_string())?;
 
-    Ok(())
+    Ok(restored)
+}
+
+#[tauri::command]
+async fn purge_trash(ref_ids: Option<Vec<String>>, handle: AppHandle) -> Result<usize, String> {
+    trash::purge(&get_trash_path(&handle), ref_ids.as_deref()).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -333,26 +903,17 @@ async fn add_tag(
     ref_id: &str,
     path: &str,
     tag: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
+    index: State<'_, LibraryIndex>,
 ) -> Result<(), String> {
     let location = Path::new(path);
-    let _ = utils::add_tag(location, tag);
+    utils::add_tag(location, tag).map_err(|e| e.to_string())?;
 
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
-
-    let found_ref = state_guard
-        .iter_mut()
-        .find(|ref_instance| ref_instance.get_id() == ref_id);
-
-    if found_ref.is_none() {
-        return Err(format!("Reference with ID '{}' not found", ref_id));
-    }
-
-    let metadata = found_ref.unwrap().get_ref_meta().unwrap();
-    metadata.add_tag(tag);
-    Ok(())
+    index
+        .update(ref_id, |found_ref| {
+            found_ref.get_ref_meta().map(|meta| meta.add_tag(tag))
+        })
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))?
 }
 
 #[tauri::command]
@@ -360,55 +921,136 @@ async fn remove_tag(
     ref_id: &str,
     path: &str,
     tag: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
+    index: State<'_, LibraryIndex>,
 ) -> Result<(), String> {
     let location = Path::new(path);
-    let _ = utils::remove_tag(location, tag);
+    utils::remove_tag(location, tag).map_err(|e| e.to_string())?;
 
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
+    index
+        .update(ref_id, |found_ref| {
+            found_ref.get_ref_meta().map(|meta| meta.remove_tag(tag))
+        })
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))?
+}
 
-    let found_ref = state_guard
-        .iter_mut()
-        .find(|ref_instance| ref_instance.get_id() == ref_id);
+#[tauri::command]
+async fn add_marker(
+    ref_id: &str,
+    timestamp: f64,
+    label: &str,
+    note: Option<String>,
+    index: State<'_, LibraryIndex>,
+) -> Result<Timeline, String> {
+    let marker = Marker::new(timestamp, label, note.as_deref().unwrap_or_default());
+    edit_timeline(ref_id, TimelineEdit::AddMarker(marker), &index)
+}
 
-    if found_ref.is_none() {
-        return Err(format!("Reference with ID '{}' not found", ref_id));
-    }
+#[tauri::command]
+async fn update_marker(
+    ref_id: &str,
+    marker: Marker,
+    index: State<'_, LibraryIndex>,
+) -> Result<Timeline, String> {
+    edit_timeline(ref_id, TimelineEdit::UpdateMarker(marker), &index)
+}
 
-    let metadata = found_ref.unwrap().get_ref_meta().unwrap();
-    metadata.remove_tag(tag);
-    Ok(())
+#[tauri::command]
+async fn remove_marker(
+    ref_id: &str,
+    marker_id: String,
+    index: State<'_, LibraryIndex>,
+) -> Result<Timeline, String> {
+    edit_timeline(ref_id, TimelineEdit::RemoveMarker(marker_id), &index)
+}
+
+#[tauri::command]
+async fn add_clip_range(
+    ref_id: &str,
+    name: &str,
+    start: f64,
+    end: f64,
+    note: Option<String>,
+    index: State<'_, LibraryIndex>,
+) -> Result<Timeline, String> {
+    let range = ClipRange::new(name, start, end, note.as_deref().unwrap_or_default());
+    edit_timeline(ref_id, TimelineEdit::AddRange(range), &index)
+}
+
+#[tauri::command]
+async fn update_clip_range(
+    ref_id: &str,
+    range: ClipRange,
+    index: State<'_, LibraryIndex>,
+) -> Result<Timeline, String> {
+    edit_timeline(ref_id, TimelineEdit::UpdateRange(range), &index)
+}
+
+#[tauri::command]
+async fn remove_clip_range(
+    ref_id: &str,
+    range_id: String,
+    index: State<'_, LibraryIndex>,
+) -> Result<Timeline, String> {
+    edit_timeline(ref_id, TimelineEdit::RemoveRange(range_id), &index)
+}
+
+/// Apply an edit to the timeline of a video or audio ref, in its sidecar then in the index
+fn edit_timeline(
+    ref_id: &str,
+    edit: TimelineEdit,
+    index: &LibraryIndex,
+) -> Result<Timeline, String> {
+    let ref_data = index
+        .get(ref_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))?;
+
+    let timeline = utils::edit_timeline(Path::new(ref_data.get_metapath()), &edit)
+        .map_err(|e| e.to_string())?;
+
+    index
+        .update(ref_id, |found_ref| match found_ref {
+            Ref::Video(VideoRef {
+                metadata: Some(ref mut metadata),
+                ..
+            }) => metadata.timeline = timeline.clone(),
+            Ref::Audio(AudioRef {
+                metadata: Some(ref mut metadata),
+                ..
+            }) => metadata.timeline = timeline.clone(),
+            _ => {}
+        })
+        .map_err(|e| e.to_string())?;
+
+    Ok(timeline)
 }
 
 #[tauri::command]
 async fn change_note_content(
     ref_id: &str,
     note_content: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
+    index: State<'_, LibraryIndex>,
     handle: AppHandle,
 ) -> Result<(), String> {
     // Write to file
     let base_path = get_collection_path(&handle).join(ref_id).join("note.text");
-    fs::write(base_path, note_content).expect("Failed to write note content to disk");
-
-    // Now update the state with the new name
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
-
-    let found_ref = state_guard
-        .iter_mut()
-        .find(|ref_instance| ref_instance.get_id() == ref_id);
-
-    match found_ref.unwrap() {
-        Ref::Note(ref mut note_ref) => {
-            note_ref.content = note_content.to_string();
-            Ok(())
-        }
-        _ => Err("Invalid reference type for note content".to_string()),
-    }
+    storage::with_ref_lock(&base_path, || {
+        storage::write_atomic(&base_path, note_content)
+    })
+    .map_err(|e| e.to_string())?;
+
+    // Now update the index with the new content
+    index
+        .update(ref_id, |found_ref| match found_ref {
+            Ref::Note(ref mut note_ref) => {
+                note_ref.content = note_content.to_string();
+                Ok(())
+            }
+            _ => Err("Invalid reference type for note content".to_string()),
+        })
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))?
 }
 
 #[tauri::command]
@@ -416,59 +1058,69 @@ async fn change_note_text(
     ref_id: &str,
     note_text: &str,
     path: &str,
-    state: State<'_, Mutex<Vec<Ref>>>,
+    index: State<'_, LibraryIndex>,
 ) -> Result<(), String> {
     let location = Path::new(path);
-    let _ = mutate_note(location, note_text);
-
-    // Now update the state with the new name
-    let mut state_guard = state
-        .lock()
-        .map_err(|_| "Failed to acquire lock on state".to_string())?;
-
-    let found_ref = state_guard
-        .iter_mut()
-        .find(|ref_instance| ref_instance.get_id() == ref_id);
-
-    if found_ref.is_none() {
-        return Err(format!("Reference with ID '{}' not found", ref_id));
-    }
-
-    let metadata = found_ref.unwrap().get_ref_meta().unwrap();
-    metadata.update_note(note_text);
-    Ok(())
+    mutate_note(location, note_text).map_err(|e| e.to_string())?;
+
+    // Now update the index with the new note
+    index
+        .update(ref_id, |found_ref| {
+            found_ref
+                .get_ref_meta()
+                .map(|meta| meta.update_note(note_text))
+        })
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))?
 }
 
 #[tauri::command]
 fn generate_id(lenght: usize) -> String {
-    let characters = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
-    let mut rng = rand::thread_rng();
-
-    let id: String = (0..lenght)
-        .map(|_| {
-            let idx = rng.gen_range(0..characters.len());
-            characters.chars().nth(idx).unwrap()
-        })
-        .collect();
-
-    id
+    utils::random_id(lenght)
 }
 
 pub fn get_handlers() -> Box<dyn Fn(tauri::Invoke<tauri::Wry>) + Send + Sync> {
     Box::new(tauri::generate_handler![
         generate_id,
-        generate_image_metadata,
-        generate_video_metadata,
-        generate_audio_metadata,
+        capture_video_frame,
         generate_note_metadata,
-        generate_doc_metadata,
         generate_link_metadata,
+        import_files,
+        import_directory,
+        cancel_import,
+        list_jobs,
+        cancel_job,
+        retry_job,
         get_all_refs,
+        get_ref,
+        query_refs,
+        get_all_tags,
+        reindex_library,
+        migrate_library,
+        check_library,
+        repair_library,
+        find_duplicates,
+        find_similar_images,
+        reextract_colors,
+        backfill_exif,
+        search_by_color,
+        find_similar_palettes,
+        sort_by_hue,
+        export_palette,
         get_settings,
         rename_ref,
         remove_ref,
+        list_trash,
+        restore_ref,
+        purge_trash,
         add_tag,
         remove_tag,
+        add_marker,
+        update_marker,
+        remove_marker,
+        add_clip_range,
+        update_clip_range,
+        remove_clip_range,
         change_note_content,
         change_note_text,
     ])
@@ -8,7 +8,18 @@ const DEFAULT_SETTINGS: &str = r#"
     "video_ref_autoplay": false
   },
   "behavior": {
-    "sort_by": "CreationTime"
+    "sort_by": "CreationTime",
+    "trash_retention_days": 30,
+    "thumbnail_format": "webp",
+    "palette": {
+      "color_count": 8,
+      "algorithm": "kmeans",
+      "ignore_white": false,
+      "ignore_black": false,
+      "ignore_transparent": true
+    },
+    "import_keywords_as_tags": false,
+    "import_genres_as_tags": false
   }
 }"#;
 
@@ -26,6 +37,31 @@ pub fn get_collection_path(handle: &AppHandle) -> PathBuf {
     app_data_dir.join("collections")
 }
 
+pub fn get_index_path(handle: &AppHandle) -> PathBuf {
+    let app_data_dir = get_app_data_dir_path(handle);
+    app_data_dir.join("library.sqlite3")
+}
+
+pub fn get_backup_path(handle: &AppHandle) -> PathBuf {
+    let app_data_dir = get_app_data_dir_path(handle);
+    app_data_dir.join("backups")
+}
+
+pub fn get_trash_path(handle: &AppHandle) -> PathBuf {
+    let app_data_dir = get_app_data_dir_path(handle);
+    app_data_dir.join(".trash")
+}
+
+pub fn get_quarantine_path(handle: &AppHandle) -> PathBuf {
+    let app_data_dir = get_app_data_dir_path(handle);
+    app_data_dir.join("quarantine")
+}
+
+pub fn get_jobs_path(handle: &AppHandle) -> PathBuf {
+    let app_data_dir = get_app_data_dir_path(handle);
+    app_data_dir.join("jobs")
+}
+
 pub fn init(handle: &AppHandle) {
     let collection_path = get_collection_path(handle);
     let settings_path = get_settings_path(handle);
@@ -0,0 +1,312 @@
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::index::LibraryIndex;
+use crate::integrity::media_candidates;
+use crate::media;
+use crate::state::Ref;
+use crate::{trash, utils};
+
+/// Default Hamming distance under which two images are considered the same picture
+pub const DEFAULT_SIMILARITY_THRESHOLD: u32 = 10;
+
+/// Images whose perceptual hashes are within the threshold of each other
+#[derive(Serialize, Debug, Clone)]
+pub struct SimilarGroup {
+    pub refs: Vec<Ref>,
+}
+
+/// BLAKE3 hash of a file as a hex string, read in chunks so large videos don't fill memory
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Find a ref other than `ref_id` already holding `content_hash`.
+///
+/// `ref_dir` is the folder `import::create_ref_dir` just made for `ref_id`. When a ref exists
+/// that folder is deleted and the existing ref returned instead. Ids that aren't a plain folder
+/// name, don't match `ref_dir` or already belong to an indexed ref are refused before anything
+/// is deleted.
+pub fn take_existing(
+    index: &LibraryIndex,
+    content_hash: &str,
+    ref_type: &str,
+    ref_id: &str,
+    ref_dir: &Path,
+) -> io::Result<Option<Ref>> {
+    trash::check_ref_id(ref_id)?;
+    if ref_dir.file_name().and_then(|name| name.to_str()) != Some(ref_id) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "'{}' is not the folder of reference '{}'",
+                ref_dir.display(),
+                ref_id
+            ),
+        ));
+    }
+    if index.get(ref_id).map_err(io::Error::other)?.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Reference with ID '{}' already exists", ref_id),
+        ));
+    }
+
+    let existing = index
+        .find_by_hash(content_hash, ref_type, ref_id)
+        .map_err(io::Error::other)?;
+
+    if existing.is_some() && ref_dir.is_dir() {
+        fs::remove_dir_all(ref_dir)?;
+    }
+
+    Ok(existing)
+}
+
+/// Hash the media of every indexed ref that doesn't have a content hash yet.
+///
+/// Returns the amount of refs updated, refs whose media can't be read are skipped.
+pub fn fill_missing_hashes(index: &LibraryIndex) -> io::Result<usize> {
+    let mut filled = 0;
+
+    for ref_data in index.missing_hashes().map_err(io::Error::other)? {
+        let metadata_path = Path::new(ref_data.get_metapath());
+        let Some(media_path) = media_file(&ref_data) else {
+            continue;
+        };
+
+        let result = hash_file(&media_path)
+            .and_then(|content_hash| utils::set_content_hash(metadata_path, &content_hash));
+
+        if let Err(e) = result {
+            eprintln!("Error hashing {}: {}", media_path.display(), e);
+            continue;
+        }
+
+        if let Some(folder) = metadata_path.parent() {
+            let folder = match folder.canonicalize() {
+                Ok(folder) => folder,
+                Err(e) => {
+                    eprintln!("Error resolving {}: {}", folder.display(), e);
+                    continue;
+                }
+            };
+            index.sync_folder(&folder).map_err(io::Error::other)?;
+            filled += 1;
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Compute the perceptual hash of every indexed image that doesn't have one yet
+pub fn fill_missing_perceptual_hashes(index: &LibraryIndex) -> io::Result<usize> {
+    let mut filled = 0;
+
+    for ref_data in index
+        .missing_perceptual_hashes()
+        .map_err(io::Error::other)?
+    {
+        let metadata_path = Path::new(ref_data.get_metapath());
+        let Some(media_path) = media_file(&ref_data) else {
+            continue;
+        };
+
+        let Some(perceptual_hash) = media::perceptual_hash(&media_path) else {
+            eprintln!(
+                "Error hashing {}: not a readable image",
+                media_path.display()
+            );
+            continue;
+        };
+
+        if let Err(e) = utils::set_perceptual_hash(metadata_path, &perceptual_hash) {
+            eprintln!("Error updating {}: {}", metadata_path.display(), e);
+            continue;
+        }
+
+        if let Some(folder) = metadata_path.parent() {
+            let folder = match folder.canonicalize() {
+                Ok(folder) => folder,
+                Err(e) => {
+                    eprintln!("Error resolving {}: {}", folder.display(), e);
+                    continue;
+                }
+            };
+            index.sync_folder(&folder).map_err(io::Error::other)?;
+            filled += 1;
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Cluster images whose perceptual hashes are at most `max_distance` bits apart.
+///
+/// Clusters are transitive, A and C end up together when both are close to B.
+pub fn cluster_similar(hashes: Vec<(String, Ref)>, max_distance: u32) -> Vec<SimilarGroup> {
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+
+    fn find(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    // Parse every hash once instead of once per pair
+    let bits: Vec<Option<u64>> = hashes
+        .iter()
+        .map(|(hash, _)| media::parse_perceptual_hash(hash))
+        .collect();
+
+    for (a, bits_a) in bits.iter().enumerate() {
+        let Some(bits_a) = *bits_a else {
+            continue;
+        };
+        for (b, bits_b) in bits.iter().enumerate().skip(a + 1) {
+            let close = bits_b
+                .is_some_and(|bits_b| media::perceptual_distance(bits_a, bits_b) <= max_distance);
+            if close {
+                let (root_a, root_b) = (find(&mut parents, a), find(&mut parents, b));
+                parents[root_b] = root_a;
+            }
+        }
+    }
+
+    let mut clusters: Vec<(usize, SimilarGroup)> = Vec::new();
+    for (i, (_, ref_data)) in hashes.into_iter().enumerate() {
+        let root = find(&mut parents, i);
+        match clusters
+            .iter_mut()
+            .find(|(cluster_root, _)| *cluster_root == root)
+        {
+            Some((_, group)) => group.refs.push(ref_data),
+            None => clusters.push((
+                root,
+                SimilarGroup {
+                    refs: vec![ref_data],
+                },
+            )),
+        }
+    }
+
+    clusters
+        .into_iter()
+        .map(|(_, group)| group)
+        .filter(|group| group.refs.len() > 1)
+        .collect()
+}
+
+/// Media file a ref was imported from
+pub fn media_file(ref_data: &Ref) -> Option<PathBuf> {
+    let ref_dir = Path::new(ref_data.get_metapath()).parent()?;
+    let file_name = match ref_data {
+        Ref::Image(image_ref) => image_ref.metadata.as_ref()?.file_name.clone(),
+        Ref::Video(video_ref) => video_ref.metadata.as_ref()?.file_name.clone(),
+        Ref::Audio(audio_ref) => audio_ref.metadata.as_ref()?.file_name.clone(),
+        Ref::Doc(_) => media_candidates(&utils::get_ref_entries(ref_dir))
+            .into_iter()
+            .next()?,
+        Ref::Note(_) | Ref::Link(_) => return None,
+    };
+    Some(ref_dir.join(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{ImageMetadata, ImageRef};
+    use crate::storage;
+
+    const IMAGE_PATH: &str = "resources/test_image.png";
+
+    fn add_image_ref(collections_dir: &Path, ref_id: &str) -> PathBuf {
+        let ref_dir = collections_dir.join(ref_id);
+        let media_path = ref_dir.join("test_image.png");
+        fs::create_dir_all(&ref_dir).unwrap();
+        fs::copy(IMAGE_PATH, &media_path).unwrap();
+
+        let metadata =
+            ImageMetadata::new(ref_id.to_string(), "test_image.png", &media_path, "all").unwrap();
+        storage::write_json(&ref_dir.join("metadata.image.json"), &metadata).unwrap();
+        ref_dir
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let collections_dir = Path::new("test_dedup_collections");
+        add_image_ref(collections_dir, "FIRSTIMAGE");
+        add_image_ref(collections_dir, "SECONDIMAGE");
+
+        let index = LibraryIndex::open_in_memory().unwrap();
+        index.sync(collections_dir).unwrap();
+        assert!(index.duplicates().unwrap().is_empty());
+
+        assert_eq!(fill_missing_hashes(&index).unwrap(), 2);
+        assert!(index.missing_hashes().unwrap().is_empty());
+
+        let groups = index.duplicates().unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].refs.len(), 2);
+        assert_eq!(
+            groups[0].content_hash,
+            hash_file(Path::new(IMAGE_PATH)).unwrap()
+        );
+
+        // Importing the same file again hands back an existing ref
+        let ref_dir = add_image_ref(collections_dir, "THIRDIMAGE");
+        let content_hash = hash_file(&ref_dir.join("test_image.png")).unwrap();
+        let existing = take_existing(&index, &content_hash, "image", "THIRDIMAGE", &ref_dir)
+            .unwrap()
+            .unwrap();
+        assert_ne!(existing.get_id(), "THIRDIMAGE");
+        assert!(!ref_dir.exists());
+
+        // Ids that could point at the whole library, outside of it or at an indexed ref
+        let first_dir = collections_dir.join("FIRSTIMAGE");
+        for (ref_id, ref_dir) in [
+            ("", collections_dir.to_path_buf()),
+            ("../FIRSTIMAGE", collections_dir.join("../FIRSTIMAGE")),
+            ("FIRSTIMAGE", first_dir.clone()),
+            ("THIRDIMAGE", first_dir.clone()),
+        ] {
+            assert!(take_existing(&index, &content_hash, "image", ref_id, &ref_dir).is_err());
+        }
+        assert!(first_dir.join("test_image.png").exists());
+
+        fs::remove_dir_all(collections_dir).unwrap();
+    }
+
+    #[test]
+    fn test_cluster_similar() {
+        let image = |id: &str| {
+            Ref::Image(ImageRef {
+                metadata: Some(ImageMetadata {
+                    id: id.to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        };
+
+        let hashes = vec![
+            ("ff00ff00ff00ff00".to_string(), image("A")),
+            ("0f0f0f0f0f0f0f0f".to_string(), image("B")),
+            ("ff00ff00ff00ff01".to_string(), image("C")),
+            ("ff00ff00ff00ff03".to_string(), image("D")),
+        ];
+
+        let groups = cluster_similar(hashes.clone(), 1);
+        assert_eq!(groups.len(), 1);
+        let ids: Vec<&str> = groups[0].refs.iter().map(Ref::get_id).collect();
+        assert_eq!(ids, vec!["A", "C", "D"]);
+
+        assert!(cluster_similar(hashes, 0).is_empty());
+    }
+}
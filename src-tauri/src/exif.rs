@@ -0,0 +1,870 @@
+use chrono::{DateTime, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::{fs, io};
+
+use crate::dedup::media_file;
+use crate::index::LibraryIndex;
+use crate::raw;
+use crate::state::Ref;
+use crate::utils;
+
+const JPEG_EXIF_HEADER: &[u8] = b"Exif\0\0";
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PHOTOSHOP_HEADER: &[u8] = b"Photoshop 3.0\0";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Photoshop resource holding the IPTC records
+const IPTC_RESOURCE: u16 = 0x0404;
+
+/// Entries read from a single IFD, guards against corrupted counts
+const MAX_IFD_ENTRIES: usize = 512;
+
+const TAG_MAKE: u16 = 0x010F;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_ARTIST: u16 = 0x013B;
+const TAG_COPYRIGHT: u16 = 0x8298;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_GPS_IFD: u16 = 0x8825;
+const TAG_EXPOSURE_TIME: u16 = 0x829A;
+const TAG_F_NUMBER: u16 = 0x829D;
+const TAG_ISO: u16 = 0x8827;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const TAG_FOCAL_LENGTH: u16 = 0x920A;
+const TAG_LENS_MODEL: u16 = 0xA434;
+const TAG_GPS_LATITUDE_REF: u16 = 0x0001;
+const TAG_GPS_LATITUDE: u16 = 0x0002;
+const TAG_GPS_LONGITUDE_REF: u16 = 0x0003;
+const TAG_GPS_LONGITUDE: u16 = 0x0004;
+const TAG_GPS_ALTITUDE_REF: u16 = 0x0005;
+const TAG_GPS_ALTITUDE: u16 = 0x0006;
+
+const IPTC_KEYWORDS: (u8, u8) = (2, 25);
+const IPTC_DATE_CREATED: (u8, u8) = (2, 55);
+const IPTC_BY_LINE: (u8, u8) = (2, 80);
+const IPTC_COPYRIGHT: (u8, u8) = (2, 116);
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Meters above sea level
+    pub altitude: Option<f64>,
+}
+
+/// Camera and authoring details embedded in an image
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ExifData {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    /// `YYYY-MM-DDTHH:MM:SS`, followed by the UTC offset when the camera recorded it
+    pub captured_at: Option<String>,
+    /// In seconds, as a fraction for short exposures (`1/250`)
+    pub exposure_time: Option<String>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    /// In millimeters
+    pub focal_length: Option<f64>,
+    pub orientation: Option<u16>,
+    pub gps: Option<GpsPosition>,
+    pub author: Option<String>,
+    pub copyright: Option<String>,
+    /// IPTC and XMP keywords
+    pub keywords: Vec<String>,
+}
+
+impl ExifData {
+    /// Capture date in unix milliseconds, dates without an offset are taken as UTC
+    pub fn captured_timestamp(&self) -> Option<i64> {
+        let captured_at = self.captured_at.as_deref()?;
+        DateTime::parse_from_rfc3339(captured_at)
+            .map(|date| date.timestamp_millis())
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(captured_at, "%Y-%m-%dT%H:%M:%S")
+                    .map(|date| date.and_utc().timestamp_millis())
+            })
+            .ok()
+    }
+}
+
+/// Metadata blocks found in an image file
+enum Block<'a> {
+    Tiff(&'a [u8]),
+    Iptc(&'a [u8]),
+    Xmp(&'a [u8]),
+}
+
+/// Read the EXIF, IPTC and XMP metadata of a JPEG, PNG, WebP or TIFF file.
+///
+/// Files without any metadata give an empty `ExifData`.
+pub fn read_metadata(file_path: &Path) -> io::Result<ExifData> {
+    let bytes = fs::read(file_path)?;
+    let mut data = ExifData::default();
+
+    // EXIF comes first so it takes precedence over the IPTC and XMP fields
+    let mut blocks = embedded_blocks(&bytes);
+    blocks.sort_by_key(|block| !matches!(block, Block::Tiff(_)));
+
+    for block in blocks {
+        match block {
+            Block::Tiff(tiff) => read_tiff(tiff, &mut data),
+            Block::Iptc(iim) => read_iptc(iim, &mut data),
+            Block::Xmp(xml) => read_xmp(&String::from_utf8_lossy(xml), &mut data),
+        }
+    }
+
+    let mut keywords: Vec<String> = Vec::new();
+    for keyword in data.keywords.drain(..) {
+        let keyword = keyword.trim().to_string();
+        if !keyword.is_empty() && !keywords.contains(&keyword) {
+            keywords.push(keyword);
+        }
+    }
+    data.keywords = keywords;
+
+    Ok(data)
+}
+
+/// Read the metadata of image refs and store it in their sidecar.
+///
+/// Keywords are added to the tags when `import_keywords` is set. Returns the amount of refs
+/// updated, refs whose media can't be read are skipped.
+pub fn fill_exif(index: &LibraryIndex, refs: Vec<Ref>, import_keywords: bool) -> io::Result<usize> {
+    let mut filled = 0;
+
+    for ref_data in refs {
+        if !matches!(ref_data, Ref::Image(_)) {
+            continue;
+        }
+
+        let metadata_path = Path::new(ref_data.get_metapath());
+        let Some(media_path) = media_file(&ref_data) else {
+            continue;
+        };
+
+        let result = read_metadata(&media_path)
+            .and_then(|exif| utils::set_exif(metadata_path, &exif, import_keywords));
+
+        if let Err(e) = result {
+            eprintln!("Error reading metadata of {}: {}", media_path.display(), e);
+            continue;
+        }
+
+        if let Some(folder) = metadata_path.parent() {
+            let folder = match folder.canonicalize() {
+                Ok(folder) => folder,
+                Err(e) => {
+                    eprintln!("Error resolving {}: {}", folder.display(), e);
+                    continue;
+                }
+            };
+            index.sync_folder(&folder).map_err(io::Error::other)?;
+            filled += 1;
+        }
+    }
+
+    Ok(filled)
+}
+
+fn embedded_blocks(bytes: &[u8]) -> Vec<Block<'_>> {
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        jpeg_blocks(bytes)
+    } else if bytes.starts_with(PNG_SIGNATURE) {
+        png_blocks(bytes)
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        webp_blocks(bytes)
+    } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        vec![Block::Tiff(bytes)]
+    } else if bytes.starts_with(raw::RAF_SIGNATURE) {
+        // Fujifilm RAW keeps its metadata in the embedded preview
+        raw::embedded_preview(bytes).map_or_else(Vec::new, jpeg_blocks)
+    } else {
+        Vec::new()
+    }
+}
+
+fn jpeg_blocks(bytes: &[u8]) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut pos = 2;
+
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+        let marker = bytes[pos + 1];
+        match marker {
+            // Fill byte
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Markers without a payload
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            // Image data starts, no metadata after this point
+            0xDA | 0xD9 => break,
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let Some(segment) = bytes.get(pos + 4..pos + 2 + length) else {
+            break;
+        };
+
+        match marker {
+            0xE1 if segment.starts_with(JPEG_EXIF_HEADER) => {
+                blocks.push(Block::Tiff(&segment[JPEG_EXIF_HEADER.len()..]))
+            }
+            0xE1 if segment.starts_with(JPEG_XMP_HEADER) => {
+                blocks.push(Block::Xmp(&segment[JPEG_XMP_HEADER.len()..]))
+            }
+            0xED if segment.starts_with(PHOTOSHOP_HEADER) => {
+                if let Some(iim) =
+                    photoshop_resource(&segment[PHOTOSHOP_HEADER.len()..], IPTC_RESOURCE)
+                {
+                    blocks.push(Block::Iptc(iim));
+                }
+            }
+            _ => {}
+        }
+
+        pos += 2 + length;
+    }
+
+    blocks
+}
+
+fn png_blocks(bytes: &[u8]) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut pos = PNG_SIGNATURE.len();
+
+    while let Some(header) = bytes.get(pos..pos + 8) {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let Some(chunk) = bytes.get(pos + 8..pos + 8 + length) else {
+            break;
+        };
+
+        match &header[4..] {
+            b"eXIf" => blocks.push(Block::Tiff(chunk)),
+            // Keyword, compression flag and method, language and translated keyword, then the text
+            b"iTXt" if chunk.starts_with(PNG_XMP_KEYWORD) => {
+                let text = chunk
+                    .get(PNG_XMP_KEYWORD.len() + 1..)
+                    .filter(|rest| rest.first() == Some(&0))
+                    .and_then(|rest| rest.get(2..))
+                    .and_then(|rest| skip_null_terminated(rest, 2));
+                if let Some(text) = text {
+                    blocks.push(Block::Xmp(text));
+                }
+            }
+            b"IDAT" | b"IEND" => break,
+            _ => {}
+        }
+
+        // Length, type, data and CRC
+        pos += 12 + length;
+    }
+
+    blocks
+}
+
+fn webp_blocks(bytes: &[u8]) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut pos = 12;
+
+    while let Some(header) = bytes.get(pos..pos + 8) {
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let Some(chunk) = bytes.get(pos + 8..pos + 8 + length) else {
+            break;
+        };
+
+        match &header[..4] {
+            b"EXIF" => blocks.push(Block::Tiff(
+                chunk.strip_prefix(JPEG_EXIF_HEADER).unwrap_or(chunk),
+            )),
+            b"XMP " => blocks.push(Block::Xmp(chunk)),
+            _ => {}
+        }
+
+        // Chunks are padded to an even size
+        pos += 8 + length + length % 2;
+    }
+
+    blocks
+}
+
+/// Data after `count` null terminated strings
+fn skip_null_terminated(bytes: &[u8], count: usize) -> Option<&[u8]> {
+    (0..count).try_fold(bytes, |rest, _| {
+        let end = rest.iter().position(|&byte| byte == 0)?;
+        rest.get(end + 1..)
+    })
+}
+
+/// Find a resource in a Photoshop image resource block
+fn photoshop_resource(bytes: &[u8], id: u16) -> Option<&[u8]> {
+    let mut pos = 0;
+
+    while bytes.get(pos..pos + 4) == Some(b"8BIM") {
+        let resource_id = u16::from_be_bytes([*bytes.get(pos + 4)?, *bytes.get(pos + 5)?]);
+
+        // Pascal string name padded to an even size
+        let name_length = *bytes.get(pos + 6)? as usize;
+        let name_size = (name_length + 1 + 1) & !1;
+        let size_pos = pos + 6 + name_size;
+        let size = u32::from_be_bytes(bytes.get(size_pos..size_pos + 4)?.try_into().ok()?) as usize;
+        let data = bytes.get(size_pos + 4..size_pos + 4 + size)?;
+
+        if resource_id == id {
+            return Some(data);
+        }
+
+        pos = size_pos + 4 + size + size % 2;
+    }
+
+    None
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct IfdEntry {
+    pub tag: u16,
+    kind: u16,
+    count: usize,
+    /// Position of the value in the TIFF data
+    offset: usize,
+}
+
+/// Reader of the TIFF structure shared by EXIF blocks and camera RAW files
+pub(crate) struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let tiff = Self {
+            data,
+            little_endian,
+        };
+        (tiff.u16(2)? == 42).then_some(tiff)
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// Offset of the first IFD
+    pub fn first_ifd(&self) -> Option<usize> {
+        self.u32(4).map(|offset| offset as usize)
+    }
+
+    /// Offset of the IFD chained after the one at `offset`
+    pub fn next_ifd(&self, offset: usize) -> Option<usize> {
+        let count = self.u16(offset)? as usize;
+        let next = self.u32(offset + 2 + count * 12)? as usize;
+        (next != 0).then_some(next)
+    }
+
+    pub fn ifd(&self, offset: usize) -> Vec<IfdEntry> {
+        let Some(count) = self.u16(offset) else {
+            return Vec::new();
+        };
+
+        (0..(count as usize).min(MAX_IFD_ENTRIES))
+            .filter_map(|i| {
+                let entry = offset + 2 + i * 12;
+                let tag = self.u16(entry)?;
+                let kind = self.u16(entry + 2)?;
+                let count = self.u32(entry + 4)? as usize;
+                let size = type_size(kind)?.checked_mul(count)?;
+                // Values of up to 4 bytes are stored in the entry itself
+                let offset = if size <= 4 {
+                    entry + 8
+                } else {
+                    self.u32(entry + 8)? as usize
+                };
+                Some(IfdEntry {
+                    tag,
+                    kind,
+                    count,
+                    offset,
+                })
+            })
+            .collect()
+    }
+
+    fn ascii(&self, entry: &IfdEntry) -> Option<String> {
+        let bytes = self.data.get(entry.offset..entry.offset + entry.count)?;
+        let text = String::from_utf8_lossy(bytes)
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_string();
+        (!text.is_empty()).then_some(text)
+    }
+
+    pub fn uint(&self, entry: &IfdEntry) -> Option<u32> {
+        self.uints(entry).first().copied()
+    }
+
+    /// Every value of an integer entry
+    pub fn uints(&self, entry: &IfdEntry) -> Vec<u32> {
+        (0..entry.count)
+            .map_while(|i| match entry.kind {
+                1 | 7 => self.data.get(entry.offset + i).map(|&byte| byte as u32),
+                3 => self.u16(entry.offset + i * 2).map(u32::from),
+                4 | 13 => self.u32(entry.offset + i * 4),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn rational(&self, entry: &IfdEntry, index: usize) -> Option<(u32, u32)> {
+        if !matches!(entry.kind, 5 | 10) || index >= entry.count {
+            return None;
+        }
+        let offset = entry.offset + index * 8;
+        Some((self.u32(offset)?, self.u32(offset + 4)?))
+    }
+
+    fn float(&self, entry: &IfdEntry, index: usize) -> Option<f64> {
+        let (numerator, denominator) = self.rational(entry, index)?;
+        if denominator == 0 {
+            return None;
+        }
+        Some(if entry.kind == 10 {
+            numerator as i32 as f64 / denominator as i32 as f64
+        } else {
+            numerator as f64 / denominator as f64
+        })
+    }
+}
+
+fn type_size(kind: u16) -> Option<usize> {
+    match kind {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+fn read_tiff(bytes: &[u8], data: &mut ExifData) {
+    let Some(tiff) = Tiff::new(bytes) else {
+        return;
+    };
+    let Some(first_ifd) = tiff.u32(4) else {
+        return;
+    };
+
+    let mut modified_at = None;
+    let mut exif_ifd = None;
+    let mut gps_ifd = None;
+
+    for entry in tiff.ifd(first_ifd as usize) {
+        match entry.tag {
+            TAG_MAKE => data.camera_make = tiff.ascii(&entry),
+            TAG_MODEL => data.camera_model = tiff.ascii(&entry),
+            TAG_ORIENTATION => data.orientation = tiff.uint(&entry).map(|value| value as u16),
+            TAG_DATE_TIME => modified_at = tiff.ascii(&entry),
+            TAG_ARTIST => data.author = tiff.ascii(&entry),
+            TAG_COPYRIGHT => data.copyright = tiff.ascii(&entry),
+            TAG_EXIF_IFD => exif_ifd = tiff.uint(&entry),
+            TAG_GPS_IFD => gps_ifd = tiff.uint(&entry),
+            _ => {}
+        }
+    }
+
+    let mut captured_at = None;
+    let mut offset = None;
+
+    for entry in exif_ifd.map_or_else(Vec::new, |ifd| tiff.ifd(ifd as usize)) {
+        match entry.tag {
+            TAG_DATE_TIME_ORIGINAL => captured_at = tiff.ascii(&entry),
+            TAG_OFFSET_TIME_ORIGINAL => offset = tiff.ascii(&entry),
+            TAG_EXPOSURE_TIME => {
+                data.exposure_time = tiff
+                    .rational(&entry, 0)
+                    .and_then(|(numerator, denominator)| exposure_time(numerator, denominator))
+            }
+            TAG_F_NUMBER => data.f_number = tiff.float(&entry, 0),
+            TAG_ISO => data.iso = tiff.uint(&entry),
+            TAG_FOCAL_LENGTH => data.focal_length = tiff.float(&entry, 0),
+            TAG_LENS_MODEL => data.lens = tiff.ascii(&entry),
+            _ => {}
+        }
+    }
+
+    data.captured_at = captured_at
+        .or(modified_at)
+        .and_then(|date| exif_date(&date, offset.as_deref()));
+
+    if let Some(ifd) = gps_ifd {
+        data.gps = read_gps(&tiff, ifd as usize);
+    }
+}
+
+fn read_gps(tiff: &Tiff, ifd: usize) -> Option<GpsPosition> {
+    let entries = tiff.ifd(ifd);
+    let find = |tag: u16| entries.iter().find(|entry| entry.tag == tag);
+
+    // Degrees, minutes and seconds, negated for the southern and western hemispheres
+    let coordinate = |value_tag: u16, ref_tag: u16, negative: &str| -> Option<f64> {
+        let entry = find(value_tag)?;
+        let degrees = tiff.float(entry, 0)?;
+        let minutes = tiff.float(entry, 1).unwrap_or(0.0);
+        let seconds = tiff.float(entry, 2).unwrap_or(0.0);
+        let value = degrees + minutes / 60.0 + seconds / 3600.0;
+
+        let hemisphere = find(ref_tag).and_then(|entry| tiff.ascii(entry));
+        Some(if hemisphere.as_deref() == Some(negative) {
+            -value
+        } else {
+            value
+        })
+    };
+
+    let altitude = find(TAG_GPS_ALTITUDE)
+        .and_then(|entry| tiff.float(entry, 0))
+        .map(|altitude| {
+            let below_sea_level =
+                find(TAG_GPS_ALTITUDE_REF).and_then(|entry| tiff.uint(entry)) == Some(1);
+            if below_sea_level {
+                -altitude
+            } else {
+                altitude
+            }
+        });
+
+    Some(GpsPosition {
+        latitude: coordinate(TAG_GPS_LATITUDE, TAG_GPS_LATITUDE_REF, "S")?,
+        longitude: coordinate(TAG_GPS_LONGITUDE, TAG_GPS_LONGITUDE_REF, "W")?,
+        altitude,
+    })
+}
+
+fn exposure_time(numerator: u32, denominator: u32) -> Option<String> {
+    if numerator == 0 || denominator == 0 {
+        return None;
+    }
+    Some(if numerator >= denominator {
+        format!("{}", numerator as f64 / denominator as f64)
+    } else {
+        format!("1/{}", (denominator as f64 / numerator as f64).round())
+    })
+}
+
+/// Turn an EXIF `YYYY:MM:DD HH:MM:SS` date into `YYYY-MM-DDTHH:MM:SS[+HH:MM]`
+fn exif_date(date: &str, offset: Option<&str>) -> Option<String> {
+    let date = NaiveDateTime::parse_from_str(date.trim(), "%Y:%m:%d %H:%M:%S").ok()?;
+    let date = date.format("%Y-%m-%dT%H:%M:%S").to_string();
+
+    match offset {
+        Some(offset) if DateTime::parse_from_rfc3339(&format!("{}{}", date, offset)).is_ok() => {
+            Some(format!("{}{}", date, offset))
+        }
+        _ => Some(date),
+    }
+}
+
+fn read_iptc(bytes: &[u8], data: &mut ExifData) {
+    let mut pos = 0;
+
+    while let Some(header) = bytes.get(pos..pos + 5) {
+        if header[0] != 0x1C {
+            break;
+        }
+        let dataset = (header[1], header[2]);
+        let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+        // Extended datasets hold binary data, nothing we read
+        if length & 0x8000 != 0 {
+            break;
+        }
+        let Some(value) = bytes.get(pos + 5..pos + 5 + length) else {
+            break;
+        };
+        let value = String::from_utf8_lossy(value).trim().to_string();
+
+        match dataset {
+            IPTC_KEYWORDS => data.keywords.push(value),
+            IPTC_BY_LINE if data.author.is_none() => data.author = Some(value),
+            IPTC_COPYRIGHT if data.copyright.is_none() => data.copyright = Some(value),
+            IPTC_DATE_CREATED if data.captured_at.is_none() => {
+                data.captured_at =
+                    NaiveDateTime::parse_from_str(&format!("{}000000", value), "%Y%m%d%H%M%S")
+                        .ok()
+                        .map(|date| date.format("%Y-%m-%dT%H:%M:%S").to_string());
+            }
+            _ => {}
+        }
+
+        pos += 5 + length;
+    }
+}
+
+fn read_xmp(xml: &str, data: &mut ExifData) {
+    data.keywords.extend(xmp_list(xml, "dc:subject"));
+
+    if data.author.is_none() {
+        data.author = xmp_list(xml, "dc:creator").into_iter().next();
+    }
+    if data.copyright.is_none() {
+        data.copyright = xmp_list(xml, "dc:rights").into_iter().next();
+    }
+}
+
+/// Items of an XMP `rdf:Bag`, `rdf:Seq` or `rdf:Alt` property
+fn xmp_list(xml: &str, property: &str) -> Vec<String> {
+    let open = format!("<{}>", property);
+    let close = format!("</{}>", property);
+
+    let Some(start) = xml.find(&open) else {
+        return Vec::new();
+    };
+    let Some(end) = xml[start..].find(&close) else {
+        return Vec::new();
+    };
+
+    let mut items = Vec::new();
+    let mut rest = &xml[start + open.len()..start + end];
+
+    while let Some(item_start) = rest.find("<rdf:li") {
+        rest = &rest[item_start..];
+        let (Some(content_start), Some(content_end)) = (rest.find('>'), rest.find("</rdf:li>"))
+        else {
+            break;
+        };
+        if content_start < content_end {
+            let value = unescape_xml(rest[content_start + 1..content_end].trim());
+            if !value.is_empty() {
+                items.push(value);
+            }
+        }
+        rest = &rest[content_end + "</rdf:li>".len()..];
+    }
+
+    items
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_ifd(tiff: &mut Vec<u8>, entries: &[(u16, u16, u32, [u8; 4])]) {
+        tiff.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        for (tag, kind, count, value) in entries {
+            tiff.extend_from_slice(&tag.to_be_bytes());
+            tiff.extend_from_slice(&kind.to_be_bytes());
+            tiff.extend_from_slice(&count.to_be_bytes());
+            tiff.extend_from_slice(value);
+        }
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+    }
+
+    /// Big endian TIFF with camera, exposure and GPS tags
+    fn tiff_block() -> Vec<u8> {
+        let mut tiff = Vec::new();
+        let offset = |value: usize| (value as u32).to_be_bytes();
+
+        // Layout: header, IFD0 (5 entries), EXIF IFD (3 entries), GPS IFD (4 entries), values
+        let ifd0 = 8;
+        let exif_ifd = ifd0 + 2 + 5 * 12 + 4;
+        let gps_ifd = exif_ifd + 2 + 3 * 12 + 4;
+        let values = gps_ifd + 2 + 4 * 12 + 4;
+
+        let model = b"Camera X100\0";
+        let date = b"2023:07:14 18:30:05\0";
+        let latitude = values + model.len() + date.len();
+        let longitude = latitude + 24;
+        let exposure = longitude + 24;
+
+        tiff.extend_from_slice(b"MM\0*");
+        tiff.extend_from_slice(&offset(ifd0));
+        push_ifd(
+            &mut tiff,
+            &[
+                (TAG_MAKE, 2, 4, *b"Fuj\0"),
+                (TAG_MODEL, 2, model.len() as u32, offset(values)),
+                (TAG_ORIENTATION, 3, 1, [0, 6, 0, 0]),
+                (TAG_EXIF_IFD, 4, 1, offset(exif_ifd)),
+                (TAG_GPS_IFD, 4, 1, offset(gps_ifd)),
+            ],
+        );
+        push_ifd(
+            &mut tiff,
+            &[
+                (
+                    TAG_DATE_TIME_ORIGINAL,
+                    2,
+                    date.len() as u32,
+                    offset(values + model.len()),
+                ),
+                (TAG_EXPOSURE_TIME, 5, 1, offset(exposure)),
+                (TAG_ISO, 3, 1, [0x01, 0x90, 0, 0]),
+            ],
+        );
+        push_ifd(
+            &mut tiff,
+            &[
+                (TAG_GPS_LATITUDE_REF, 2, 2, *b"S\0\0\0"),
+                (TAG_GPS_LATITUDE, 5, 3, offset(latitude)),
+                (TAG_GPS_LONGITUDE_REF, 2, 2, *b"E\0\0\0"),
+                (TAG_GPS_LONGITUDE, 5, 3, offset(longitude)),
+            ],
+        );
+        tiff.extend_from_slice(model);
+        tiff.extend_from_slice(date);
+        for rational in [
+            (33, 1),
+            (30, 1),
+            (0, 1),
+            (151, 1),
+            (12, 1),
+            (36, 1),
+            (1, 250),
+        ] {
+            tiff.extend_from_slice(&(rational.0 as u32).to_be_bytes());
+            tiff.extend_from_slice(&(rational.1 as u32).to_be_bytes());
+        }
+
+        tiff
+    }
+
+    fn iptc_block() -> Vec<u8> {
+        let mut iim = Vec::new();
+        for (dataset, value) in [(25, "travel"), (25, "sydney"), (80, "Jane Doe")] {
+            iim.extend_from_slice(&[0x1C, 2, dataset]);
+            iim.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            iim.extend_from_slice(value.as_bytes());
+        }
+
+        let mut resource = PHOTOSHOP_HEADER.to_vec();
+        resource.extend_from_slice(b"8BIM");
+        resource.extend_from_slice(&IPTC_RESOURCE.to_be_bytes());
+        resource.extend_from_slice(&[0, 0]);
+        resource.extend_from_slice(&(iim.len() as u32).to_be_bytes());
+        resource.extend_from_slice(&iim);
+        resource
+    }
+
+    fn xmp_block() -> Vec<u8> {
+        let mut xmp = JPEG_XMP_HEADER.to_vec();
+        xmp.extend_from_slice(
+            br#"<x:xmpmeta><rdf:RDF><rdf:Description>
+                <dc:subject><rdf:Bag><rdf:li>sydney</rdf:li><rdf:li>Opera &amp; harbour</rdf:li></rdf:Bag></dc:subject>
+                <dc:rights><rdf:Alt><rdf:li xml:lang="x-default">CC BY 4.0</rdf:li></rdf:Alt></dc:rights>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        );
+        xmp
+    }
+
+    fn jpeg_with_segments(segments: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut image = Vec::new();
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(
+                &mut io::Cursor::new(&mut image),
+                image::ImageOutputFormat::Jpeg(80),
+            )
+            .unwrap();
+
+        let mut jpeg = image[..2].to_vec();
+        for (marker, payload) in segments {
+            jpeg.extend_from_slice(&[0xFF, *marker]);
+            jpeg.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+            jpeg.extend_from_slice(payload);
+        }
+        jpeg.extend_from_slice(&image[2..]);
+        jpeg
+    }
+
+    #[test]
+    fn test_read_jpeg_metadata() {
+        let base_path = Path::new("test_exif_jpeg");
+        fs::create_dir_all(base_path).unwrap();
+
+        let mut exif = JPEG_EXIF_HEADER.to_vec();
+        exif.extend_from_slice(&tiff_block());
+        let jpeg = jpeg_with_segments(&[(0xE1, exif), (0xED, iptc_block()), (0xE1, xmp_block())]);
+        let image_path = base_path.join("photo.jpg");
+        fs::write(&image_path, jpeg).unwrap();
+
+        // The file is still a valid image
+        assert!(image::open(&image_path).is_ok());
+
+        let data = read_metadata(&image_path).unwrap();
+        assert_eq!(data.camera_make.as_deref(), Some("Fuj"));
+        assert_eq!(data.camera_model.as_deref(), Some("Camera X100"));
+        assert_eq!(data.orientation, Some(6));
+        assert_eq!(data.iso, Some(400));
+        assert_eq!(data.exposure_time.as_deref(), Some("1/250"));
+        assert_eq!(data.captured_at.as_deref(), Some("2023-07-14T18:30:05"));
+        assert_eq!(data.author.as_deref(), Some("Jane Doe"));
+        assert_eq!(data.copyright.as_deref(), Some("CC BY 4.0"));
+        assert_eq!(data.keywords, vec!["travel", "sydney", "Opera & harbour"]);
+
+        let gps = data.gps.unwrap();
+        assert!((gps.latitude + 33.5).abs() < 1e-9);
+        assert!((gps.longitude - 151.21).abs() < 1e-9);
+
+        fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[test]
+    fn test_read_metadata_without_exif() {
+        let data = read_metadata(Path::new("resources/test_image.png")).unwrap();
+        assert_eq!(data.camera_model, None);
+        assert_eq!(data.captured_at, None);
+        assert!(data.keywords.is_empty());
+        assert!(read_metadata(Path::new("resources/missing.png")).is_err());
+    }
+
+    #[test]
+    fn test_captured_timestamp() {
+        let data = ExifData {
+            captured_at: exif_date("2023:07:14 18:30:05", Some("+02:00")),
+            ..Default::default()
+        };
+        assert_eq!(
+            data.captured_at.as_deref(),
+            Some("2023-07-14T18:30:05+02:00")
+        );
+        assert_eq!(data.captured_timestamp(), Some(1689352205000));
+
+        let data = ExifData {
+            captured_at: exif_date("2023:07:14 16:30:05", None),
+            ..Default::default()
+        };
+        assert_eq!(data.captured_timestamp(), Some(1689352205000));
+    }
+}
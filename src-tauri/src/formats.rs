@@ -0,0 +1,810 @@
+use image::{DynamicImage, ImageBuffer, Rgba};
+use resvg::{tiny_skia, usvg};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use crate::bmff::{boxes, find_box, read_u16, read_u32, read_u64};
+use crate::raw;
+
+/// Photoshop files have no registered mime type
+pub const PSD_MEDIA_TYPE: &str = "image/vnd.adobe.photoshop";
+
+/// `ftyp` brands of HEIF based images, AVIF included
+const HEIF_BRANDS: [&[u8; 4]; 10] = [
+    b"avif", b"avis", b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+];
+
+/// JPEG XL codestream and container signatures
+const JXL_CODESTREAM: &[u8] = &[0xff, 0x0a];
+const JXL_CONTAINER: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+
+/// Longest side SVG files are rasterized at
+const SVG_RASTER_SIZE: f32 = 2048.0;
+
+/// System fonts for SVG text, loaded on the first rasterized file
+static SVG_FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+
+/// Image formats `image` cannot decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtraFormat {
+    /// HEIC and AVIF photos
+    Heif,
+    JpegXl,
+    Svg,
+    Psd,
+    /// Camera RAW, read through its embedded preview
+    Raw,
+}
+
+impl ExtraFormat {
+    /// Detect the format from the leading bytes of a file, SVG and RAW also from its extension
+    pub fn detect(file_path: &Path, data: &[u8]) -> Option<ExtraFormat> {
+        // Most RAW formats are TIFF files `image` would decode as their tiny first IFD
+        if raw::is_raw(file_path) || data.starts_with(raw::RAF_SIGNATURE) {
+            return Some(ExtraFormat::Raw);
+        }
+        if data.starts_with(b"8BPS") {
+            return Some(ExtraFormat::Psd);
+        }
+        if data.get(4..8) == Some(b"ftyp")
+            && HEIF_BRANDS
+                .iter()
+                .any(|brand| data[8..].starts_with(*brand))
+        {
+            return Some(ExtraFormat::Heif);
+        }
+        if data.starts_with(JXL_CODESTREAM) || data.starts_with(JXL_CONTAINER) {
+            return Some(ExtraFormat::JpegXl);
+        }
+
+        let is_svg_file = file_path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"));
+        let head = String::from_utf8_lossy(&data[..data.len().min(1024)]);
+        if is_svg_file || head.contains("<svg") {
+            return Some(ExtraFormat::Svg);
+        }
+
+        None
+    }
+
+    /// Whether this build can decode the format, HEIF and JPEG XL decoders are optional features
+    pub fn is_decodable(self) -> bool {
+        let (heif, jxl) = (cfg!(feature = "heif"), cfg!(feature = "jxl"));
+        match self {
+            ExtraFormat::Heif => heif,
+            ExtraFormat::JpegXl => jxl,
+            _ => true,
+        }
+    }
+}
+
+/// Dimensions of an image in one of the extra formats, rotation included
+pub fn dimensions(format: ExtraFormat, data: &[u8]) -> io::Result<(u32, u32)> {
+    let dimensions = match format {
+        ExtraFormat::Heif => heif_dimensions(data),
+        ExtraFormat::JpegXl => jxl_dimensions(data),
+        ExtraFormat::Svg => svg_dimensions(&String::from_utf8_lossy(data)),
+        ExtraFormat::Psd => PsdHeader::parse(data).map(|header| (header.width, header.height)),
+        ExtraFormat::Raw => return raw::preview_dimensions(data),
+    };
+
+    dimensions.ok_or_else(|| invalid_data("Failed to read the image dimensions"))
+}
+
+/// Decode an image in one of the extra formats.
+///
+/// Photoshop files are rasterized from the composite image they embed, RAW files from their
+/// preview. Formats whose decoder isn't part of the build fail as `Unsupported`.
+pub fn decode(format: ExtraFormat, data: &[u8]) -> io::Result<DynamicImage> {
+    match format {
+        ExtraFormat::Heif => decode_heif(data),
+        ExtraFormat::JpegXl => decode_jxl(data),
+        ExtraFormat::Svg => rasterize_svg(data),
+        ExtraFormat::Psd => decode_psd(data),
+        ExtraFormat::Raw => raw::decode_preview(data),
+    }
+}
+
+/// Render an SVG with its longest side at `SVG_RASTER_SIZE`
+fn rasterize_svg(data: &[u8]) -> io::Result<DynamicImage> {
+    let fontdb = SVG_FONTS.get_or_init(|| {
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_system_fonts();
+        Arc::new(fontdb)
+    });
+    let options = usvg::Options {
+        fontdb: fontdb.clone(),
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_data(data, &options).map_err(|e| invalid_data(&e.to_string()))?;
+
+    let size = tree.size();
+    let scale = SVG_RASTER_SIZE / size.width().max(size.height());
+    let width = (size.width() * scale).round().max(1.0) as u32;
+    let height = (size.height() * scale).round().max(1.0) as u32;
+
+    let mut pixmap =
+        tiny_skia::Pixmap::new(width, height).ok_or_else(|| invalid_data("Invalid SVG size"))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    let pixels: Vec<u8> = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    let image = ImageBuffer::from_raw(width, height, pixels)
+        .ok_or_else(|| invalid_data("Invalid SVG size"))?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+/// Decode the primary image of a HEIC or AVIF file, rotation and cropping applied
+#[cfg(feature = "heif")]
+fn decode_heif(data: &[u8]) -> io::Result<DynamicImage> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(data).map_err(io::Error::other)?;
+    let handle = context.primary_image_handle().map_err(io::Error::other)?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(io::Error::other)?;
+
+    let planes = decoded.planes();
+    let plane = planes
+        .interleaved
+        .ok_or_else(|| invalid_data("Missing HEIF image plane"))?;
+    let row_length = plane.width as usize * 4;
+    let pixels: Vec<u8> = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .map(|row| row.get(..row_length))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid_data("Truncated HEIF image"))?
+        .concat();
+    let image = ImageBuffer::from_raw(plane.width, plane.height, pixels)
+        .ok_or_else(|| invalid_data("Truncated HEIF image"))?;
+
+    let profile = handle.color_profile_raw().map(|profile| profile.data);
+    Ok(crate::icc::convert_to_srgb(
+        DynamicImage::ImageRgba8(image),
+        profile.as_deref(),
+    ))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(_data: &[u8]) -> io::Result<DynamicImage> {
+    Err(unsupported(ExtraFormat::Heif))
+}
+
+/// Decode the first frame of a JPEG XL image, orientation applied
+#[cfg(feature = "jxl")]
+fn decode_jxl(data: &[u8]) -> io::Result<DynamicImage> {
+    let jxl_image = jxl_oxide::JxlImage::builder()
+        .read(data)
+        .map_err(io::Error::other)?;
+    let render = jxl_image.render_frame(0).map_err(io::Error::other)?;
+    let frame = render.image_all_channels();
+
+    let (width, height) = (frame.width() as u32, frame.height() as u32);
+    let pixels: Vec<u8> = frame
+        .buf()
+        .iter()
+        .map(|sample| (sample.clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect();
+    let image = match frame.channels() {
+        1 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        2 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        3 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        4 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+        _ => None,
+    };
+    image.ok_or_else(|| invalid_data("Unsupported JPEG XL channel layout"))
+}
+
+#[cfg(not(feature = "jxl"))]
+fn decode_jxl(_data: &[u8]) -> io::Result<DynamicImage> {
+    Err(unsupported(ExtraFormat::JpegXl))
+}
+
+/// Size of the root element, from its width and height or else its view box
+fn svg_dimensions(svg: &str) -> Option<(u32, u32)> {
+    let start = svg.find("<svg")?;
+    let tag = &svg[start..start + svg[start..].find('>')?];
+
+    let attribute = |name: &str| -> Option<&str> {
+        let position = tag.match_indices(name).find_map(|(i, _)| {
+            let preceded_by_space = tag[..i].ends_with(char::is_whitespace);
+            let rest = tag[i + name.len()..].trim_start().strip_prefix('=')?;
+            preceded_by_space.then_some(rest)
+        })?;
+        let rest = position.trim_start();
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        rest[1..].split(quote).next()
+    };
+    // Absolute lengths only, percentages depend on the page showing the file
+    let length = |value: &str| -> Option<f32> {
+        let number = value.trim().trim_end_matches("px");
+        number.parse::<f32>().ok().filter(|length| *length > 0.0)
+    };
+
+    let size = attribute("width")
+        .and_then(length)
+        .zip(attribute("height").and_then(length));
+    let size = size.or_else(|| {
+        let view_box: Vec<f32> = attribute("viewBox")?
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(|part| part.parse().ok())
+            .collect::<Option<_>>()?;
+        match view_box[..] {
+            [_, _, width, height] if width > 0.0 && height > 0.0 => Some((width, height)),
+            _ => None,
+        }
+    })?;
+
+    Some((size.0.round() as u32, size.1.round() as u32))
+}
+
+/// Size of the primary item of a HEIF file, from its `ispe` and `irot` properties
+fn heif_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let meta = find_box(data, b"meta")?.get(4..)?;
+    let iprp = find_box(meta, b"iprp")?;
+    let properties: Vec<(&[u8; 4], &[u8])> = boxes(find_box(iprp, b"ipco")?).collect();
+
+    let primary = find_box(meta, b"pitm").and_then(|pitm| match pitm.first()? {
+        0 => read_u16(pitm, 4).map(u32::from),
+        _ => read_u32(pitm, 4),
+    });
+    let associated = find_box(iprp, b"ipma")
+        .zip(primary)
+        .and_then(|(ipma, primary)| item_properties(ipma, primary))
+        .unwrap_or_default();
+
+    let spatial_extent = |(kind, body): &(&[u8; 4], &[u8])| -> Option<(u32, u32)> {
+        (*kind == b"ispe").then_some(())?;
+        Some((read_u32(body, 4)?, read_u32(body, 8)?))
+    };
+
+    // Files without associations fall back to the largest extent, previews are smaller
+    let size = associated
+        .iter()
+        .filter_map(|&index| properties.get(index.checked_sub(1)?))
+        .find_map(spatial_extent)
+        .or_else(|| {
+            properties
+                .iter()
+                .filter_map(spatial_extent)
+                .max_by_key(|(width, height)| *width as u64 * *height as u64)
+        })?;
+
+    let quarter_turns = associated
+        .iter()
+        .filter_map(|&index| properties.get(index.checked_sub(1)?))
+        .find(|(kind, _)| *kind == b"irot")
+        .and_then(|(_, body)| body.first())
+        .map_or(0, |angle| angle & 0b11);
+
+    if quarter_turns % 2 == 1 {
+        Some((size.1, size.0))
+    } else {
+        Some(size)
+    }
+}
+
+/// 1 based indices of the properties associated with an item in an `ipma` box
+fn item_properties(ipma: &[u8], item_id: u32) -> Option<Vec<usize>> {
+    let version = *ipma.first()?;
+    let large_indices = ipma.get(3)? & 1 == 1;
+    let entry_count = read_u32(ipma, 4)?;
+
+    let mut offset = 8;
+    for _ in 0..entry_count {
+        let id = if version < 1 {
+            offset += 2;
+            u32::from(read_u16(ipma, offset - 2)?)
+        } else {
+            offset += 4;
+            read_u32(ipma, offset - 4)?
+        };
+        let count = *ipma.get(offset)? as usize;
+        offset += 1;
+
+        let mut indices = Vec::with_capacity(count);
+        for _ in 0..count {
+            let index = if large_indices {
+                offset += 2;
+                (read_u16(ipma, offset - 2)? & 0x7fff) as usize
+            } else {
+                offset += 1;
+                (ipma.get(offset - 1)? & 0x7f) as usize
+            };
+            indices.push(index);
+        }
+
+        if id == item_id {
+            return Some(indices);
+        }
+    }
+
+    None
+}
+
+/// Size of a JPEG XL image from the `SizeHeader` of its codestream
+fn jxl_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let codestream = if data.starts_with(JXL_CONTAINER) {
+        boxes(data).find_map(|(kind, body)| match kind {
+            b"jxlc" => Some(body),
+            // Partial codestreams start with their index
+            b"jxlp" => body.get(4..),
+            _ => None,
+        })?
+    } else {
+        data
+    };
+
+    let mut bits = BitReader::new(codestream.strip_prefix(JXL_CODESTREAM)?);
+    let dimension = |bits: &mut BitReader| -> Option<u32> {
+        let size = match bits.read(2)? {
+            0 => bits.read(9)?,
+            1 => bits.read(13)?,
+            2 => bits.read(18)?,
+            _ => bits.read(30)?,
+        };
+        Some(size + 1)
+    };
+
+    let small = bits.read(1)? == 1;
+    let height = if small {
+        (bits.read(5)? + 1) * 8
+    } else {
+        dimension(&mut bits)?
+    };
+    let ratio = bits.read(3)?;
+    let width = match ratio {
+        0 if small => (bits.read(5)? + 1) * 8,
+        0 => dimension(&mut bits)?,
+        _ => {
+            let (numerator, denominator) =
+                [(1, 1), (12, 10), (4, 3), (3, 2), (16, 9), (5, 4), (2, 1)][ratio as usize - 1];
+            (height as u64 * numerator / denominator) as u32
+        }
+    };
+
+    // Image metadata follows, its extra fields hold the orientation
+    let all_default = bits.read(1)? == 1;
+    let orientation = if !all_default && bits.read(1)? == 1 {
+        bits.read(3)? + 1
+    } else {
+        1
+    };
+
+    if orientation > 4 {
+        Some((height, width))
+    } else {
+        Some((width, height))
+    }
+}
+
+/// Least significant bit first reader of the JPEG XL codestream
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, position: 0 }
+    }
+
+    fn read(&mut self, count: usize) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.data.get(self.position / 8)?;
+            let bit = (byte >> (self.position % 8)) & 1;
+            value |= (bit as u32) << i;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+struct PsdHeader {
+    /// PSB, the large document format, uses wider lengths
+    large: bool,
+    channels: usize,
+    height: u32,
+    width: u32,
+    depth: u16,
+    mode: u16,
+}
+
+impl PsdHeader {
+    fn parse(data: &[u8]) -> Option<PsdHeader> {
+        if !data.starts_with(b"8BPS") {
+            return None;
+        }
+        Some(PsdHeader {
+            large: read_u16(data, 4)? == 2,
+            channels: read_u16(data, 12)? as usize,
+            height: read_u32(data, 14)?,
+            width: read_u32(data, 18)?,
+            depth: read_u16(data, 22)?,
+            mode: read_u16(data, 24)?,
+        })
+    }
+}
+
+/// Decode the composite image Photoshop stores after the layers
+fn decode_psd(data: &[u8]) -> io::Result<DynamicImage> {
+    let header = PsdHeader::parse(data).ok_or_else(|| invalid_data("Invalid Photoshop file"))?;
+    let color_channels = match header.mode {
+        1 => 1,
+        3 => 3,
+        4 => 4,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Only grayscale, RGB and CMYK Photoshop files are supported",
+            ))
+        }
+    };
+    if !matches!(header.depth, 8 | 16) || header.channels < color_channels {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unsupported Photoshop channel layout",
+        ));
+    }
+
+    // Skip the color mode data, the image resources and the layers
+    let truncated = || invalid_data("Truncated Photoshop file");
+    let mut offset = 26;
+    for large_length in [false, false, header.large] {
+        let length = if large_length {
+            read_u64(data, offset).map(|length| (length, 8))
+        } else {
+            read_u32(data, offset).map(|length| (length as u64, 4))
+        };
+        let (length, size) = length.ok_or_else(truncated)?;
+        offset += size + usize::try_from(length).map_err(|_| truncated())?;
+    }
+
+    let compression = read_u16(data, offset).ok_or_else(truncated)?;
+    offset += 2;
+
+    // Alpha is the first extra channel of RGB and grayscale documents
+    let has_alpha = header.mode != 4 && header.channels > color_channels;
+    let channel_count = color_channels + has_alpha as usize;
+    let (width, height) = (header.width as usize, header.height as usize);
+    let row_bytes = width * header.depth as usize / 8;
+
+    let channels: Vec<Vec<u8>> = match compression {
+        0 => (0..channel_count)
+            .map(|channel| {
+                let start = offset + channel * height * row_bytes;
+                data.get(start..start + height * row_bytes)
+                    .map(|plane| plane.to_vec())
+                    .ok_or_else(truncated)
+            })
+            .collect::<io::Result<_>>()?,
+        1 => {
+            // Byte counts of every row of every channel come first
+            let count_size = if header.large { 4 } else { 2 };
+            let row_count = header.channels * height;
+            let counts: Vec<usize> = (0..row_count)
+                .map(|row| {
+                    let position = offset + row * count_size;
+                    match header.large {
+                        true => read_u32(data, position).map(|count| count as usize),
+                        false => read_u16(data, position).map(|count| count as usize),
+                    }
+                })
+                .collect::<Option<_>>()
+                .ok_or_else(truncated)?;
+
+            let mut position = offset + row_count * count_size;
+            let mut channels = Vec::with_capacity(channel_count);
+            for channel in 0..channel_count {
+                let mut plane = Vec::with_capacity(height * row_bytes);
+                for count in &counts[channel * height..(channel + 1) * height] {
+                    let row = data.get(position..position + count).ok_or_else(truncated)?;
+                    unpack_bits(row, row_bytes, &mut plane).ok_or_else(truncated)?;
+                    position += count;
+                }
+                channels.push(plane);
+            }
+            channels
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Zip compressed Photoshop composites are not supported",
+            ))
+        }
+    };
+
+    // 16 bit samples are big endian, their high byte is enough for previews
+    let sample = |channel: usize, pixel: usize| -> u8 {
+        match header.depth {
+            16 => channels[channel][pixel * 2],
+            _ => channels[channel][pixel],
+        }
+    };
+
+    let image = ImageBuffer::from_fn(header.width, header.height, |x, y| {
+        let pixel = y as usize * width + x as usize;
+        let alpha = if has_alpha {
+            sample(color_channels, pixel)
+        } else {
+            u8::MAX
+        };
+        match header.mode {
+            1 => {
+                let gray = sample(0, pixel);
+                Rgba([gray, gray, gray, alpha])
+            }
+            // Photoshop stores CMYK inverted, 255 is no ink
+            4 => {
+                let black = sample(3, pixel) as u16;
+                let [r, g, b] =
+                    [0, 1, 2].map(|channel| (sample(channel, pixel) as u16 * black / 255) as u8);
+                Rgba([r, g, b, alpha])
+            }
+            _ => Rgba([sample(0, pixel), sample(1, pixel), sample(2, pixel), alpha]),
+        }
+    });
+
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+/// Decode a PackBits row of `length` bytes
+fn unpack_bits(mut data: &[u8], length: usize, output: &mut Vec<u8>) -> Option<()> {
+    let end = output.len() + length;
+
+    while output.len() < end {
+        let header = *data.first()? as i8;
+        data = &data[1..];
+
+        match header {
+            0..=127 => {
+                let count = header as usize + 1;
+                output.extend_from_slice(data.get(..count)?);
+                data = &data[count..];
+            }
+            -127..=-1 => {
+                let value = *data.first()?;
+                output.extend(std::iter::repeat_n(value, (1 - header as isize) as usize));
+                data = &data[1..];
+            }
+            // No operation
+            -128 => {}
+        }
+    }
+
+    output.truncate(end);
+    Some(())
+}
+
+fn unsupported(format: ExtraFormat) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("This build has no decoder for {:?} images", format),
+    )
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn iso_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn spatial_extent(width: u32, height: u32) -> Vec<u8> {
+        let mut body = vec![0; 4];
+        body.extend_from_slice(&width.to_be_bytes());
+        body.extend_from_slice(&height.to_be_bytes());
+        iso_box(b"ispe", &body)
+    }
+
+    /// LSB first bits of a JPEG XL codestream after its signature
+    fn jxl(fields: &[(u32, usize)]) -> Vec<u8> {
+        let mut data = JXL_CODESTREAM.to_vec();
+        let mut bits = Vec::new();
+        for (value, count) in fields {
+            bits.extend((0..*count).map(|i| (value >> i) & 1 == 1));
+        }
+        for chunk in bits.chunks(8) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, bit)| byte | (*bit as u8) << i);
+            data.push(byte);
+        }
+        data
+    }
+
+    #[test]
+    fn test_detect() {
+        let path = Path::new("image.bin");
+        assert_eq!(
+            ExtraFormat::detect(path, b"8BPS\0\x01"),
+            Some(ExtraFormat::Psd)
+        );
+        assert_eq!(
+            ExtraFormat::detect(path, b"\0\0\0\x18ftypheic\0\0\0\0"),
+            Some(ExtraFormat::Heif)
+        );
+        assert_eq!(
+            ExtraFormat::detect(path, b"\0\0\0\x18ftypavif\0\0\0\0"),
+            Some(ExtraFormat::Heif)
+        );
+        assert_eq!(
+            ExtraFormat::detect(path, b"\0\0\0\x18ftypisom\0\0\0\0"),
+            None
+        );
+        assert_eq!(
+            ExtraFormat::detect(path, JXL_CONTAINER),
+            Some(ExtraFormat::JpegXl)
+        );
+        assert_eq!(
+            ExtraFormat::detect(path, b"<?xml version=\"1.0\"?>\n<svg>"),
+            Some(ExtraFormat::Svg)
+        );
+        assert_eq!(
+            ExtraFormat::detect(Path::new("logo.SVG"), b""),
+            Some(ExtraFormat::Svg)
+        );
+        assert_eq!(ExtraFormat::detect(path, b"\x89PNG\r\n"), None);
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="120px" height='80'>"#;
+        assert_eq!(
+            dimensions(ExtraFormat::Svg, svg.as_bytes()).unwrap(),
+            (120, 80)
+        );
+        let svg = r#"<svg stroke-width="2" viewBox="0 0 24.4 12"></svg>"#;
+        assert_eq!(
+            dimensions(ExtraFormat::Svg, svg.as_bytes()).unwrap(),
+            (24, 12)
+        );
+        assert!(dimensions(ExtraFormat::Svg, b"<svg width=\"100%\">").is_err());
+
+        // Primary item 1 is the 4032x3024 image rotated a quarter turn, item 2 its preview
+        let mut ipco = spatial_extent(320, 240);
+        ipco.extend(spatial_extent(4032, 3024));
+        ipco.extend(iso_box(b"irot", &[1]));
+        let mut ipma = vec![0, 0, 0, 0, 0, 0, 0, 2];
+        ipma.extend_from_slice(&[0, 1, 2, 0x82, 3]);
+        ipma.extend_from_slice(&[0, 2, 1, 0x81]);
+        let mut iprp = iso_box(b"ipco", &ipco);
+        iprp.extend(iso_box(b"ipma", &ipma));
+        let mut meta = vec![0; 4];
+        meta.extend(iso_box(b"pitm", &[0, 0, 0, 0, 0, 1]));
+        meta.extend(iso_box(b"iprp", &iprp));
+        let mut heif = iso_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+        heif.extend(iso_box(b"meta", &meta));
+        assert_eq!(dimensions(ExtraFormat::Heif, &heif).unwrap(), (3024, 4032));
+
+        // Small 64x32 image, then 1000x750 from a 4:3 ratio
+        let small = jxl(&[(1, 1), (3, 5), (0, 3), (7, 5), (1, 1)]);
+        assert_eq!(dimensions(ExtraFormat::JpegXl, &small).unwrap(), (64, 32));
+        let ratio = jxl(&[(0, 1), (1, 2), (749, 13), (3, 3), (1, 1)]);
+        assert_eq!(
+            dimensions(ExtraFormat::JpegXl, &ratio).unwrap(),
+            (1000, 750)
+        );
+        let mut container = JXL_CONTAINER.to_vec();
+        container.extend(iso_box(b"jxlc", &ratio));
+        assert_eq!(
+            dimensions(ExtraFormat::JpegXl, &container).unwrap(),
+            (1000, 750)
+        );
+    }
+
+    #[test]
+    fn test_decode_psd() {
+        let psd = |compression: u16, planes: &[Vec<u8>]| -> Vec<u8> {
+            let mut data = b"8BPS\0\x01\0\0\0\0\0\0".to_vec();
+            data.extend_from_slice(&(planes.len() as u16).to_be_bytes());
+            data.extend_from_slice(&2u32.to_be_bytes());
+            data.extend_from_slice(&3u32.to_be_bytes());
+            data.extend_from_slice(&[0, 8, 0, 3]);
+            // Empty color mode data, image resources and layers
+            data.extend_from_slice(&[0; 12]);
+            data.extend_from_slice(&compression.to_be_bytes());
+            for plane in planes {
+                data.extend_from_slice(plane);
+            }
+            data
+        };
+
+        // 3x2 RGB, red then blue rows, raw
+        let raw = psd(
+            0,
+            &[
+                vec![255, 255, 255, 0, 0, 0],
+                vec![0; 6],
+                vec![0, 0, 0, 255, 255, 255],
+            ],
+        );
+        let image = decode(ExtraFormat::Psd, &raw).unwrap().into_rgba8();
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.get_pixel(2, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(0, 1), &Rgba([0, 0, 255, 255]));
+
+        // Same image with PackBits rows and a transparent alpha channel
+        let mut counts = Vec::new();
+        for _ in 0..8 {
+            counts.extend_from_slice(&2u16.to_be_bytes());
+        }
+        let rows = [
+            [0xfe, 255],
+            [0xfe, 0],
+            [0xfe, 0],
+            [0xfe, 0],
+            [0xfe, 0],
+            [0xfe, 255],
+            [0xfe, 0],
+            [0xfe, 0],
+        ];
+        let mut rle = counts;
+        rle.extend(rows.iter().flatten());
+        let image = decode(
+            ExtraFormat::Psd,
+            &psd(1, &[rle[..].to_vec(), vec![], vec![], vec![]]),
+        )
+        .unwrap()
+        .into_rgba8();
+        assert_eq!(image.get_pixel(1, 0), &Rgba([255, 0, 0, 0]));
+        assert_eq!(image.get_pixel(1, 1), &Rgba([0, 0, 255, 0]));
+
+        assert!(decode(ExtraFormat::Psd, &raw[..40]).is_err());
+        if !ExtraFormat::Heif.is_decodable() {
+            let heif_error = decode(ExtraFormat::Heif, b"").unwrap_err();
+            assert_eq!(heif_error.kind(), io::ErrorKind::Unsupported);
+        }
+    }
+
+    #[test]
+    fn test_rasterize_svg() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20">
+            <rect width="20" height="20" fill="#F00"/><rect x="20" width="20" height="20" fill="blue" fill-opacity="0.5"/></svg>"##;
+
+        let image = decode(ExtraFormat::Svg, svg.as_bytes())
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(image.dimensions(), (2048, 1024));
+        assert_eq!(image.get_pixel(10, 10), &Rgba([255, 0, 0, 255]));
+        let translucent = image.get_pixel(1500, 500);
+        assert_eq!((translucent[2], translucent[3]), (255, 128));
+
+        // Palettes come from the rendered pixels
+        let path = Path::new("test_rasterize_svg.svg");
+        fs::write(path, svg).unwrap();
+        let options = crate::colors::PaletteOptions::default();
+        let colors = crate::media::extract_colors(path, &options).unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(colors.iter().any(|color| color.hex == "#ff0000"));
+
+        assert!(decode(ExtraFormat::Svg, b"<svg").is_err());
+    }
+}
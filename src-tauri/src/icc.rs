@@ -0,0 +1,323 @@
+use image::{DynamicImage, RgbaImage};
+
+/// XYZ (D50) to linear sRGB, the inverse of the sRGB colorants adapted to D50
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133856, -1.616867, -0.490615],
+    [-0.978768, 1.916141, 0.033454],
+    [0.071945, -0.228991, 1.405243],
+];
+
+/// sRGB colorants adapted to D50 as stored in sRGB profiles, one column per channel
+const SRGB_COLORANTS: [[f32; 3]; 3] = [
+    [0.4361, 0.3851, 0.1431],
+    [0.2225, 0.7169, 0.0606],
+    [0.0139, 0.0971, 0.7141],
+];
+
+/// Precision of the linear to sRGB lookup table
+const ENCODE_STEPS: usize = 4096;
+
+/// Tone response curve of a channel
+#[derive(Debug, Clone, PartialEq)]
+enum Curve {
+    Gamma(f32),
+    Table(Vec<f32>),
+    /// ICC parametric curve, `[g, a, b, c, d, e, f]`
+    Parametric([f32; 7]),
+}
+
+impl Curve {
+    /// Linear value of an encoded value, both in 0..=1
+    fn linearize(&self, value: f32) -> f32 {
+        match self {
+            Curve::Gamma(gamma) => value.powf(*gamma),
+            Curve::Table(table) => {
+                let position = value * (table.len() - 1) as f32;
+                let low = position.floor() as usize;
+                let high = (low + 1).min(table.len() - 1);
+                let t = position - low as f32;
+                table[low] * (1.0 - t) + table[high] * t
+            }
+            Curve::Parametric([g, a, b, c, d, e, f]) => {
+                if value >= *d {
+                    (a * value + b).max(0.0).powf(*g) + e
+                } else {
+                    c * value + f
+                }
+            }
+        }
+    }
+}
+
+/// RGB matrix/TRC profile, the kind embedded by cameras, phones and editors.
+///
+/// Lookup table based profiles (CMYK, print) are not supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Colorants in XYZ (D50), one column per channel
+    colorants: [[f32; 3]; 3],
+    curves: [Curve; 3],
+}
+
+impl Profile {
+    /// Parse an ICC profile, `None` when it is not an RGB matrix/TRC profile
+    pub fn parse(data: &[u8]) -> Option<Profile> {
+        if data.len() < 132 || &data[16..20] != b"RGB " || &data[20..24] != b"XYZ " {
+            return None;
+        }
+
+        let tag = |signature: &[u8; 4]| -> Option<&[u8]> {
+            let count = read_u32(data, 128)? as usize;
+            (0..count).find_map(|i| {
+                let entry = 132 + i * 12;
+                if data.get(entry..entry + 4)? != signature {
+                    return None;
+                }
+                let offset = read_u32(data, entry + 4)? as usize;
+                let size = read_u32(data, entry + 8)? as usize;
+                data.get(offset..offset.checked_add(size)?)
+            })
+        };
+
+        let mut colorants = [[0.0; 3]; 3];
+        for (channel, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+            let xyz = tag(signature)?;
+            if xyz.get(..4)? != b"XYZ " {
+                return None;
+            }
+            for (row, colorant) in colorants.iter_mut().enumerate() {
+                colorant[channel] = read_s15_fixed16(xyz, 8 + row * 4)?;
+            }
+        }
+
+        let curves = [
+            parse_curve(tag(b"rTRC")?)?,
+            parse_curve(tag(b"gTRC")?)?,
+            parse_curve(tag(b"bTRC")?)?,
+        ];
+
+        Some(Profile { colorants, curves })
+    }
+
+    /// Whether the profile describes sRGB, images using it need no conversion
+    pub fn is_srgb(&self) -> bool {
+        let same_colorants = self
+            .colorants
+            .iter()
+            .flatten()
+            .zip(SRGB_COLORANTS.iter().flatten())
+            .all(|(a, b)| (a - b).abs() < 0.002);
+
+        same_colorants
+            && self.curves.iter().all(|curve| {
+                [0.02, 0.2, 0.5, 0.8]
+                    .into_iter()
+                    .all(|value| (curve.linearize(value) - srgb_to_linear(value)).abs() < 0.005)
+            })
+    }
+
+    /// Convert an image to sRGB, keeping its alpha channel
+    pub fn to_srgb(&self, image: &DynamicImage) -> DynamicImage {
+        let matrix = multiply(&XYZ_TO_SRGB, &self.colorants);
+        let linear: Vec<[f32; 256]> = self
+            .curves
+            .iter()
+            .map(|curve| std::array::from_fn(|i| curve.linearize(i as f32 / 255.0)))
+            .collect();
+        let encode: Vec<u8> = (0..=ENCODE_STEPS)
+            .map(|i| {
+                let value = linear_to_srgb(i as f32 / ENCODE_STEPS as f32);
+                (value * 255.0).round() as u8
+            })
+            .collect();
+
+        let mut rgba: RgbaImage = image.to_rgba8();
+        for pixel in rgba.pixels_mut() {
+            let source = [
+                linear[0][pixel[0] as usize],
+                linear[1][pixel[1] as usize],
+                linear[2][pixel[2] as usize],
+            ];
+            for (channel, row) in matrix.iter().enumerate() {
+                let value = row[0] * source[0] + row[1] * source[1] + row[2] * source[2];
+                pixel[channel] = encode[(value.clamp(0.0, 1.0) * ENCODE_STEPS as f32) as usize];
+            }
+        }
+
+        if image.color().has_alpha() {
+            DynamicImage::ImageRgba8(rgba)
+        } else {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).into_rgb8())
+        }
+    }
+}
+
+/// Convert an image to sRGB when it embeds a supported, non sRGB profile
+pub fn convert_to_srgb(image: DynamicImage, profile: Option<&[u8]>) -> DynamicImage {
+    match profile.and_then(Profile::parse) {
+        Some(profile) if !profile.is_srgb() => profile.to_srgb(&image),
+        _ => image,
+    }
+}
+
+fn parse_curve(data: &[u8]) -> Option<Curve> {
+    match data.get(..4)? {
+        b"curv" => {
+            let count = read_u32(data, 8)? as usize;
+            match count {
+                0 => Some(Curve::Gamma(1.0)),
+                1 => Some(Curve::Gamma(read_u16(data, 12)? as f32 / 256.0)),
+                _ => (0..count)
+                    .map(|i| read_u16(data, 12 + i * 2).map(|value| value as f32 / 65535.0))
+                    .collect::<Option<Vec<f32>>>()
+                    .map(Curve::Table),
+            }
+        }
+        b"para" => {
+            let parameter_count = match read_u16(data, 8)? {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return None,
+            };
+            let mut parameters = [0.0; 7];
+            for (i, parameter) in parameters.iter_mut().take(parameter_count).enumerate() {
+                *parameter = read_s15_fixed16(data, 12 + i * 4)?;
+            }
+
+            // Express every function type as type 4: Y = (aX + b)^g + e if X >= d, else cX + f
+            let [g, a, b, c, d, _, _] = parameters;
+            let parameters = match parameter_count {
+                1 => [g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                3 => [g, a, b, 0.0, -b / a, 0.0, 0.0],
+                4 => [g, a, b, 0.0, -b / a, c, c],
+                5 => [g, a, b, c, d, 0.0, 0.0],
+                _ => parameters,
+            };
+            Some(Curve::Parametric(parameters))
+        }
+        _ => None,
+    }
+}
+
+fn multiply(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    std::array::from_fn(|row| {
+        std::array::from_fn(|col| (0..3).map(|k| a[row][k] * b[k][col]).sum())
+    })
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_s15_fixed16(data: &[u8], offset: usize) -> Option<f32> {
+    Some(read_u32(data, offset)? as i32 as f32 / 65536.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    /// Minimal RGB profile with the given colorants and a gamma curve for every channel
+    fn profile(colorants: [[f32; 3]; 3], gamma: f32) -> Vec<u8> {
+        let fixed = |value: f32| ((value * 65536.0).round() as i32).to_be_bytes();
+
+        let mut xyz_tags = Vec::new();
+        for channel in 0..3 {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            for colorant in colorants {
+                tag.extend_from_slice(&fixed(colorant[channel]));
+            }
+            xyz_tags.push(tag);
+        }
+        let mut curve = b"curv\0\0\0\0".to_vec();
+        curve.extend_from_slice(&1u32.to_be_bytes());
+        curve.extend_from_slice(&((gamma * 256.0) as u16).to_be_bytes());
+        curve.extend_from_slice(&[0, 0]);
+
+        let tags: Vec<(&[u8; 4], &[u8])> = vec![
+            (b"rXYZ", &xyz_tags[0]),
+            (b"gXYZ", &xyz_tags[1]),
+            (b"bXYZ", &xyz_tags[2]),
+            (b"rTRC", &curve),
+            (b"gTRC", &curve),
+            (b"bTRC", &curve),
+        ];
+
+        let mut data = vec![0u8; 128];
+        data[16..20].copy_from_slice(b"RGB ");
+        data[20..24].copy_from_slice(b"XYZ ");
+        data.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+
+        let mut offset = 132 + tags.len() * 12;
+        let mut body = Vec::new();
+        for (signature, tag) in &tags {
+            data.extend_from_slice(*signature);
+            data.extend_from_slice(&((offset + body.len()) as u32).to_be_bytes());
+            data.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            body.extend_from_slice(tag);
+        }
+        offset += body.len();
+        data.extend_from_slice(&body);
+        data[..4].copy_from_slice(&(offset as u32).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let gamma = Profile::parse(&profile(SRGB_COLORANTS, 2.5)).unwrap();
+        assert_eq!(gamma.curves[0], Curve::Gamma(2.5));
+        // sRGB colorants alone do not make an sRGB profile
+        assert!(!gamma.is_srgb());
+
+        assert!(Profile::parse(b"not a profile").is_none());
+        let mut cmyk = profile(SRGB_COLORANTS, 2.5);
+        cmyk[16..20].copy_from_slice(b"CMYK");
+        assert!(Profile::parse(&cmyk).is_none());
+    }
+
+    #[test]
+    fn test_convert_to_srgb() {
+        let image =
+            DynamicImage::ImageRgb8(image::ImageBuffer::from_pixel(2, 2, Rgb([128, 0, 255])));
+
+        // Linear sRGB: only the curve differs, mid gray gets brighter once encoded
+        let linear = profile(SRGB_COLORANTS, 1.0);
+        let converted = convert_to_srgb(image.clone(), Some(&linear)).into_rgb8();
+        let pixel = converted.get_pixel(0, 0);
+        assert!((186..=190).contains(&pixel[0]));
+        assert_eq!(pixel[1], 0);
+        assert!(pixel[2] >= 254);
+
+        // Images without a usable profile are left untouched
+        assert_eq!(convert_to_srgb(image.clone(), None), image);
+        assert_eq!(convert_to_srgb(image.clone(), Some(b"garbage")), image);
+    }
+}
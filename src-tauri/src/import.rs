@@ -0,0 +1,500 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{fs, io, thread};
+use walkdir::WalkDir;
+
+use crate::dedup;
+use crate::media;
+use crate::state::Ref;
+use crate::utils;
+
+/// Length of the generated ref folder names
+const REF_ID_LENGTH: usize = 13;
+
+/// Kind of ref a file becomes once imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefKind {
+    Image,
+    Video,
+    Audio,
+    Doc,
+}
+
+impl RefKind {
+    /// Deduce the ref kind from the mime type of a file name, anything unknown is a doc
+    pub fn detect(file_name: &str) -> Self {
+        let media_type = media::determine_media_type(file_name);
+
+        if media_type.starts_with("image/") {
+            RefKind::Image
+        } else if media_type.starts_with("video/") {
+            RefKind::Video
+        } else if media_type.starts_with("audio/") {
+            RefKind::Audio
+        } else {
+            RefKind::Doc
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// Outcome of an import, every path ends up in exactly one of the lists
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct ImportReport {
+    pub imported: Vec<Ref>,
+    pub failed: Vec<ImportFailure>,
+}
+
+/// How the subfolders of an imported directory are kept on the refs
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubfolderMapping {
+    #[default]
+    Ignore,
+    /// The closest subfolder name becomes the collection
+    Collection,
+    /// Every subfolder name becomes a tag
+    Tags,
+}
+
+/// Filters and behaviour of `import_directory`, every field is optional
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct DirectoryImportOptions {
+    /// Glob patterns matched against the path relative to the directory, empty means everything
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Allowed extensions without the dot, empty means every extension
+    pub extensions: Vec<String>,
+    /// File size limits in bytes
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub subfolders: SubfolderMapping,
+    pub move_files: bool,
+    /// Amount of files ingested in parallel, defaults to the available cores
+    pub workers: Option<usize>,
+}
+
+/// A file found by `scan_directory`, with the collection and tags it will get
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportCandidate {
+    pub path: PathBuf,
+    pub collection: String,
+    pub tags: Vec<String>,
+}
+
+/// Counters emitted while a directory import runs
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct ImportProgress {
+    pub job_id: String,
+    pub seen: usize,
+    pub imported: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub finished: bool,
+    pub cancelled: bool,
+}
+
+/// Final state of a directory import along with the files that failed
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportResult {
+    #[serde(flatten)]
+    pub progress: ImportProgress,
+    pub failures: Vec<ImportFailure>,
+}
+
+/// Cancellation flags of the directory imports currently running
+#[derive(Default)]
+pub struct ImportJobs {
+    jobs: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl ImportJobs {
+    pub fn start(&self, job_id: &str) -> Arc<AtomicBool> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.insert(job_id.to_string(), cancel.clone());
+        cancel
+    }
+
+    /// Ask a running import to stop, returns `false` when no such job is running
+    pub fn cancel(&self, job_id: &str) -> bool {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        match jobs.get(job_id) {
+            Some(cancel) => {
+                cancel.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn finish(&self, job_id: &str) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.remove(job_id);
+    }
+}
+
+/// A file copied into a fresh ref folder, waiting for its metadata
+#[derive(Debug, Clone)]
+pub struct StagedFile {
+    pub ref_id: String,
+    pub ref_dir: PathBuf,
+    pub file_name: String,
+    pub kind: RefKind,
+}
+
+/// Create a new ref folder in `collections_dir` and copy `source` into it
+pub fn stage_file(collections_dir: &Path, source: &Path) -> io::Result<StagedFile> {
+    if !source.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a file", source.display()),
+        ));
+    }
+
+    let file_name = source
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?;
+
+    let (ref_id, ref_dir) = create_ref_dir(collections_dir)?;
+
+    if let Err(e) = fs::copy(source, ref_dir.join(&file_name)) {
+        let _ = fs::remove_dir_all(&ref_dir);
+        return Err(e);
+    }
+
+    Ok(StagedFile {
+        ref_id,
+        ref_dir,
+        kind: RefKind::detect(&file_name),
+        file_name,
+    })
+}
+
+/// Create the folder of a new ref under an unused id, returns the id and the folder
+pub fn create_ref_dir(collections_dir: &Path) -> io::Result<(String, PathBuf)> {
+    fs::create_dir_all(collections_dir)?;
+    loop {
+        let ref_id = utils::random_id(REF_ID_LENGTH);
+        let ref_dir = collections_dir.join(&ref_id);
+        match fs::create_dir(&ref_dir) {
+            Ok(()) => return Ok((ref_id, ref_dir)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Drop the folder of a file whose import failed
+pub fn discard(staged: &StagedFile) {
+    if staged.ref_dir.exists() {
+        if let Err(e) = fs::remove_dir_all(&staged.ref_dir) {
+            eprintln!("Error removing {}: {}", staged.ref_dir.display(), e);
+        }
+    }
+}
+
+/// Walk `root` recursively and keep the files matching `options`.
+///
+/// Returns the candidates along with the amount of files that were filtered out.
+/// Hidden files and folders are never imported.
+pub fn scan_directory(
+    root: &Path,
+    collection: &str,
+    options: &DirectoryImportOptions,
+) -> io::Result<(Vec<ImportCandidate>, usize)> {
+    let include = build_globs(&options.include)?;
+    let exclude = build_globs(&options.exclude)?;
+    let extensions: Vec<String> = options
+        .extensions
+        .iter()
+        .map(|extension| extension.trim_start_matches('.').to_lowercase())
+        .collect();
+
+    let mut candidates = Vec::new();
+    let mut skipped = 0;
+
+    let walker = WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !is_hidden(entry.path()));
+
+    for entry in walker {
+        let entry = entry.map_err(io::Error::other)?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let size = entry.metadata().map_err(io::Error::other)?.len();
+
+        let extension = relative
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        let accepted = (include.is_empty() || include.is_match(relative))
+            && !exclude.is_match(relative)
+            && (extensions.is_empty() || extensions.contains(&extension))
+            && options.min_size.is_none_or(|min| size >= min)
+            && options.max_size.is_none_or(|max| size <= max);
+
+        if !accepted {
+            skipped += 1;
+            continue;
+        }
+
+        let folders: Vec<String> = relative
+            .parent()
+            .map(|parent| {
+                parent
+                    .iter()
+                    .map(|folder| folder.to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let (collection, tags) = match options.subfolders {
+            SubfolderMapping::Collection => (
+                folders.last().cloned().unwrap_or(collection.to_string()),
+                Vec::new(),
+            ),
+            SubfolderMapping::Tags => (collection.to_string(), folders),
+            SubfolderMapping::Ignore => (collection.to_string(), Vec::new()),
+        };
+
+        candidates.push(ImportCandidate {
+            path: entry.into_path(),
+            collection,
+            tags,
+        });
+    }
+
+    Ok((candidates, skipped))
+}
+
+/// Ingest `candidates` on a pool of `workers` threads.
+///
+/// `on_progress` is called after every file, workers stop picking new files once `cancel` is set.
+/// Identical files are ingested one after the other, so the later ones find the ref created for
+/// the first one instead of racing it to the index.
+pub fn run_import<F, P>(
+    candidates: Vec<ImportCandidate>,
+    workers: usize,
+    cancel: &AtomicBool,
+    progress: ImportProgress,
+    ingest: F,
+    on_progress: P,
+) -> ImportResult
+where
+    F: Fn(&ImportCandidate) -> Result<Ref, String> + Sync,
+    P: Fn(&ImportProgress) + Sync,
+{
+    let queue = Mutex::new(VecDeque::from(candidates));
+    let state = Mutex::new(ImportResult {
+        progress,
+        failures: Vec::new(),
+    });
+    let hash_locks: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            scope.spawn(|| loop {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let Some(candidate) = queue.lock().unwrap_or_else(|e| e.into_inner()).pop_front()
+                else {
+                    break;
+                };
+
+                let hash_lock = dedup::hash_file(&candidate.path).ok().map(|hash| {
+                    let mut hash_locks = hash_locks.lock().unwrap_or_else(|e| e.into_inner());
+                    hash_locks.entry(hash).or_default().clone()
+                });
+                let result = {
+                    let _guard = hash_lock
+                        .as_ref()
+                        .map(|lock| lock.lock().unwrap_or_else(|e| e.into_inner()));
+                    ingest(&candidate)
+                };
+
+                let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+                match result {
+                    Ok(_) => state.progress.imported += 1,
+                    Err(error) => {
+                        state.progress.failed += 1;
+                        state.failures.push(ImportFailure {
+                            path: candidate.path.to_string_lossy().to_string(),
+                            error,
+                        });
+                    }
+                }
+                on_progress(&state.progress);
+            });
+        }
+    });
+
+    let mut result = state.into_inner().unwrap_or_else(|e| e.into_inner());
+    result.progress.finished = true;
+    result.progress.cancelled = cancel.load(Ordering::Relaxed);
+    result
+}
+
+fn build_globs(patterns: &[String]) -> io::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid pattern '{}': {}", pattern, e),
+            )
+        })?;
+        builder.add(glob);
+    }
+    builder.build().map_err(io::Error::other)
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_kind() {
+        assert_eq!(RefKind::detect("image.png"), RefKind::Image);
+        assert_eq!(RefKind::detect("clip.mp4"), RefKind::Video);
+        assert_eq!(RefKind::detect("song.mp3"), RefKind::Audio);
+        assert_eq!(RefKind::detect("paper.pdf"), RefKind::Doc);
+        assert_eq!(RefKind::detect("unknown.file"), RefKind::Doc);
+    }
+
+    #[test]
+    fn test_stage_file() {
+        let collections_dir = Path::new("test_import_collections");
+
+        let staged = stage_file(collections_dir, Path::new("resources/test_image.png")).unwrap();
+        assert_eq!(staged.kind, RefKind::Image);
+        assert_eq!(staged.ref_id.len(), REF_ID_LENGTH);
+        assert!(staged.ref_dir.join("test_image.png").is_file());
+
+        assert!(stage_file(collections_dir, Path::new("resources")).is_err());
+
+        discard(&staged);
+        assert!(!staged.ref_dir.exists());
+
+        fs::remove_dir_all(collections_dir).unwrap();
+    }
+
+    #[test]
+    fn test_scan_and_run_import() {
+        let root = Path::new("test_import_directory");
+        fs::create_dir_all(root.join("moodboard").join("faces")).unwrap();
+        fs::create_dir_all(root.join(".cache")).unwrap();
+        fs::write(root.join("cover.png"), [0u8; 10]).unwrap();
+        fs::write(root.join("notes.txt"), [0u8; 10]).unwrap();
+        fs::write(
+            root.join("moodboard").join("faces").join("face.jpg"),
+            [0u8; 200],
+        )
+        .unwrap();
+        fs::write(root.join(".cache").join("thumb.png"), [0u8; 10]).unwrap();
+
+        let options = DirectoryImportOptions {
+            extensions: vec!["png".to_string(), ".JPG".to_string()],
+            subfolders: SubfolderMapping::Tags,
+            ..Default::default()
+        };
+        let (candidates, skipped) = scan_directory(root, "all", &options).unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[1].tags, vec!["moodboard", "faces"]);
+
+        let options = DirectoryImportOptions {
+            exclude: vec!["**/faces/**".to_string()],
+            max_size: Some(100),
+            subfolders: SubfolderMapping::Collection,
+            ..Default::default()
+        };
+        let (candidates, skipped) = scan_directory(root, "all", &options).unwrap();
+        assert_eq!(skipped, 1);
+        assert!(candidates
+            .iter()
+            .all(|candidate| candidate.collection == "all"));
+
+        let cancel = AtomicBool::new(false);
+        let result = run_import(
+            candidates,
+            2,
+            &cancel,
+            ImportProgress::default(),
+            |candidate| {
+                if candidate.path.ends_with("notes.txt") {
+                    Err("Unsupported file".to_string())
+                } else {
+                    Ok(Ref::Note(Default::default()))
+                }
+            },
+            |_| {},
+        );
+        assert!(result.progress.finished);
+        assert_eq!(result.progress.imported, 1);
+        assert_eq!(result.progress.failed, 1);
+        assert_eq!(result.failures.len(), 1);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_run_import_identical_files() {
+        let root = Path::new("test_import_identical_files");
+        fs::create_dir_all(root).unwrap();
+        fs::write(root.join("first.png"), [1u8; 64]).unwrap();
+        fs::write(root.join("copy.png"), [1u8; 64]).unwrap();
+        fs::write(root.join("other.png"), [2u8; 64]).unwrap();
+
+        let (candidates, _) =
+            scan_directory(root, "all", &DirectoryImportOptions::default()).unwrap();
+        assert_eq!(candidates.len(), 3);
+
+        // Stand in for the index: look the hash up, take a while, then insert it
+        let hashes = Mutex::new(Vec::new());
+        let cancel = AtomicBool::new(false);
+        let result = run_import(
+            candidates,
+            3,
+            &cancel,
+            ImportProgress::default(),
+            |candidate| {
+                let hash = dedup::hash_file(&candidate.path).map_err(|e| e.to_string())?;
+                if !hashes.lock().unwrap().contains(&hash) {
+                    thread::sleep(std::time::Duration::from_millis(50));
+                    hashes.lock().unwrap().push(hash);
+                }
+                Ok(Ref::Note(Default::default()))
+            },
+            |_| {},
+        );
+
+        assert_eq!(result.progress.imported, 3);
+        assert_eq!(hashes.into_inner().unwrap().len(), 2);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
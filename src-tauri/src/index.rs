@@ -0,0 +1,748 @@
+use chrono::DateTime;
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::parser::parse_refs;
+use crate::state::{AudioRef, DocRef, ImageRef, LinkRef, NoteRef, Ref, SortBy, VideoRef};
+use crate::utils;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS refs (
+    id TEXT PRIMARY KEY,
+    ref_type TEXT NOT NULL,
+    name TEXT NOT NULL,
+    collection TEXT NOT NULL,
+    folder TEXT NOT NULL,
+    fingerprint INTEGER NOT NULL DEFAULT 0,
+    content_hash TEXT,
+    perceptual_hash TEXT,
+    captured_at INTEGER,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS refs_collection ON refs (collection);
+CREATE INDEX IF NOT EXISTS refs_ref_type ON refs (ref_type);
+CREATE INDEX IF NOT EXISTS refs_created_at ON refs (created_at);
+CREATE INDEX IF NOT EXISTS refs_updated_at ON refs (updated_at);
+CREATE INDEX IF NOT EXISTS refs_folder ON refs (folder);
+CREATE INDEX IF NOT EXISTS refs_content_hash ON refs (content_hash);
+
+CREATE TABLE IF NOT EXISTS ref_tags (
+    ref_id TEXT NOT NULL REFERENCES refs (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (ref_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS ref_tags_tag ON ref_tags (tag);
+
+CREATE TABLE IF NOT EXISTS library_properties (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+"#;
+
+/// Bumped whenever `SCHEMA` or the stored ref data changes, older indexes are dropped and filled again by the next sync
+const INDEX_VERSION: i32 = 4;
+
+/// Ref types backed by a media file, the only ones with a content hash
+const FILE_REF_TYPES: &str = "('image', 'video', 'audio', 'doc')";
+
+/// Filters accepted by `query_refs`, every field is optional
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct RefQuery {
+    pub collection: Option<String>,
+    pub tag: Option<String>,
+    pub ref_type: Option<String>,
+    /// Unix timestamps in milliseconds
+    pub created_after: Option<i64>,
+    pub created_before: Option<i64>,
+    pub updated_after: Option<i64>,
+    pub updated_before: Option<i64>,
+    pub sort_by: SortBy,
+}
+
+/// Outcome of a synchronisation between the index and the collections directory
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct SyncReport {
+    pub scanned: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub failed: usize,
+}
+
+/// Refs sharing the exact same file content
+#[derive(Serialize, Debug, Clone)]
+pub struct DuplicateGroup {
+    pub content_hash: String,
+    pub refs: Vec<Ref>,
+}
+
+/// What happened to a single ref folder during `sync_folder`
+#[derive(Debug, Clone)]
+pub enum FolderChange {
+    Unchanged,
+    Added(Ref),
+    Updated(Ref),
+    Removed(Vec<String>),
+    Invalid(String),
+}
+
+/// SQLite index of every ref in the library.
+///
+/// The `metadata.*.json` sidecars stay the source of truth, the index only
+/// mirrors them so lookups don't have to walk the collections directory.
+pub struct LibraryIndex {
+    conn: Mutex<Connection>,
+}
+
+impl LibraryIndex {
+    pub fn open(db_path: &Path) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open(db_path)?)
+    }
+
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+
+        let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version < INDEX_VERSION {
+            conn.execute_batch("DROP TABLE IF EXISTS ref_tags; DROP TABLE IF EXISTS refs;")?;
+            conn.pragma_update(None, "user_version", INDEX_VERSION)?;
+        }
+
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn lock(&self) -> rusqlite::Result<std::sync::MutexGuard<'_, Connection>> {
+        self.conn
+            .lock()
+            .map_err(|_| rusqlite::Error::InvalidParameterName("poisoned index lock".to_string()))
+    }
+
+    /// Bring the index up to date with the collections directory.
+    ///
+    /// Only folders whose content changed since the last sync are parsed again,
+    /// rows for folders that disappeared are dropped.
+    pub fn sync(&self, collections_dir: &Path) -> rusqlite::Result<SyncReport> {
+        let mut report = SyncReport::default();
+        let ref_dirs = utils::get_ref_dirs(collections_dir).unwrap_or_else(|e| {
+            eprintln!("Error reading collections directory: {}", e);
+            Vec::new()
+        });
+
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        let mut known: HashMap<String, (String, i64)> = HashMap::new();
+        {
+            let mut stmt = tx.prepare("SELECT id, folder, fingerprint FROM refs")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(1)?, (row.get(0)?, row.get(2)?)))
+            })?;
+            for row in rows {
+                let (folder, entry) = row?;
+                known.insert(folder, entry);
+            }
+        }
+
+        let mut errors = Vec::new();
+
+        for dir in ref_dirs {
+            report.scanned += 1;
+            let folder = dir.to_string_lossy().to_string();
+            let entries = utils::get_ref_entries(&dir);
+            let fingerprint = folder_fingerprint(&entries);
+
+            if let Some((_, indexed)) = known.remove(&folder) {
+                if indexed == fingerprint {
+                    continue;
+                }
+            }
+
+            match parse_refs(&entries) {
+                Ok(ref_data) => {
+                    upsert_ref(&tx, &ref_data, &folder, fingerprint)?;
+                    report.updated += 1;
+                }
+                Err(err) => {
+                    tx.execute("DELETE FROM refs WHERE folder = ?1", params![folder])?;
+                    errors.push((folder, err));
+                    report.failed += 1;
+                }
+            }
+        }
+
+        for (folder, (id, _)) in &known {
+            tx.execute(
+                "DELETE FROM refs WHERE id = ?1 AND folder = ?2",
+                params![id, folder],
+            )?;
+            report.removed += 1;
+        }
+
+        tx.commit()?;
+
+        if !errors.is_empty() {
+            eprintln!("Errors encountered during reference parsing:");
+            for (folder, err) in &errors {
+                eprintln!(" - {}: {}", folder, err);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Bring a single ref folder up to date, `folder` may no longer exist
+    pub fn sync_folder(&self, folder: &Path) -> rusqlite::Result<FolderChange> {
+        let folder_key = folder.to_string_lossy().to_string();
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        let indexed: Vec<(String, i64)> = {
+            let mut stmt = tx.prepare("SELECT id, fingerprint FROM refs WHERE folder = ?1")?;
+            let rows = stmt.query_map(params![folder_key], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        if !folder.is_dir() {
+            tx.execute("DELETE FROM refs WHERE folder = ?1", params![folder_key])?;
+            tx.commit()?;
+
+            if indexed.is_empty() {
+                return Ok(FolderChange::Unchanged);
+            }
+            return Ok(FolderChange::Removed(
+                indexed.into_iter().map(|(id, _)| id).collect(),
+            ));
+        }
+
+        let entries = utils::get_ref_entries(folder);
+        let fingerprint = folder_fingerprint(&entries);
+
+        if matches!(indexed.as_slice(), [(_, indexed)] if *indexed == fingerprint) {
+            return Ok(FolderChange::Unchanged);
+        }
+
+        let ref_data = match parse_refs(&entries) {
+            Ok(ref_data) => ref_data,
+            Err(err) => return Ok(FolderChange::Invalid(err.to_string())),
+        };
+
+        let existed = get_ref(&tx, ref_data.get_id())?.is_some();
+        upsert_ref(&tx, &ref_data, &folder_key, fingerprint)?;
+        tx.commit()?;
+
+        if existed {
+            Ok(FolderChange::Updated(ref_data))
+        } else {
+            Ok(FolderChange::Added(ref_data))
+        }
+    }
+
+    /// Drop every row and index the collections directory from scratch
+    pub fn rebuild(&self, collections_dir: &Path) -> rusqlite::Result<SyncReport> {
+        self.lock()?.execute("DELETE FROM refs", [])?;
+        self.sync(collections_dir)
+    }
+
+    /// Insert or replace a ref, refreshing its folder fingerprint
+    pub fn upsert(&self, ref_data: &Ref) -> rusqlite::Result<()> {
+        let folder = ref_folder(ref_data);
+        let fingerprint = folder_fingerprint(&utils::get_ref_entries(&folder));
+        let conn = self.lock()?;
+        upsert_ref(&conn, ref_data, &folder.to_string_lossy(), fingerprint)
+    }
+
+    pub fn get(&self, ref_id: &str) -> rusqlite::Result<Option<Ref>> {
+        let conn = self.lock()?;
+        get_ref(&conn, ref_id)
+    }
+
+    /// Apply `mutate` to the indexed ref and store the result.
+    ///
+    /// Returns `None` when no ref with this id is indexed.
+    pub fn update<T, F>(&self, ref_id: &str, mutate: F) -> rusqlite::Result<Option<T>>
+    where
+        F: FnOnce(&mut Ref) -> T,
+    {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        let Some(mut ref_data) = get_ref(&tx, ref_id)? else {
+            return Ok(None);
+        };
+
+        let folder = ref_folder(&ref_data);
+        let result = mutate(&mut ref_data);
+        let fingerprint = folder_fingerprint(&utils::get_ref_entries(&folder));
+        upsert_ref(&tx, &ref_data, &folder.to_string_lossy(), fingerprint)?;
+        tx.commit()?;
+
+        Ok(Some(result))
+    }
+
+    pub fn remove(&self, ref_id: &str) -> rusqlite::Result<bool> {
+        let conn = self.lock()?;
+        let removed = conn.execute("DELETE FROM refs WHERE id = ?1", params![ref_id])?;
+        Ok(removed > 0)
+    }
+
+    pub fn all(&self) -> rusqlite::Result<Vec<Ref>> {
+        self.query(&RefQuery::default())
+    }
+
+    pub fn query(&self, query: &RefQuery) -> rusqlite::Result<Vec<Ref>> {
+        let mut sql = String::from("SELECT ref_type, data FROM refs");
+        let mut clauses: Vec<&str> = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(collection) = &query.collection {
+            clauses.push("collection = ?");
+            values.push(Value::Text(collection.clone()));
+        }
+        if let Some(tag) = &query.tag {
+            clauses.push("id IN (SELECT ref_id FROM ref_tags WHERE tag = ?)");
+            values.push(Value::Text(tag.clone()));
+        }
+        if let Some(ref_type) = &query.ref_type {
+            clauses.push("ref_type = ?");
+            values.push(Value::Text(ref_type.clone()));
+        }
+        if let Some(after) = query.created_after {
+            clauses.push("created_at >= ?");
+            values.push(Value::Integer(after));
+        }
+        if let Some(before) = query.created_before {
+            clauses.push("created_at <= ?");
+            values.push(Value::Integer(before));
+        }
+        if let Some(after) = query.updated_after {
+            clauses.push("updated_at >= ?");
+            values.push(Value::Integer(after));
+        }
+        if let Some(before) = query.updated_before {
+            clauses.push("updated_at <= ?");
+            values.push(Value::Integer(before));
+        }
+
+        if !clauses.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&clauses.join(" AND "));
+        }
+
+        sql.push_str(match query.sort_by {
+            SortBy::CreationTime => " ORDER BY created_at DESC",
+            SortBy::ModificationTime => " ORDER BY updated_at DESC",
+            SortBy::CaptureTime => " ORDER BY COALESCE(captured_at, created_at) DESC",
+        });
+
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            decode_ref(&row.get::<_, String>(0)?, &row.get::<_, String>(1)?)
+        })?;
+
+        rows.collect()
+    }
+
+    pub fn get_property(&self, key: &str) -> rusqlite::Result<Option<String>> {
+        let conn = self.lock()?;
+        conn.query_row(
+            "SELECT value FROM library_properties WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    pub fn set_property(&self, key: &str, value: &str) -> rusqlite::Result<()> {
+        let conn = self.lock()?;
+        conn.execute(
+            "INSERT INTO library_properties (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Oldest ref of `ref_type` whose media has `content_hash`, ignoring `exclude_id`
+    pub fn find_by_hash(
+        &self,
+        content_hash: &str,
+        ref_type: &str,
+        exclude_id: &str,
+    ) -> rusqlite::Result<Option<Ref>> {
+        let conn = self.lock()?;
+        conn.query_row(
+            "SELECT ref_type, data FROM refs
+             WHERE content_hash = ?1 AND ref_type = ?2 AND id != ?3
+             ORDER BY created_at LIMIT 1",
+            params![content_hash, ref_type, exclude_id],
+            |row| decode_ref(&row.get::<_, String>(0)?, &row.get::<_, String>(1)?),
+        )
+        .optional()
+    }
+
+    /// File backed refs that were indexed before content hashes existed
+    pub fn missing_hashes(&self) -> rusqlite::Result<Vec<Ref>> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT ref_type, data FROM refs WHERE content_hash IS NULL AND ref_type IN {}",
+            FILE_REF_TYPES
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            decode_ref(&row.get::<_, String>(0)?, &row.get::<_, String>(1)?)
+        })?;
+        rows.collect()
+    }
+
+    /// Image refs that were indexed before perceptual hashes existed
+    pub fn missing_perceptual_hashes(&self) -> rusqlite::Result<Vec<Ref>> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(
+            "SELECT ref_type, data FROM refs WHERE perceptual_hash IS NULL AND ref_type = 'image'",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            decode_ref(&row.get::<_, String>(0)?, &row.get::<_, String>(1)?)
+        })?;
+        rows.collect()
+    }
+
+    /// Image refs whose embedded metadata has never been read
+    pub fn missing_exif(&self) -> rusqlite::Result<Vec<Ref>> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(
+            "SELECT ref_type, data FROM refs
+             WHERE ref_type = 'image' AND json_extract(data, '$.metadata.exif') IS NULL",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            decode_ref(&row.get::<_, String>(0)?, &row.get::<_, String>(1)?)
+        })?;
+        rows.collect()
+    }
+
+    /// Every image ref with its perceptual hash, oldest first
+    pub fn perceptual_hashes(&self) -> rusqlite::Result<Vec<(String, Ref)>> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(
+            "SELECT perceptual_hash, ref_type, data FROM refs
+             WHERE perceptual_hash IS NOT NULL ORDER BY created_at",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                decode_ref(&row.get::<_, String>(1)?, &row.get::<_, String>(2)?)?,
+            ))
+        })?;
+        rows.collect()
+    }
+
+    /// Every group of refs sharing a content hash, oldest ref first
+    pub fn duplicates(&self) -> rusqlite::Result<Vec<DuplicateGroup>> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(
+            "SELECT content_hash, ref_type, data FROM refs
+             WHERE content_hash IN (
+                SELECT content_hash FROM refs
+                WHERE content_hash IS NOT NULL
+                GROUP BY content_hash HAVING COUNT(*) > 1
+             )
+             ORDER BY content_hash, created_at",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                decode_ref(&row.get::<_, String>(1)?, &row.get::<_, String>(2)?)?,
+            ))
+        })?;
+
+        let mut groups: Vec<DuplicateGroup> = Vec::new();
+        for row in rows {
+            let (content_hash, ref_data) = row?;
+            match groups.last_mut() {
+                Some(group) if group.content_hash == content_hash => group.refs.push(ref_data),
+                _ => groups.push(DuplicateGroup {
+                    content_hash,
+                    refs: vec![ref_data],
+                }),
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Every distinct tag in the library with the amount of refs using it
+    pub fn tags(&self) -> rusqlite::Result<Vec<(String, usize)>> {
+        let conn = self.lock()?;
+        let mut stmt =
+            conn.prepare("SELECT tag, COUNT(*) FROM ref_tags GROUP BY tag ORDER BY tag")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?;
+        rows.collect()
+    }
+}
+
+fn get_ref(conn: &Connection, ref_id: &str) -> rusqlite::Result<Option<Ref>> {
+    conn.query_row(
+        "SELECT ref_type, data FROM refs WHERE id = ?1",
+        params![ref_id],
+        |row| decode_ref(&row.get::<_, String>(0)?, &row.get::<_, String>(1)?),
+    )
+    .optional()
+}
+
+fn upsert_ref(
+    conn: &Connection,
+    ref_data: &Ref,
+    folder: &str,
+    fingerprint: i64,
+) -> rusqlite::Result<()> {
+    let id = ref_data.get_id();
+    let meta = ref_data.get_meta();
+    let image_metadata = match ref_data {
+        Ref::Image(image_ref) => image_ref.metadata.as_ref(),
+        _ => None,
+    };
+    let perceptual_hash = image_metadata.and_then(|metadata| metadata.perceptual_hash.as_deref());
+    let captured_at = image_metadata
+        .and_then(|metadata| metadata.exif.as_ref())
+        .and_then(|exif| exif.captured_timestamp());
+    let data = serde_json::to_string(ref_data)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+
+    conn.execute(
+        "INSERT INTO refs (id, ref_type, name, collection, folder, fingerprint, content_hash, perceptual_hash, captured_at, created_at, updated_at, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+         ON CONFLICT (id) DO UPDATE SET
+            ref_type = excluded.ref_type,
+            name = excluded.name,
+            collection = excluded.collection,
+            folder = excluded.folder,
+            fingerprint = excluded.fingerprint,
+            content_hash = excluded.content_hash,
+            perceptual_hash = excluded.perceptual_hash,
+            captured_at = excluded.captured_at,
+            created_at = excluded.created_at,
+            updated_at = excluded.updated_at,
+            data = excluded.data",
+        params![
+            id,
+            ref_data.get_ref_type(),
+            meta.get_name(),
+            meta.get_collection(),
+            folder,
+            fingerprint,
+            meta.get_content_hash(),
+            perceptual_hash,
+            captured_at,
+            parse_timestamp(meta.get_created_at()),
+            parse_timestamp(meta.get_updated_at()),
+            data,
+        ],
+    )?;
+
+    conn.execute("DELETE FROM ref_tags WHERE ref_id = ?1", params![id])?;
+    let mut stmt =
+        conn.prepare_cached("INSERT OR IGNORE INTO ref_tags (ref_id, tag) VALUES (?1, ?2)")?;
+    for tag in meta.get_tags() {
+        stmt.execute(params![id, tag])?;
+    }
+
+    Ok(())
+}
+
+fn decode_ref(ref_type: &str, data: &str) -> rusqlite::Result<Ref> {
+    let decoded = match ref_type {
+        "image" => serde_json::from_str::<ImageRef>(data).map(Ref::Image),
+        "video" => serde_json::from_str::<VideoRef>(data).map(Ref::Video),
+        "audio" => serde_json::from_str::<AudioRef>(data).map(Ref::Audio),
+        "note" => serde_json::from_str::<NoteRef>(data).map(Ref::Note),
+        "link" => serde_json::from_str::<LinkRef>(data).map(Ref::Link),
+        "doc" => serde_json::from_str::<DocRef>(data).map(Ref::Doc),
+        _ => serde_json::from_str::<Ref>(data),
+    };
+
+    decoded.map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+/// Folder holding the ref, derived from its metadata path
+fn ref_folder(ref_data: &Ref) -> PathBuf {
+    let folder = Path::new(ref_data.get_metapath())
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    folder.canonicalize().unwrap_or(folder)
+}
+
+/// Cheap signature of a ref folder based on the names, sizes and mtimes of its files
+fn folder_fingerprint(entries: &[PathBuf]) -> i64 {
+    let mut stamps: Vec<(String, u64, u128)> = entries
+        .iter()
+        .filter_map(|entry| {
+            let meta = fs::metadata(entry).ok()?;
+            let mtime = meta
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_nanos())
+                .unwrap_or(0);
+            Some((entry.to_string_lossy().to_string(), meta.len(), mtime))
+        })
+        .collect();
+    stamps.sort();
+
+    let mut hasher = fxhash::FxHasher64::default();
+    stamps.hash(&mut hasher);
+    hasher.finish() as i64
+}
+
+/// Convert a `created_at`/`updated_at` string into unix milliseconds
+fn parse_timestamp(date: &str) -> i64 {
+    DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S%.f %:z")
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .map(|date| date.timestamp_millis())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const IMAGE_METADATA_PATH: &str = "resources/metadata.image.json";
+    const NOTE_METADATA_PATH: &str = "resources/metadata.note.json";
+
+    fn setup(test_name: &str) -> PathBuf {
+        let collections_dir = PathBuf::from(format!("test_index_{}", test_name));
+        let image_dir = collections_dir.join("2Z4ED1XBNFMGV");
+        let note_dir = collections_dir.join("MXUQSL97EOQIX");
+
+        fs::create_dir_all(&image_dir).expect("Failed to create image ref directory");
+        fs::create_dir_all(&note_dir).expect("Failed to create note ref directory");
+        fs::copy(IMAGE_METADATA_PATH, image_dir.join("metadata.image.json"))
+            .expect("Failed to copy image metadata file");
+        fs::copy(NOTE_METADATA_PATH, note_dir.join("metadata.note.json"))
+            .expect("Failed to copy note metadata file");
+
+        collections_dir
+    }
+
+    fn teardown(collections_dir: PathBuf) {
+        fs::remove_dir_all(collections_dir).expect("Failed to delete test collections directory");
+    }
+
+    #[test]
+    fn test_sync_and_query() {
+        let collections_dir = setup("sync");
+        let index = LibraryIndex::open_in_memory().unwrap();
+
+        let report = index.sync(&collections_dir).unwrap();
+        assert_eq!(report.scanned, 2);
+        assert_eq!(report.updated, 2);
+
+        // A second pass without changes shouldn't parse anything
+        let report = index.sync(&collections_dir).unwrap();
+        assert_eq!(report.updated, 0);
+
+        assert_eq!(index.all().unwrap().len(), 2);
+        assert!(index.get("2Z4ED1XBNFMGV").unwrap().is_some());
+
+        let images = index
+            .query(&RefQuery {
+                ref_type: Some("image".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].get_id(), "2Z4ED1XBNFMGV");
+
+        // Deleted folders are dropped from the index
+        fs::remove_dir_all(collections_dir.join("MXUQSL97EOQIX")).unwrap();
+        let report = index.sync(&collections_dir).unwrap();
+        assert_eq!(report.removed, 1);
+        assert!(index.get("MXUQSL97EOQIX").unwrap().is_none());
+
+        teardown(collections_dir);
+    }
+
+    #[test]
+    fn test_sync_folder() {
+        let collections_dir = setup("folder");
+        let index = LibraryIndex::open_in_memory().unwrap();
+        let note_dir = collections_dir
+            .join("MXUQSL97EOQIX")
+            .canonicalize()
+            .unwrap();
+
+        assert!(matches!(
+            index.sync_folder(&note_dir).unwrap(),
+            FolderChange::Added(_)
+        ));
+        assert!(matches!(
+            index.sync_folder(&note_dir).unwrap(),
+            FolderChange::Unchanged
+        ));
+
+        fs::write(note_dir.join("note.txt"), "content").unwrap();
+        match index.sync_folder(&note_dir).unwrap() {
+            FolderChange::Updated(Ref::Note(note_ref)) => assert_eq!(note_ref.content, "content"),
+            change => panic!("Unexpected change {:?}", change),
+        }
+
+        fs::remove_dir_all(&note_dir).unwrap();
+        match index.sync_folder(&note_dir).unwrap() {
+            FolderChange::Removed(ids) => assert_eq!(ids, vec!["MXUQSL97EOQIX".to_string()]),
+            change => panic!("Unexpected change {:?}", change),
+        }
+
+        teardown(collections_dir);
+    }
+
+    #[test]
+    fn test_update_tags() {
+        let collections_dir = setup("tags");
+        let index = LibraryIndex::open_in_memory().unwrap();
+        index.sync(&collections_dir).unwrap();
+
+        let updated = index
+            .update("2Z4ED1XBNFMGV", |ref_data| {
+                ref_data.get_ref_meta().unwrap().add_tag("moodboard")
+            })
+            .unwrap();
+        assert!(updated.is_some());
+
+        let tagged = index
+            .query(&RefQuery {
+                tag: Some("moodboard".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(index.tags().unwrap(), vec![("moodboard".to_string(), 1)]);
+
+        assert!(index.update("UNKNOWN", |_| ()).unwrap().is_none());
+        assert!(index.remove("2Z4ED1XBNFMGV").unwrap());
+        assert!(index.tags().unwrap().is_empty());
+
+        teardown(collections_dir);
+    }
+}
@@ -0,0 +1,498 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::media;
+use crate::migration::sidecar_kind;
+use crate::state::{AudioMetadata, DocMetadata, ImageMetadata, RefMeta, VideoMetadata};
+use crate::thumbnail::{self, ThumbnailFormat};
+use crate::{storage, utils};
+
+/// Collection given to refs whose metadata has to be rebuilt from scratch
+const DEFAULT_COLLECTION: &str = "all";
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IssueKind {
+    /// Folder without any file
+    EmptyFolder,
+    /// Media files without a `metadata.*.json` sidecar
+    MissingMetadata { media_files: Vec<String> },
+    /// More than one sidecar in the same folder
+    MultipleMetadata { files: Vec<String> },
+    /// Sidecar that can't be parsed
+    InvalidMetadata { file: String, error: String },
+    /// Sidecar id different from the folder name
+    IdMismatch { found: String },
+    /// Sidecar pointing at a media file that doesn't exist
+    MissingMedia { file_name: String },
+    /// Image without any generated thumbnail
+    MissingThumbnail { file_name: String },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Issue {
+    pub ref_id: String,
+    pub path: String,
+    #[serde(flatten)]
+    pub kind: IssueKind,
+}
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct IntegrityReport {
+    pub scanned: usize,
+    pub issues: Vec<Issue>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RepairOptions {
+    pub regenerate_thumbnails: bool,
+    pub regenerate_metadata: bool,
+    pub relink_orphans: bool,
+    pub quarantine_broken: bool,
+    /// Format of regenerated thumbnails, the one from the settings when unset
+    pub thumbnail_format: Option<ThumbnailFormat>,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self {
+            regenerate_thumbnails: true,
+            regenerate_metadata: true,
+            relink_orphans: true,
+            quarantine_broken: false,
+            thumbnail_format: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RepairAction {
+    RegeneratedThumbnail,
+    RegeneratedMetadata,
+    FixedId,
+    Relinked { file_name: String },
+    Quarantined { path: String },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RepairOutcome {
+    pub issue: Issue,
+    #[serde(flatten)]
+    pub action: RepairAction,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RepairFailure {
+    pub issue: Issue,
+    pub error: String,
+}
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct RepairReport {
+    pub repaired: Vec<RepairOutcome>,
+    pub failed: Vec<RepairFailure>,
+    pub skipped: Vec<Issue>,
+}
+
+/// Scan every ref folder of the collections directory and report what's wrong with it
+pub fn check_library(collections_dir: &Path) -> io::Result<IntegrityReport> {
+    let mut report = IntegrityReport::default();
+
+    for ref_dir in utils::get_ref_dirs(collections_dir)? {
+        report.scanned += 1;
+        report.issues.extend(check_ref_dir(&ref_dir));
+    }
+
+    Ok(report)
+}
+
+/// Check a single ref folder
+pub fn check_ref_dir(ref_dir: &Path) -> Vec<Issue> {
+    let ref_id = path_name(ref_dir);
+    let issue = |kind| Issue {
+        ref_id: ref_id.clone(),
+        path: ref_dir.to_string_lossy().to_string(),
+        kind,
+    };
+
+    let entries = utils::get_ref_entries(ref_dir);
+    if entries.is_empty() {
+        return vec![issue(IssueKind::EmptyFolder)];
+    }
+
+    let sidecars: Vec<&PathBuf> = entries
+        .iter()
+        .filter(|entry| sidecar_kind(entry).is_some())
+        .collect();
+
+    match sidecars.as_slice() {
+        [] => {
+            return vec![issue(IssueKind::MissingMetadata {
+                media_files: media_candidates(&entries),
+            })]
+        }
+        [_] => {}
+        _ => {
+            return vec![issue(IssueKind::MultipleMetadata {
+                files: sidecars.iter().map(|path| path_name(path)).collect(),
+            })]
+        }
+    }
+
+    let sidecar = sidecars[0];
+    let ref_data = match utils::read_metadata(sidecar) {
+        Ok(ref_data) => ref_data,
+        Err(err) => {
+            return vec![issue(IssueKind::InvalidMetadata {
+                file: path_name(sidecar),
+                error: err.to_string(),
+            })]
+        }
+    };
+
+    let mut issues = Vec::new();
+
+    let id = metadata_id(&ref_data);
+    if id != ref_id {
+        issues.push(issue(IssueKind::IdMismatch {
+            found: id.to_string(),
+        }));
+    }
+
+    match media_file_name(&ref_data) {
+        Some(file_name) if !ref_dir.join(file_name).exists() => {
+            issues.push(issue(IssueKind::MissingMedia {
+                file_name: file_name.to_string(),
+            }));
+        }
+        Some(file_name) if needs_thumbnail(&ref_data, ref_dir, file_name) => {
+            issues.push(issue(IssueKind::MissingThumbnail {
+                file_name: file_name.to_string(),
+            }));
+        }
+        Some(_) => {}
+        None if matches!(ref_data, RefMeta::Doc(_)) && media_candidates(&entries).is_empty() => {
+            issues.push(issue(IssueKind::MissingMedia {
+                file_name: String::new(),
+            }));
+        }
+        None => {}
+    }
+
+    issues
+}
+
+/// Try to fix every issue of `report`, folders that can't be fixed are moved to `quarantine_dir`
+pub fn repair_library(
+    report: &IntegrityReport,
+    quarantine_dir: &Path,
+    options: &RepairOptions,
+) -> RepairReport {
+    let mut result = RepairReport::default();
+    let mut quarantined: Vec<String> = Vec::new();
+
+    for issue in &report.issues {
+        if quarantined.contains(&issue.path) {
+            continue;
+        }
+
+        match repair_issue(issue, quarantine_dir, options) {
+            Ok(Some(action)) => {
+                if let RepairAction::Quarantined { .. } = action {
+                    quarantined.push(issue.path.clone());
+                }
+                result.repaired.push(RepairOutcome {
+                    issue: issue.clone(),
+                    action,
+                });
+            }
+            Ok(None) => result.skipped.push(issue.clone()),
+            Err(err) => result.failed.push(RepairFailure {
+                issue: issue.clone(),
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    result
+}
+
+fn repair_issue(
+    issue: &Issue,
+    quarantine_dir: &Path,
+    options: &RepairOptions,
+) -> io::Result<Option<RepairAction>> {
+    let ref_dir = Path::new(&issue.path);
+
+    let quarantine = || -> io::Result<Option<RepairAction>> {
+        if !options.quarantine_broken {
+            return Ok(None);
+        }
+        let destination = quarantine_folder(ref_dir, quarantine_dir)?;
+        Ok(Some(RepairAction::Quarantined {
+            path: destination.to_string_lossy().to_string(),
+        }))
+    };
+
+    match &issue.kind {
+        IssueKind::EmptyFolder | IssueKind::MultipleMetadata { .. } => quarantine(),
+        IssueKind::MissingMetadata { media_files } => match media_files.first() {
+            Some(file_name) if options.relink_orphans => {
+                create_metadata(ref_dir, &issue.ref_id, file_name)?;
+                Ok(Some(RepairAction::RegeneratedMetadata))
+            }
+            _ => quarantine(),
+        },
+        IssueKind::InvalidMetadata { file, .. } => {
+            let candidates = media_candidates(&utils::get_ref_entries(ref_dir));
+            match candidates.first() {
+                Some(file_name) if options.regenerate_metadata => {
+                    let sidecar = ref_dir.join(file);
+                    fs::rename(&sidecar, ref_dir.join(format!("{}.corrupt", file)))?;
+                    create_metadata(ref_dir, &issue.ref_id, file_name)?;
+                    Ok(Some(RepairAction::RegeneratedMetadata))
+                }
+                _ => quarantine(),
+            }
+        }
+        IssueKind::IdMismatch { .. } if options.regenerate_metadata => {
+            let ref_id = issue.ref_id.clone();
+            utils::update_metadata(&find_sidecar(ref_dir)?, |ref_data| match ref_data {
+                RefMeta::Image(image_ref) => image_ref.id = ref_id,
+                RefMeta::Video(video_ref) => video_ref.id = ref_id,
+                RefMeta::Audio(audio_ref) => audio_ref.id = ref_id,
+                RefMeta::Note(note_ref) => note_ref.id = ref_id,
+                RefMeta::Link(link_ref) => link_ref.id = ref_id,
+                RefMeta::Doc(doc_ref) => doc_ref.id = ref_id,
+            })?;
+            Ok(Some(RepairAction::FixedId))
+        }
+        IssueKind::IdMismatch { .. } => Ok(None),
+        IssueKind::MissingMedia { .. } => {
+            let candidates = media_candidates(&utils::get_ref_entries(ref_dir));
+            match candidates.as_slice() {
+                [file_name] if options.relink_orphans => {
+                    let new_name = file_name.clone();
+                    utils::update_metadata(&find_sidecar(ref_dir)?, |ref_data| match ref_data {
+                        RefMeta::Image(image_ref) => image_ref.file_name = new_name,
+                        RefMeta::Video(video_ref) => video_ref.file_name = new_name,
+                        RefMeta::Audio(audio_ref) => audio_ref.file_name = new_name,
+                        _ => {}
+                    })?;
+                    Ok(Some(RepairAction::Relinked {
+                        file_name: file_name.clone(),
+                    }))
+                }
+                _ => quarantine(),
+            }
+        }
+        IssueKind::MissingThumbnail { file_name } if options.regenerate_thumbnails => {
+            thumbnail::generate_thumbnails(
+                &ref_dir.join(file_name),
+                ref_dir,
+                options.thumbnail_format.unwrap_or_default(),
+            )?;
+            Ok(Some(RepairAction::RegeneratedThumbnail))
+        }
+        IssueKind::MissingThumbnail { .. } => Ok(None),
+    }
+}
+
+/// Write a fresh sidecar for `file_name`, deducing the ref type from its mime type
+fn create_metadata(ref_dir: &Path, ref_id: &str, file_name: &str) -> io::Result<()> {
+    let media_path = ref_dir.join(file_name);
+    let media_type = media::determine_media_type(file_name);
+    let to_io = |e: String| io::Error::other(e);
+
+    storage::with_ref_lock(ref_dir, || {
+        if media_type.starts_with("image") {
+            let metadata = ImageMetadata::new(
+                ref_id.to_string(),
+                file_name,
+                &media_path,
+                DEFAULT_COLLECTION,
+            )
+            .map_err(to_io)?;
+            storage::write_json(&ref_dir.join("metadata.image.json"), &metadata)
+        } else if media_type.starts_with("video") {
+            let metadata = VideoMetadata::new(
+                ref_id.to_string(),
+                file_name,
+                &media_path,
+                DEFAULT_COLLECTION,
+            )
+            .map_err(to_io)?;
+            storage::write_json(&ref_dir.join("metadata.video.json"), &metadata)
+        } else if media_type.starts_with("audio") {
+            let metadata = AudioMetadata::new(
+                ref_id.to_string(),
+                file_name,
+                &media_path,
+                DEFAULT_COLLECTION,
+            )
+            .map_err(to_io)?;
+            storage::write_json(&ref_dir.join("metadata.audio.json"), &metadata)
+        } else {
+            let metadata =
+                DocMetadata::new(ref_id.to_string(), DEFAULT_COLLECTION).map_err(to_io)?;
+            storage::write_json(&ref_dir.join("metadata.doc.json"), &metadata)
+        }
+    })
+}
+
+/// Move a broken ref folder out of the collections directory
+fn quarantine_folder(ref_dir: &Path, quarantine_dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(quarantine_dir)?;
+
+    let mut destination = quarantine_dir.join(path_name(ref_dir));
+    let mut suffix = 1;
+    while destination.exists() {
+        destination = quarantine_dir.join(format!("{}_{}", path_name(ref_dir), suffix));
+        suffix += 1;
+    }
+
+    fs::rename(ref_dir, &destination)?;
+    Ok(destination)
+}
+
+fn find_sidecar(ref_dir: &Path) -> io::Result<PathBuf> {
+    utils::get_ref_entries(ref_dir)
+        .into_iter()
+        .find(|entry| sidecar_kind(entry).is_some())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Metadata file not found"))
+}
+
+/// Files of a ref folder that could be its media, i.e. not a sidecar, thumbnail or leftover
+pub fn media_candidates(entries: &[PathBuf]) -> Vec<String> {
+    let mut candidates: Vec<String> = entries
+        .iter()
+        .filter(|entry| entry.is_file() && sidecar_kind(entry).is_none())
+        .map(|entry| path_name(entry))
+        .filter(|name| {
+            !name.starts_with("lower_")
+                && !name.starts_with('.')
+                && !name.ends_with(".corrupt")
+                && !name.starts_with("note.")
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+fn needs_thumbnail(ref_data: &RefMeta, ref_dir: &Path, file_name: &str) -> bool {
+    matches!(ref_data, RefMeta::Image(_))
+        && thumbnail::supports_thumbnails(file_name)
+        && thumbnail::list_thumbnails(ref_dir).is_empty()
+}
+
+fn media_file_name(ref_data: &RefMeta) -> Option<&str> {
+    match ref_data {
+        RefMeta::Image(image_ref) => Some(&image_ref.file_name),
+        RefMeta::Video(video_ref) => Some(&video_ref.file_name),
+        RefMeta::Audio(audio_ref) => Some(&audio_ref.file_name),
+        _ => None,
+    }
+}
+
+fn metadata_id(ref_data: &RefMeta) -> &str {
+    match ref_data {
+        RefMeta::Image(image_ref) => &image_ref.id,
+        RefMeta::Video(video_ref) => &video_ref.id,
+        RefMeta::Audio(audio_ref) => &audio_ref.id,
+        RefMeta::Note(note_ref) => &note_ref.id,
+        RefMeta::Link(link_ref) => &link_ref.id,
+        RefMeta::Doc(doc_ref) => &doc_ref.id,
+    }
+}
+
+fn path_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_PATH: &str = "resources/test_image.png";
+    const IMAGE_METADATA_PATH: &str = "resources/metadata.image.json";
+
+    fn kinds(report: &IntegrityReport) -> Vec<IssueKind> {
+        report
+            .issues
+            .iter()
+            .map(|issue| issue.kind.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_check_and_repair_library() {
+        let root = Path::new("test_integrity");
+        let collections_dir = root.join("collections");
+        let quarantine_dir = root.join("quarantine");
+
+        // Sidecar whose id doesn't match the folder and whose media is missing
+        let broken_dir = collections_dir.join("BROKEN");
+        fs::create_dir_all(&broken_dir).unwrap();
+        fs::copy(IMAGE_METADATA_PATH, broken_dir.join("metadata.image.json")).unwrap();
+
+        // Media without a sidecar
+        let orphan_dir = collections_dir.join("ORPHAN");
+        fs::create_dir_all(&orphan_dir).unwrap();
+        fs::copy(IMAGE_PATH, orphan_dir.join("test_image.png")).unwrap();
+
+        // Empty folder
+        fs::create_dir_all(collections_dir.join("EMPTY")).unwrap();
+
+        let report = check_library(&collections_dir).unwrap();
+        assert_eq!(report.scanned, 3);
+
+        let found = kinds(&report);
+        assert!(found.contains(&IssueKind::EmptyFolder));
+        assert!(found.contains(&IssueKind::IdMismatch {
+            found: "2Z4ED1XBNFMGV".to_string()
+        }));
+        assert!(found.contains(&IssueKind::MissingMedia {
+            file_name: "WaifuGen.jpeg".to_string()
+        }));
+        assert!(found.contains(&IssueKind::MissingMetadata {
+            media_files: vec!["test_image.png".to_string()]
+        }));
+
+        let options = RepairOptions {
+            quarantine_broken: true,
+            ..Default::default()
+        };
+        let repair = repair_library(&report, &quarantine_dir, &options);
+        assert!(repair.failed.is_empty());
+
+        assert!(orphan_dir.join("metadata.image.json").exists());
+        assert!(!collections_dir.join("EMPTY").exists());
+        assert!(quarantine_dir.join("EMPTY").exists());
+
+        // The orphan is now a regular ref, only its thumbnail is missing
+        let report = check_library(&collections_dir).unwrap();
+        let orphan_issues: Vec<IssueKind> = report
+            .issues
+            .iter()
+            .filter(|issue| issue.ref_id == "ORPHAN")
+            .map(|issue| issue.kind.clone())
+            .collect();
+        assert_eq!(
+            orphan_issues,
+            vec![IssueKind::MissingThumbnail {
+                file_name: "test_image.png".to_string()
+            }]
+        );
+
+        let repair = repair_library(&report, &quarantine_dir, &options);
+        assert!(repair.failed.is_empty());
+        assert!(!thumbnail::list_thumbnails(&orphan_dir).is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,660 @@
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+use std::{fs, io, thread};
+use tauri::{AppHandle, Manager, WindowUrl};
+use tauri_plugin_snapshot::{snapshot, Options, Region};
+
+use crate::colors::PaletteColor;
+use crate::config::get_collection_path;
+use crate::index::LibraryIndex;
+use crate::state::{AudioRef, ImageRef, Ref, Settings, VideoRef};
+use crate::utils::{self, convert_file_src};
+use crate::{media, storage, thumbnail};
+
+/// Jobs processed at the same time
+pub const WORKERS: usize = 2;
+
+/// Seconds a page gets to load before its snapshot is taken
+const SNAPSHOT_DELAY: u64 = 30;
+
+/// Work done in the background once a ref has been created
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobKind {
+    ExtractColors {
+        ref_id: String,
+        media_path: String,
+        metadata_path: String,
+    },
+    LinkSnapshot {
+        ref_id: String,
+        url: String,
+    },
+}
+
+impl JobKind {
+    /// Color extraction of an image ref, `None` for refs without metadata
+    pub fn extract_colors(image_ref: &ImageRef) -> Option<Self> {
+        let metadata = image_ref.metadata.as_ref()?;
+        let metadata_path = Path::new(&image_ref.metapath);
+        let media_path = metadata_path.parent()?.join(&metadata.file_name);
+
+        Some(JobKind::ExtractColors {
+            ref_id: metadata.id.clone(),
+            media_path: media_path.to_string_lossy().to_string(),
+            metadata_path: image_ref.metapath.clone(),
+        })
+    }
+
+    /// Color extraction of the poster of a video ref, `None` for videos without a poster
+    pub fn extract_video_colors(video_ref: &VideoRef) -> Option<Self> {
+        let metadata = video_ref.metadata.as_ref()?;
+        Self::extract_thumbnail_colors(&metadata.id, &video_ref.metapath)
+    }
+
+    /// Color extraction of the cover art of an audio ref, `None` for files without one
+    pub fn extract_audio_colors(audio_ref: &AudioRef) -> Option<Self> {
+        let metadata = audio_ref.metadata.as_ref()?;
+        Self::extract_thumbnail_colors(&metadata.id, &audio_ref.metapath)
+    }
+
+    /// Color extraction of the largest thumbnail of a ref
+    fn extract_thumbnail_colors(ref_id: &str, metapath: &str) -> Option<Self> {
+        let ref_dir = Path::new(metapath).parent()?;
+        let thumbnail_path = thumbnail::largest_thumbnail(ref_dir)?;
+
+        Some(JobKind::ExtractColors {
+            ref_id: ref_id.to_string(),
+            media_path: thumbnail_path.to_string_lossy().to_string(),
+            metadata_path: metapath.to_string(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub id: String,
+    #[serde(flatten)]
+    pub kind: JobKind,
+    pub status: JobStatus,
+    /// From 0 to 1, only reported by jobs that can tell
+    pub progress: f32,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    #[serde(skip)]
+    cancel: Arc<AtomicBool>,
+}
+
+/// Payload of the `colors-added` event
+#[derive(Serialize, Debug, Clone)]
+pub struct ColorsExtracted {
+    pub ref_id: String,
+    pub colors: Vec<PaletteColor>,
+}
+
+/// Handed to a running job so it can report progress and notice cancellation
+pub struct JobContext<'a> {
+    queue: &'a QueueInner,
+    job_id: String,
+    cancel: Arc<AtomicBool>,
+}
+
+impl JobContext<'_> {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    pub fn set_progress(&self, progress: f32) {
+        let mut jobs = self.queue.lock();
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == self.job_id) {
+            job.progress = progress.clamp(0.0, 1.0);
+            (self.queue.notify)(job);
+        }
+    }
+}
+
+type Notify = Box<dyn Fn(&Job) + Send + Sync>;
+
+struct QueueInner {
+    jobs_dir: PathBuf,
+    jobs: Mutex<Vec<Job>>,
+    available: Condvar,
+    notify: Notify,
+}
+
+impl QueueInner {
+    fn lock(&self) -> MutexGuard<'_, Vec<Job>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn persist(&self, job: &Job) {
+        let job_path = self.jobs_dir.join(format!("{}.json", job.id));
+        if let Err(e) = storage::write_json(&job_path, job) {
+            eprintln!("Error saving job {}: {}", job.id, e);
+        }
+    }
+
+    /// Apply `update` to a job, save it and report it
+    fn update<F: FnOnce(&mut Job)>(&self, job_id: &str, update: F) -> Option<Job> {
+        let mut jobs = self.lock();
+        let job = jobs.iter_mut().find(|job| job.id == job_id)?;
+        update(job);
+        job.updated_at = Local::now().to_rfc3339();
+        self.persist(job);
+        (self.notify)(job);
+        Some(job.clone())
+    }
+}
+
+/// Queue of background jobs, saved in the jobs directory so unfinished work survives a restart
+pub struct JobQueue {
+    inner: Arc<QueueInner>,
+}
+
+impl JobQueue {
+    /// Load the saved jobs, jobs interrupted by a shutdown are queued again.
+    ///
+    /// Finished jobs of previous sessions are forgotten. `notify` is called on every change.
+    pub fn open<N>(jobs_dir: &Path, notify: N) -> io::Result<Self>
+    where
+        N: Fn(&Job) + Send + Sync + 'static,
+    {
+        fs::create_dir_all(jobs_dir)?;
+
+        let mut jobs = Vec::new();
+        for entry in fs::read_dir(jobs_dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+
+            let job = fs::read_to_string(&path)
+                .ok()
+                .and_then(|job_json| serde_json::from_str::<Job>(&job_json).ok());
+
+            match job {
+                Some(job) if matches!(job.status, JobStatus::Completed | JobStatus::Cancelled) => {
+                    fs::remove_file(&path)?;
+                }
+                Some(mut job) => {
+                    if job.status == JobStatus::Running {
+                        job.status = JobStatus::Queued;
+                    }
+                    jobs.push(job);
+                }
+                None => eprintln!("Skipping unreadable job file {}", path.display()),
+            }
+        }
+        jobs.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+        Ok(Self {
+            inner: Arc::new(QueueInner {
+                jobs_dir: jobs_dir.to_path_buf(),
+                jobs: Mutex::new(jobs),
+                available: Condvar::new(),
+                notify: Box::new(notify),
+            }),
+        })
+    }
+
+    /// Spawn `workers` threads running the queued jobs with `run`.
+    ///
+    /// Errors and panics of `run` mark the job as failed instead of killing the worker.
+    pub fn start<R>(&self, workers: usize, run: R)
+    where
+        R: Fn(&Job, &JobContext) -> Result<(), String> + Send + Sync + 'static,
+    {
+        let run = Arc::new(run);
+
+        for _ in 0..workers.max(1) {
+            let inner = self.inner.clone();
+            let run = run.clone();
+            thread::spawn(move || loop {
+                let job = next_job(&inner);
+                let context = JobContext {
+                    queue: &inner,
+                    job_id: job.id.clone(),
+                    cancel: job.cancel.clone(),
+                };
+
+                let result = panic::catch_unwind(AssertUnwindSafe(|| run(&job, &context)))
+                    .unwrap_or_else(|payload| Err(panic_message(payload)));
+
+                inner.update(&job.id, |job| {
+                    if job.cancel.load(Ordering::Relaxed) {
+                        job.status = JobStatus::Cancelled;
+                        job.error = None;
+                        return;
+                    }
+                    match result {
+                        Ok(()) => {
+                            job.status = JobStatus::Completed;
+                            job.progress = 1.0;
+                            job.error = None;
+                        }
+                        Err(error) => {
+                            job.status = JobStatus::Failed;
+                            job.error = Some(error);
+                        }
+                    }
+                });
+            });
+        }
+    }
+
+    pub fn enqueue(&self, kind: JobKind) -> Job {
+        let now = Local::now().to_rfc3339();
+        let job = Job {
+            id: utils::random_id(13),
+            kind,
+            status: JobStatus::Queued,
+            progress: 0.0,
+            attempts: 0,
+            error: None,
+            created_at: now.clone(),
+            updated_at: now,
+            cancel: Arc::default(),
+        };
+
+        self.inner.persist(&job);
+        (self.inner.notify)(&job);
+        self.inner.lock().push(job.clone());
+        self.inner.available.notify_one();
+
+        job
+    }
+
+    /// Every known job, oldest first
+    pub fn list(&self) -> Vec<Job> {
+        self.inner.lock().clone()
+    }
+
+    /// Cancel a queued or running job, running jobs stop at their next checkpoint
+    pub fn cancel(&self, job_id: &str) -> Result<Job, String> {
+        let status = self
+            .inner
+            .lock()
+            .iter()
+            .find(|job| job.id == job_id)
+            .map(|job| {
+                job.cancel.store(true, Ordering::Relaxed);
+                job.status
+            })
+            .ok_or_else(|| format!("Job '{}' not found", job_id))?;
+
+        match status {
+            JobStatus::Queued => self
+                .inner
+                .update(job_id, |job| job.status = JobStatus::Cancelled)
+                .ok_or_else(|| format!("Job '{}' not found", job_id)),
+            JobStatus::Running => Ok(self.find(job_id)?),
+            _ => Err(format!("Job '{}' is already finished", job_id)),
+        }
+    }
+
+    /// Queue a failed or cancelled job again
+    pub fn retry(&self, job_id: &str) -> Result<Job, String> {
+        let status = self.find(job_id)?.status;
+        if !matches!(status, JobStatus::Failed | JobStatus::Cancelled) {
+            return Err(format!("Job '{}' can't be retried", job_id));
+        }
+
+        let job = self
+            .inner
+            .update(job_id, |job| {
+                job.status = JobStatus::Queued;
+                job.progress = 0.0;
+                job.error = None;
+                job.cancel = Arc::default();
+            })
+            .ok_or_else(|| format!("Job '{}' not found", job_id))?;
+        self.inner.available.notify_one();
+
+        Ok(job)
+    }
+
+    fn find(&self, job_id: &str) -> Result<Job, String> {
+        self.inner
+            .lock()
+            .iter()
+            .find(|job| job.id == job_id)
+            .cloned()
+            .ok_or_else(|| format!("Job '{}' not found", job_id))
+    }
+}
+
+/// Block until a job is queued and mark it as running
+fn next_job(inner: &QueueInner) -> Job {
+    let mut jobs = inner.lock();
+    loop {
+        if let Some(job) = jobs.iter_mut().find(|job| job.status == JobStatus::Queued) {
+            job.status = JobStatus::Running;
+            job.attempts += 1;
+            job.updated_at = Local::now().to_rfc3339();
+            inner.persist(job);
+            (inner.notify)(job);
+            return job.clone();
+        }
+        jobs = inner
+            .available
+            .wait(jobs)
+            .unwrap_or_else(|e| e.into_inner());
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".to_string());
+    format!("Job panicked: {}", message)
+}
+
+/// Run a job of the app
+pub fn run_job(handle: &AppHandle, job: &Job, context: &JobContext) -> Result<(), String> {
+    match &job.kind {
+        JobKind::ExtractColors {
+            ref_id,
+            media_path,
+            metadata_path,
+        } => extract_colors(
+            handle,
+            ref_id,
+            Path::new(media_path),
+            Path::new(metadata_path),
+            context,
+        ),
+        JobKind::LinkSnapshot { ref_id, url } => {
+            snapshot_link(handle, &job.id, ref_id, url, context)
+        }
+    }
+}
+
+fn extract_colors(
+    handle: &AppHandle,
+    ref_id: &str,
+    media_path: &Path,
+    metadata_path: &Path,
+    context: &JobContext,
+) -> Result<(), String> {
+    let options = handle
+        .state::<Mutex<Settings>>()
+        .lock()
+        .map(|settings| settings.behavior.palette.clone())
+        .unwrap_or_default();
+
+    let colors = media::extract_colors(media_path, &options).map_err(|e| e.to_string())?;
+    if context.is_cancelled() {
+        return Ok(());
+    }
+
+    let index = handle.state::<LibraryIndex>();
+    let updated_ref = store_colors(&index, ref_id, metadata_path, &colors)?;
+
+    handle
+        .emit_all(
+            "colors-added",
+            ColorsExtracted {
+                ref_id: ref_id.to_string(),
+                colors,
+            },
+        )
+        .map_err(|e| e.to_string())?;
+    handle
+        .emit_all("ref-updated", updated_ref)
+        .map_err(|e| e.to_string())
+}
+
+/// Write the colors of an image, video or audio ref to its sidecar and to the index, returns the updated ref
+pub fn store_colors(
+    index: &LibraryIndex,
+    ref_id: &str,
+    metadata_path: &Path,
+    colors: &[PaletteColor],
+) -> Result<Ref, String> {
+    utils::set_colors(metadata_path, colors).map_err(|e| e.to_string())?;
+
+    index
+        .update(ref_id, |found_ref| match found_ref {
+            Ref::Image(ImageRef {
+                metadata: Some(ref mut metadata),
+                ..
+            }) => {
+                metadata.colors = colors.to_vec();
+                Ok(found_ref.clone())
+            }
+            Ref::Video(VideoRef {
+                metadata: Some(ref mut metadata),
+                ..
+            }) => {
+                metadata.colors = colors.to_vec();
+                Ok(found_ref.clone())
+            }
+            Ref::Audio(AudioRef {
+                metadata: Some(ref mut metadata),
+                ..
+            }) => {
+                metadata.colors = colors.to_vec();
+                Ok(found_ref.clone())
+            }
+            _ => Err("Invalid reference type for colors".to_string()),
+        })
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))?
+}
+
+fn snapshot_link(
+    handle: &AppHandle,
+    job_id: &str,
+    ref_id: &str,
+    url: &str,
+    context: &JobContext,
+) -> Result<(), String> {
+    let url = url
+        .parse()
+        .map_err(|e| format!("Invalid url '{}': {}", url, e))?;
+    let window = tauri::WindowBuilder::new(
+        handle,
+        format!("capture-{}", job_id),
+        WindowUrl::External(url),
+    )
+    .build()
+    .map_err(|e| e.to_string())?;
+
+    let options = Options {
+        capture: None,
+        region: Some(Region::Document),
+        save: None,
+    };
+
+    let _ = window.hide();
+
+    // Give the page time to load, checking for cancellation every second
+    for elapsed in 0..SNAPSHOT_DELAY {
+        if context.is_cancelled() {
+            let _ = window.close();
+            return Ok(());
+        }
+        context.set_progress(elapsed as f32 / SNAPSHOT_DELAY as f32);
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    let img_buffer = snapshot(window.clone(), options).map_err(|error| error.to_string());
+    let _ = window.close();
+    let img_buffer = img_buffer?;
+
+    // save image to disk
+    let img_path = get_collection_path(handle)
+        .join(ref_id)
+        .join("snapshot.png");
+    storage::with_ref_lock(&img_path, || storage::write_atomic(&img_path, &img_buffer))
+        .map_err(|e| e.to_string())?;
+
+    // update the image in the index
+    let index = handle.state::<LibraryIndex>();
+
+    index
+        .update(ref_id, |found_ref| match found_ref {
+            Ref::Link(ref mut link_ref) => {
+                link_ref.snapshoot = convert_file_src(&img_path);
+                Ok(())
+            }
+            _ => Err("Invalid reference type for link snapshot".to_string()),
+        })
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reference with ID '{}' not found", ref_id))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ImageMetadata;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Instant;
+
+    fn colors_job(ref_id: &str) -> JobKind {
+        JobKind::ExtractColors {
+            ref_id: ref_id.to_string(),
+            media_path: String::new(),
+            metadata_path: String::new(),
+        }
+    }
+
+    fn wait_for(queue: &JobQueue, job_id: &str, status: JobStatus) -> Job {
+        let start = Instant::now();
+        loop {
+            let job = queue.find(job_id).unwrap();
+            if job.status == status {
+                return job;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "job stuck");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_failed_jobs_can_be_retried() {
+        let jobs_dir = Path::new("test_jobs_retry");
+        let queue = JobQueue::open(jobs_dir, |_| {}).unwrap();
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        queue.start(1, move |_, _| {
+            match counter.fetch_add(1, Ordering::Relaxed) + 1 {
+                1 => Err("First attempt fails".to_string()),
+                2 => panic!("Second attempt panics"),
+                _ => Ok(()),
+            }
+        });
+
+        let job = queue.enqueue(colors_job("REF"));
+        let failed = wait_for(&queue, &job.id, JobStatus::Failed);
+        assert_eq!(failed.error.as_deref(), Some("First attempt fails"));
+
+        queue.retry(&job.id).unwrap();
+        let failed = wait_for(&queue, &job.id, JobStatus::Failed);
+        assert!(failed.error.unwrap().contains("Second attempt panics"));
+
+        queue.retry(&job.id).unwrap();
+        let completed = wait_for(&queue, &job.id, JobStatus::Completed);
+        assert_eq!(completed.attempts, 3);
+        assert!(queue.retry(&job.id).is_err());
+
+        fs::remove_dir_all(jobs_dir).unwrap();
+    }
+
+    #[test]
+    fn test_unfinished_jobs_resume_after_restart() {
+        let jobs_dir = Path::new("test_jobs_resume");
+
+        let queue = JobQueue::open(jobs_dir, |_| {}).unwrap();
+        let queued = queue.enqueue(colors_job("QUEUED"));
+        let cancelled = queue.enqueue(colors_job("CANCELLED"));
+        queue.cancel(&cancelled.id).unwrap();
+        drop(queue);
+
+        // Nothing ran, reopening keeps the queued job and forgets the cancelled one
+        let queue = JobQueue::open(jobs_dir, |_| {}).unwrap();
+        let jobs = queue.list();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, queued.id);
+        assert_eq!(jobs[0].status, JobStatus::Queued);
+
+        queue.start(1, |_, _| Ok(()));
+        wait_for(&queue, &queued.id, JobStatus::Completed);
+
+        fs::remove_dir_all(jobs_dir).unwrap();
+    }
+
+    #[test]
+    fn test_store_colors_updates_the_index() {
+        let collections_dir = Path::new("test_jobs_colors");
+        let ref_dir = collections_dir.join("COLORIMAGE");
+        let media_path = ref_dir.join("test_image.png");
+        let metadata_path = ref_dir.join("metadata.image.json");
+        fs::create_dir_all(&ref_dir).unwrap();
+        fs::copy("resources/test_image.png", &media_path).unwrap();
+        let metadata = ImageMetadata::new(
+            "COLORIMAGE".to_string(),
+            "test_image.png",
+            &media_path,
+            "all",
+        )
+        .unwrap();
+        storage::write_json(&metadata_path, &metadata).unwrap();
+
+        let index = LibraryIndex::open_in_memory().unwrap();
+        index.sync(collections_dir).unwrap();
+
+        let Some(Ref::Image(image_ref)) = index.get("COLORIMAGE").unwrap() else {
+            panic!("image ref not indexed");
+        };
+        let JobKind::ExtractColors {
+            media_path: job_media_path,
+            ..
+        } = JobKind::extract_colors(&image_ref).unwrap()
+        else {
+            panic!("wrong job kind");
+        };
+        assert!(Path::new(&job_media_path).ends_with(&media_path));
+
+        let colors = vec![
+            PaletteColor {
+                hex: "#ff0000".to_string(),
+                percentage: 60.0,
+            },
+            PaletteColor {
+                hex: "#00ff00".to_string(),
+                percentage: 40.0,
+            },
+        ];
+        store_colors(&index, "COLORIMAGE", &metadata_path, &colors).unwrap();
+
+        let Some(Ref::Image(image_ref)) = index.get("COLORIMAGE").unwrap() else {
+            panic!("image ref not indexed");
+        };
+        assert_eq!(image_ref.metadata.unwrap().colors, colors);
+        let on_disk = fs::read_to_string(&metadata_path).unwrap();
+        assert!(on_disk.contains("#00ff00"));
+
+        fs::remove_dir_all(collections_dir).unwrap();
+    }
+}
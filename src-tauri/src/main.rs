@@ -4,12 +4,33 @@ use tauri::Manager;
 use tauri_plugin_log::LogTarget;
 use window_shadows::set_shadow;
 
+mod animation;
+mod audio;
+mod bmff;
+mod colors;
 mod commands;
 mod config;
+mod dedup;
+mod exif;
+mod formats;
+mod icc;
+mod import;
+mod index;
+mod integrity;
+mod jobs;
 mod media;
+mod migration;
 mod parser;
+mod raw;
 mod state;
+mod storage;
+mod swatches;
+mod thumbnail;
+mod timeline;
+mod trash;
 mod utils;
+mod video;
+mod watcher;
 
 fn main() {
     tauri::Builder::default()
@@ -25,8 +46,18 @@ fn main() {
             let handle = app.handle();
 
             config::init(&handle);
-            app.manage(state::init_media_ref(&handle));
+            app.manage(state::init_library_index(&handle));
             app.manage(state::init_settings(&handle));
+            app.manage(import::ImportJobs::default());
+            app.manage(state::init_job_queue(&handle));
+            state::init_trash(&handle);
+
+            match watcher::watch_collections(&handle) {
+                Ok(library_watcher) => {
+                    app.manage(library_watcher);
+                }
+                Err(e) => eprintln!("Error watching collections directory: {}", e),
+            }
 
             // Set window shadow (macos & windows only)
             #[cfg(any(windows, target_os = "macos"))]
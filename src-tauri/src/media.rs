@@ -1,138 +1,157 @@
-use fxhash::FxHashMap;
-use image::{imageops, DynamicImage, GenericImageView, RgbaImage};
-use kmeans_colors::{get_kmeans, get_kmeans_hamerly, Calculate, Kmeans, MapColor, Sort};
-use palette::cast::{AsComponents, ComponentsAs};
-use palette::{white_point::D65, Alpha, FromColor, IntoColor, Lab, LinSrgba, Srgb, Srgba};
+use image::codecs::{jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder, webp::WebPDecoder};
+use image::{imageops, DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageResult};
 
 use mime_guess::from_path;
-use std::collections::HashSet;
-use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
 
-use crate::utils::cached_srgba_to_lab;
+use crate::colors::{self, PaletteColor, PaletteOptions};
+use crate::formats::{self, ExtraFormat};
+use crate::{exif, icc, raw};
 
 /// Determine the media type of a file based on its extension
 pub fn determine_media_type<P>(file_path: P) -> String
 where
     P: AsRef<Path>,
 {
-    if let Some(media_type) = from_path(file_path).first() {
-        media_type.to_string()
-    } else {
-        "application/octet-stream".to_string()
+    let is_psd = file_path.as_ref().extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("psd") || extension.eq_ignore_ascii_case("psb")
+    });
+    if is_psd {
+        return formats::PSD_MEDIA_TYPE.to_string();
+    }
+
+    match from_path(&file_path).first() {
+        Some(media_type) => media_type.to_string(),
+        None if raw::is_raw(file_path.as_ref()) => raw::RAW_MEDIA_TYPE.to_string(),
+        None => "application/octet-stream".to_string(),
     }
 }
 
-/// Get the dimensions of an image (width, height)
-pub fn analyze_dimensions(file_path: &Path) -> Option<(u32, u32)> {
-    if let Ok(image) = image::open(file_path) {
-        Some(image.dimensions())
-    } else {
-        None
+/// Open an image as it is displayed, EXIF orientation applied and colors converted to sRGB
+pub fn open_image(file_path: &Path) -> io::Result<DynamicImage> {
+    if let Some(format) = extra_format(file_path)? {
+        let image = formats::decode(format, &fs::read(file_path)?)?;
+        return Ok(apply_orientation(image, orientation(file_path)));
     }
+
+    let reader = image::io::Reader::open(file_path)?.with_guessed_format()?;
+    let file = || File::open(file_path).map(BufReader::new);
+
+    let (image, profile) = match reader.format() {
+        Some(ImageFormat::Png) => decode_with_profile(PngDecoder::new(file()?))?,
+        Some(ImageFormat::Jpeg) => decode_with_profile(JpegDecoder::new(file()?))?,
+        Some(ImageFormat::WebP) => decode_with_profile(WebPDecoder::new(file()?))?,
+        Some(ImageFormat::Tiff) => decode_with_profile(TiffDecoder::new(file()?))?,
+        _ => (reader.decode().map_err(io::Error::other)?, None),
+    };
+
+    let image = icc::convert_to_srgb(image, profile.as_deref());
+    Ok(apply_orientation(image, orientation(file_path)))
 }
 
-/// Extract the colors from an image
-pub fn extract_colors(file_path: &Path) -> Vec<String> {
-    let media_type = determine_media_type(file_path);
+/// Format of an image `image` cannot decode, detected from its first bytes
+fn extra_format(file_path: &Path) -> io::Result<Option<ExtraFormat>> {
+    let mut head = Vec::new();
+    File::open(file_path)?.take(1024).read_to_end(&mut head)?;
+    Ok(ExtraFormat::detect(file_path, &head))
+}
 
-    if media_type.contains("video") {
-        return Vec::new();
-    }
+fn decode_with_profile<'a>(
+    decoder: ImageResult<impl ImageDecoder<'a>>,
+) -> io::Result<(DynamicImage, Option<Vec<u8>>)> {
+    let mut decoder = decoder.map_err(io::Error::other)?;
+    let profile = decoder.icc_profile();
+    let image = DynamicImage::from_decoder(decoder).map_err(io::Error::other)?;
+    Ok((image, profile))
+}
 
-    let mut lab_cache = FxHashMap::default();
-    let mut lab_pixels: Vec<Lab<D65, f32>> = Vec::new();
-
-    let img = image::open(file_path).unwrap();
-    img.resize(150, 150, image::imageops::Triangle);
-    let raw_img = img.into_rgb8();
-    let pixels: &[Srgba<u8>] = raw_img.as_raw().components_as();
-
-    lab_pixels.clear();
-    cached_srgba_to_lab(pixels.iter(), &mut lab_cache, &mut lab_pixels);
-
-    let mut result = Kmeans::new();
-    let colors_amount = 8;
-    let max_iterations = 20;
-    let converge = 10.0;
-    let verbose = false;
-    let run = 3;
-    let seed: u64 = 0;
-
-    for i in 0..run {
-        let run_result = get_kmeans_hamerly(
-            colors_amount,
-            max_iterations,
-            converge,
-            verbose,
-            &lab_pixels,
-            seed + i,
-        );
-        if run_result.score < result.score {
-            result = run_result;
-        }
+/// EXIF orientation of an image, 1 when it has none
+fn orientation(file_path: &Path) -> u16 {
+    exif::read_metadata(file_path)
+        .ok()
+        .and_then(|data| data.orientation)
+        .unwrap_or(1)
+}
+
+/// Rotate and mirror an image according to its EXIF orientation
+pub fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
     }
+}
 
-    let mut res = Lab::<D65, f32>::sort_indexed_colors(&result.centroids, &result.indices);
-    res.sort_unstable_by(|a, b| (b.percentage).total_cmp(&a.percentage));
+/// Get the displayed dimensions of an image (width, height), rotated ones are swapped
+pub fn analyze_dimensions(file_path: &Path) -> Option<(u32, u32)> {
+    let (width, height) = match extra_format(file_path).ok()? {
+        Some(format) => formats::dimensions(format, &fs::read(file_path).ok()?).ok()?,
+        None => image::image_dimensions(file_path).ok()?,
+    };
 
-    let mut hex_colors = Vec::new();
-    for r in res.iter() {
-        let c: Srgb<u8> = Srgb::from_color(r.centroid).into_format();
-        let hex_str = format!("#{:02x}{:02x}{:02x}", c.red, c.green, c.blue);
-        hex_colors.push(hex_str);
+    if (5..=8).contains(&orientation(file_path)) {
+        Some((height, width))
+    } else {
+        Some((width, height))
     }
-
-    hex_colors
 }
 
-/// Generate a lower quality image
-pub fn generate_image(
-    file_name: &str,
-    file_dir: &Path,
-    height: u32,
-    width: u32,
-    target_size: u32,
-) -> bool {
-    // If the file is a gif or video stop the process
-    let media_type = determine_media_type(file_name);
-    let file_path = file_dir.join(file_name);
-
-    if media_type.contains("gif") || media_type.contains("video") {
-        return false;
+/// Difference hash of an image as a 16 digit hex string.
+///
+/// Resized or recompressed copies of a picture end up with hashes a few bits apart.
+pub fn perceptual_hash(file_path: &Path) -> Option<String> {
+    let image = open_image(file_path).ok()?;
+    let small = image
+        .grayscale()
+        .resize_exact(9, 8, imageops::FilterType::Triangle)
+        .into_luma8();
+
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | (left > right) as u64;
+        }
     }
 
-    // Open and resize the image (First)
-    let image_data = image::open(file_path).expect("Failed to open image");
-    let image_data = image_data.resize(height, width, image::imageops::FilterType::Lanczos3);
-    let image_data = RgbaImage::from(image_data.to_rgba8());
+    Some(format!("{:016x}", hash))
+}
 
-    // Lower the image quality (Second)
-    reduce_image_quality(&image_data, target_size);
-    let new_image = DynamicImage::ImageRgba8(image_data);
+/// Bits of a perceptual hash stored as hex
+pub fn parse_perceptual_hash(hash: &str) -> Option<u64> {
+    u64::from_str_radix(hash, 16).ok()
+}
 
-    // Save into filename_lower
-    let dest_file_name = format!("lower_{}", file_name);
-    let dest_file_path = file_dir.join(dest_file_name);
+/// Amount of differing bits between two perceptual hashes
+pub fn perceptual_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
 
-    new_image
-        .save(dest_file_path)
-        .expect("Failed to save image");
+/// Extract the color palette of an image, videos have none
+pub fn extract_colors(file_path: &Path, options: &PaletteOptions) -> io::Result<Vec<PaletteColor>> {
+    let media_type = determine_media_type(file_path);
 
-    true
-}
+    if media_type.contains("video") {
+        return Ok(Vec::new());
+    }
 
-fn reduce_image_quality(rgba_image: &RgbaImage, target_size_kb: u32) {
-    let target_size_bytes = target_size_kb * 1024;
-    let current_size_bytes = rgba_image.dimensions().0 * rgba_image.dimensions().1 * 4;
-    let quality_factor = (target_size_bytes as f64 / current_size_bytes as f64).sqrt();
-
-    // Apply the quality factor to reduce image quality
-    imageops::resize(
-        rgba_image,
-        (quality_factor * rgba_image.width() as f64) as u32,
-        (quality_factor * rgba_image.height() as f64) as u32,
-        image::imageops::FilterType::CatmullRom,
-    );
+    match open_image(file_path) {
+        Ok(img) => {
+            let small = img.thumbnail(150, 150).into_rgba8();
+            Ok(colors::extract_palette(&small, options))
+        }
+        // Formats without a decoder in this build keep an empty palette
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
 }
 
 #[cfg(test)]
@@ -183,35 +202,77 @@ mod tests {
         let video_path = Path::new(VIDEO_PATH);
         let gif_path = Path::new(GIF_PATH);
 
-        let colors = extract_colors(image_path);
+        let options = PaletteOptions::default();
+
+        let colors = extract_colors(image_path, &options).unwrap();
         assert!(!colors.is_empty());
+        assert!(colors.len() <= options.color_count);
 
-        let colors = extract_colors(gif_path);
+        let colors = extract_colors(gif_path, &options).unwrap();
         assert!(!colors.is_empty());
 
-        let colors = extract_colors(video_path);
+        let colors = extract_colors(video_path, &options).unwrap();
         assert!(colors.is_empty());
     }
 
     #[test]
-    fn test_generate_image() {
-        // Create a destination directory
-        let base_path = Path::new("generated");
-        fs::create_dir_all(base_path).expect("Failed to create destination directory");
-        let _ = fs::copy(IMAGE_PATH, base_path.join("test_image.png"));
-
-        println!("Image path: {:?}", IMAGE_PATH);
-        println!("Base path: {:?}", base_path);
-
-        // Generate a lower quality image
-        let result = generate_image("test_image.png", base_path, 200, 200, 50);
-        assert!(result);
+    fn test_open_image_orientation() {
+        let base_path = Path::new("test_open_image_orientation");
+        fs::create_dir_all(base_path).expect("Failed to create test directory");
+
+        let mut jpeg = Vec::new();
+        DynamicImage::new_rgb8(40, 20)
+            .write_to(
+                &mut io::Cursor::new(&mut jpeg),
+                image::ImageOutputFormat::Jpeg(90),
+            )
+            .expect("Failed to encode image");
+
+        // APP1 segment with a big endian TIFF holding orientation 6, rotated 90 degrees
+        let mut tiff = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        tiff.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+        let mut app1 = vec![0xff, 0xe1];
+        app1.extend_from_slice(&((tiff.len() + 2) as u16).to_be_bytes());
+        app1.extend_from_slice(&tiff);
+        jpeg.splice(2..2, app1);
+
+        let rotated_path = base_path.join("rotated.jpg");
+        fs::write(&rotated_path, jpeg).expect("Failed to write image");
+
+        let image = open_image(&rotated_path).expect("Failed to open image");
+        assert_eq!(image.dimensions(), (20, 40));
+        assert_eq!(analyze_dimensions(&rotated_path), Some((20, 40)));
+
+        let image = open_image(Path::new(IMAGE_PATH)).expect("Failed to open image");
+        assert_eq!(image.dimensions(), (1200, 900));
+
+        fs::remove_dir_all(base_path).expect("Failed to delete test directory");
+    }
 
-        // Check if the file was generated
-        let generated_file_path = Path::new(base_path).join("lower_test_image.png");
-        assert!(generated_file_path.exists());
+    #[test]
+    fn test_perceptual_hash() {
+        let base_path = Path::new("test_perceptual_hash");
+        fs::create_dir_all(base_path).expect("Failed to create test directory");
+
+        // A downscaled copy stays close to the original
+        let resized_path = base_path.join("resized.png");
+        image::open(IMAGE_PATH)
+            .expect("Failed to open image")
+            .resize(300, 300, imageops::FilterType::Lanczos3)
+            .save(&resized_path)
+            .expect("Failed to save resized image");
+
+        let original = perceptual_hash(Path::new(IMAGE_PATH)).expect("Failed to hash image");
+        let resized = perceptual_hash(&resized_path).expect("Failed to hash resized image");
+
+        assert_eq!(original.len(), 16);
+        let distance = perceptual_distance(
+            parse_perceptual_hash(&original).unwrap(),
+            parse_perceptual_hash(&resized).unwrap(),
+        );
+        assert!(distance <= 6);
+        assert!(perceptual_hash(Path::new(VIDEO_PATH)).is_none());
 
-        // Clean up the generated file
-        fs::remove_dir_all(base_path).expect("Failed to delete destination directory");
+        fs::remove_dir_all(base_path).expect("Failed to delete test directory");
     }
 }
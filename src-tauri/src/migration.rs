@@ -0,0 +1,333 @@
+use chrono::Local;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::storage;
+use crate::utils::{self, human_size};
+
+/// Version written into the `schema_version` field of every new sidecar
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// A single upgrade step, bringing a sidecar from version `n` to `n + 1`
+type Migration = fn(&mut Map<String, Value>, &str, &mut Vec<String>);
+
+/// Upgrade steps, `MIGRATIONS[n]` migrates a version `n` sidecar
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [migrate_v0_to_v1, migrate_v1_to_v2];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MigrationEntry {
+    pub path: String,
+    pub from_version: u32,
+    pub to_version: u32,
+    pub changes: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MigrationFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct MigrationReport {
+    pub dry_run: bool,
+    pub scanned: usize,
+    pub migrated: Vec<MigrationEntry>,
+    pub failed: Vec<MigrationFailure>,
+    pub backup_dir: Option<String>,
+}
+
+/// Ref type of a sidecar, taken from its `metadata.<type>.json` file name
+pub fn sidecar_kind(metadata_path: &Path) -> Option<&str> {
+    metadata_path
+        .file_name()?
+        .to_str()?
+        .strip_prefix("metadata.")?
+        .strip_suffix(".json")
+}
+
+/// Version recorded in a sidecar, files written before versioning are version 0
+pub fn schema_version(value: &Value) -> u32 {
+    value
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as u32
+}
+
+/// Upgrade a sidecar in memory to `SCHEMA_VERSION`.
+///
+/// Returns the list of applied changes, empty when the file is already up to date.
+/// Files written by a newer version are left untouched.
+pub fn upgrade(value: &mut Value, ref_type: &str) -> io::Result<Vec<String>> {
+    let fields = value.as_object_mut().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Metadata is not a JSON object")
+    })?;
+
+    let mut changes = Vec::new();
+    let mut version = fields
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as u32;
+
+    while version < SCHEMA_VERSION {
+        MIGRATIONS[version as usize](fields, ref_type, &mut changes);
+        version += 1;
+        fields.insert("schema_version".to_string(), Value::from(version));
+    }
+
+    Ok(changes)
+}
+
+/// Upgrade a sidecar on disk, keeping a copy of the original in `backup_dir`
+pub fn migrate_sidecar(
+    metadata_path: &Path,
+    backup_dir: Option<&Path>,
+    dry_run: bool,
+) -> io::Result<Option<MigrationEntry>> {
+    let ref_type = sidecar_kind(metadata_path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a metadata sidecar"))?;
+
+    // Read and rewrite under the lock so concurrent edits aren't overwritten with stale data
+    storage::with_ref_lock(metadata_path, || {
+        let metadata_json = fs::read_to_string(metadata_path)?;
+        let mut value: Value = serde_json::from_str(&metadata_json)?;
+        let from_version = schema_version(&value);
+
+        if from_version >= SCHEMA_VERSION {
+            return Ok(None);
+        }
+
+        let changes = upgrade(&mut value, ref_type)?;
+
+        if !dry_run {
+            if let Some(backup_dir) = backup_dir {
+                backup_sidecar(metadata_path, backup_dir)?;
+            }
+
+            storage::write_json(metadata_path, &value)?;
+        }
+
+        Ok(Some(MigrationEntry {
+            path: metadata_path.to_string_lossy().to_string(),
+            from_version,
+            to_version: SCHEMA_VERSION,
+            changes,
+        }))
+    })
+}
+
+/// Upgrade every sidecar of the collections directory.
+///
+/// Originals are copied into a timestamped folder of `backups_dir` before being rewritten,
+/// nothing is written at all when `dry_run` is set.
+pub fn migrate_library(
+    collections_dir: &Path,
+    backups_dir: &Path,
+    dry_run: bool,
+) -> io::Result<MigrationReport> {
+    let backup_dir = backups_dir.join(format!(
+        "migration_{}",
+        Local::now().format("%Y%m%d_%H%M%S")
+    ));
+
+    let mut report = MigrationReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    for ref_dir in utils::get_ref_dirs(collections_dir)? {
+        let sidecars = utils::get_ref_entries(&ref_dir)
+            .into_iter()
+            .filter(|path| sidecar_kind(path).is_some());
+
+        for sidecar in sidecars {
+            report.scanned += 1;
+
+            match migrate_sidecar(&sidecar, Some(&backup_dir), dry_run) {
+                Ok(Some(entry)) => report.migrated.push(entry),
+                Ok(None) => {}
+                Err(err) => report.failed.push(MigrationFailure {
+                    path: sidecar.to_string_lossy().to_string(),
+                    error: err.to_string(),
+                }),
+            }
+        }
+    }
+
+    if !dry_run && !report.migrated.is_empty() {
+        report.backup_dir = Some(backup_dir.to_string_lossy().to_string());
+    }
+
+    Ok(report)
+}
+
+/// Copy a sidecar into `backup_dir/<ref folder>/<file name>`
+fn backup_sidecar(metadata_path: &Path, backup_dir: &Path) -> io::Result<PathBuf> {
+    let ref_folder = metadata_path
+        .parent()
+        .and_then(Path::file_name)
+        .unwrap_or_default();
+    let file_name = metadata_path.file_name().unwrap_or_default();
+
+    let destination = backup_dir.join(ref_folder);
+    fs::create_dir_all(&destination)?;
+
+    let backup_path = destination.join(file_name);
+    fs::copy(metadata_path, &backup_path)?;
+
+    Ok(backup_path)
+}
+
+/// Normalise the fields that drifted between the unversioned metadata structs
+fn migrate_v0_to_v1(fields: &mut Map<String, Value>, ref_type: &str, changes: &mut Vec<String>) {
+    match fields.get("ref_type").and_then(Value::as_str) {
+        Some(current) if current == ref_type => {}
+        current => {
+            changes.push(format!(
+                "ref_type: {} -> {}",
+                current.unwrap_or("missing"),
+                ref_type
+            ));
+            fields.insert("ref_type".to_string(), Value::from(ref_type));
+        }
+    }
+
+    if let Some(size) = fields.get("file_size").and_then(Value::as_u64) {
+        changes.push(format!("file_size: {} -> {}", size, human_size(size)));
+        fields.insert("file_size".to_string(), Value::from(human_size(size)));
+    }
+
+    let mut defaults = vec![
+        ("name", Value::from("")),
+        ("note_text", Value::from("")),
+        ("tags", Value::Array(Vec::new())),
+    ];
+
+    if ref_type == "image" {
+        defaults.push(("colors", Value::Array(Vec::new())));
+    }
+
+    for (field, default) in defaults {
+        if fields.get(field).is_none_or(Value::is_null) {
+            changes.push(format!("{}: added", field));
+            fields.insert(field.to_string(), default);
+        }
+    }
+}
+
+/// Turn the hex strings of image palettes into colors with a share.
+///
+/// The shares were never stored, every color gets an equal part until the colors are extracted again.
+fn migrate_v1_to_v2(fields: &mut Map<String, Value>, ref_type: &str, changes: &mut Vec<String>) {
+    if ref_type != "image" {
+        return;
+    }
+
+    let Some(Value::Array(colors)) = fields.get_mut("colors") else {
+        return;
+    };
+
+    if !colors.iter().any(Value::is_string) {
+        return;
+    }
+
+    let percentage = 100.0 / colors.len() as f64;
+    for color in colors.iter_mut() {
+        if let Value::String(hex) = color {
+            *color = json!({ "hex": hex, "percentage": percentage });
+        }
+    }
+    changes.push("colors: added percentages".to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_upgrade_unversioned_doc() {
+        let mut value = json!({
+            "id": "DOCREF",
+            "name": "",
+            "ref_type": "link",
+            "collection": "all",
+            "created_at": "2024-04-08 19:33:05.857872355 +00:00",
+            "updated_at": "2024-04-08 19:33:05.857942555 +00:00",
+        });
+
+        let changes = upgrade(&mut value, "doc").unwrap();
+
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        assert_eq!(value["ref_type"], "doc");
+        assert_eq!(value["note_text"], "");
+        assert_eq!(value["tags"], json!([]));
+        assert!(changes.contains(&"ref_type: link -> doc".to_string()));
+
+        // Already migrated files are left alone
+        assert!(upgrade(&mut value, "doc").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_upgrade_image_colors() {
+        let mut value = json!({
+            "id": "IMAGEREF",
+            "ref_type": "image",
+            "schema_version": 1,
+            "colors": ["#dfdfdf", "#040304"],
+        });
+
+        let changes = upgrade(&mut value, "image").unwrap();
+        assert_eq!(changes, vec!["colors: added percentages".to_string()]);
+        assert_eq!(
+            value["colors"],
+            json!([
+                { "hex": "#dfdfdf", "percentage": 50.0 },
+                { "hex": "#040304", "percentage": 50.0 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_upgrade_numeric_file_size() {
+        let mut value = json!({
+            "id": "VIDEOREF",
+            "ref_type": "video",
+            "file_size": 59400,
+        });
+
+        upgrade(&mut value, "video").unwrap();
+        assert_eq!(value["file_size"], human_size(59400));
+    }
+
+    #[test]
+    fn test_migrate_library_dry_run_and_backup() {
+        let collections_dir = Path::new("test_migration_collections");
+        let backups_dir = Path::new("test_migration_backups");
+        let ref_dir = collections_dir.join("2Z4ED1XBNFMGV");
+        let sidecar = ref_dir.join("metadata.image.json");
+
+        fs::create_dir_all(&ref_dir).unwrap();
+        fs::copy("resources/metadata.image.json", &sidecar).unwrap();
+        let original = fs::read_to_string(&sidecar).unwrap();
+
+        let report = migrate_library(collections_dir, backups_dir, true).unwrap();
+        assert_eq!(report.migrated.len(), 1);
+        assert_eq!(fs::read_to_string(&sidecar).unwrap(), original);
+        assert!(!backups_dir.exists());
+
+        let report = migrate_library(collections_dir, backups_dir, false).unwrap();
+        assert_eq!(report.migrated.len(), 1);
+        let backup_dir = PathBuf::from(report.backup_dir.unwrap());
+        let backup = backup_dir.join("2Z4ED1XBNFMGV").join("metadata.image.json");
+        assert_eq!(fs::read_to_string(backup).unwrap(), original);
+
+        let migrated: Value = serde_json::from_str(&fs::read_to_string(&sidecar).unwrap()).unwrap();
+        assert_eq!(schema_version(&migrated), SCHEMA_VERSION);
+
+        fs::remove_dir_all(collections_dir).unwrap();
+        fs::remove_dir_all(backups_dir).unwrap();
+    }
+}
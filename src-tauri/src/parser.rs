@@ -4,9 +4,11 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use crate::migration;
 use crate::state::{AudioMetadata, AudioRef, LinkMetadata, LinkRef};
 use crate::state::{DocMetadata, DocRef, NoteMetadata, NoteRef};
 use crate::state::{ImageMetadata, ImageRef, Ref, Settings, VideoMetadata, VideoRef};
+use crate::thumbnail::{self, THUMBNAIL_DIR};
 use crate::utils::convert_file_src;
 
 /// Parse a pathbuffer array into a Ref struct
@@ -45,10 +47,14 @@ fn parse_image_ref(refs: &[PathBuf]) -> Result<Ref, io::Error> {
     let mut image_ref = ImageRef::default();
 
     for ref_path in refs {
+        if is_leftover(ref_path) {
+            continue;
+        }
+
         if let Some(file_name) = ref_path.file_name().and_then(|f| f.to_str()) {
             if file_name == "metadata.image.json" {
                 let json_txt = std::fs::read_to_string(ref_path)?;
-                let metadata = parse_metadata::<ImageMetadata>(&json_txt)?;
+                let metadata = parse_metadata::<ImageMetadata>(&json_txt, "image")?;
                 image_ref.metapath = ref_path
                     .to_str()
                     .ok_or_else(|| {
@@ -56,15 +62,24 @@ fn parse_image_ref(refs: &[PathBuf]) -> Result<Ref, io::Error> {
                     })?
                     .to_string();
                 image_ref.metadata = Some(metadata);
+            } else if file_name == THUMBNAIL_DIR {
+                if let Some(ref_dir) = ref_path.parent() {
+                    image_ref.thumbnails = thumbnail::list_thumbnails(ref_dir);
+                    image_ref.animated_preview = thumbnail::animated_preview(ref_dir);
+                }
             } else if file_name.starts_with("lower_") {
+                // Thumbnail written before the thumbnails folder existed
                 image_ref.low_res_imagepath = convert_file_src(ref_path);
             } else {
                 image_ref.image_path = convert_file_src(ref_path);
+                image_ref.unsupported_format = thumbnail::is_unsupported_image(file_name);
             }
         }
     }
 
-    if image_ref.low_res_imagepath.is_empty() {
+    if let Some(preview) = thumbnail::preview(&image_ref.thumbnails) {
+        image_ref.low_res_imagepath = preview.path.clone();
+    } else if image_ref.low_res_imagepath.is_empty() && !image_ref.unsupported_format {
         image_ref.low_res_imagepath = image_ref.image_path.clone();
     }
 
@@ -76,10 +91,14 @@ fn parse_video_ref(refs: &[PathBuf]) -> Result<Ref, std::io::Error> {
     let mut video_ref = VideoRef::default();
 
     for ref_path in refs {
+        if is_leftover(ref_path) {
+            continue;
+        }
+
         if let Some(file_name) = ref_path.file_name().and_then(|f| f.to_str()) {
             if file_name == "metadata.video.json" {
                 let json_txt = std::fs::read_to_string(ref_path)?;
-                let metadata = parse_metadata::<VideoMetadata>(&json_txt)?;
+                let metadata = parse_metadata::<VideoMetadata>(&json_txt, "video")?;
 
                 video_ref.metapath = ref_path
                     .to_str()
@@ -88,12 +107,19 @@ fn parse_video_ref(refs: &[PathBuf]) -> Result<Ref, std::io::Error> {
                     })?
                     .to_string();
                 video_ref.metadata = Some(metadata);
+            } else if file_name == THUMBNAIL_DIR {
+                if let Some(ref_dir) = ref_path.parent() {
+                    video_ref.thumbnails = thumbnail::list_thumbnails(ref_dir);
+                }
             } else {
                 video_ref.video_path = convert_file_src(ref_path);
             }
         }
     }
 
+    video_ref.poster =
+        thumbnail::preview(&video_ref.thumbnails).map(|preview| preview.path.clone());
+
     Ok(Ref::Video(video_ref))
 }
 
@@ -102,17 +128,30 @@ fn parse_audio_ref(refs: &[PathBuf]) -> Result<Ref, std::io::Error> {
     let mut audio_ref = AudioRef::default();
 
     for ref_path in refs {
+        if is_leftover(ref_path) {
+            continue;
+        }
+
         if ref_path.file_name().unwrap() == "metadata.audio.json" {
             let json_txt = std::fs::read_to_string(ref_path)?;
-            let metadata = parse_metadata::<AudioMetadata>(&json_txt)?;
+            let metadata = parse_metadata::<AudioMetadata>(&json_txt, "audio")?;
             audio_ref.metapath = ref_path.to_str().unwrap().to_string();
             audio_ref.metadata = Some(metadata);
             continue;
         }
 
+        if ref_path.file_name().unwrap() == THUMBNAIL_DIR {
+            if let Some(ref_dir) = ref_path.parent() {
+                audio_ref.thumbnails = thumbnail::list_thumbnails(ref_dir);
+            }
+            continue;
+        }
+
         audio_ref.audio_path = convert_file_src(ref_path);
     }
 
+    audio_ref.cover = thumbnail::preview(&audio_ref.thumbnails).map(|preview| preview.path.clone());
+
     Ok(Ref::Audio(audio_ref))
 }
 
@@ -123,7 +162,7 @@ fn parse_note_ref(refs: &[PathBuf]) -> Result<Ref, std::io::Error> {
     for ref_path in refs {
         if ref_path.file_name().unwrap() == "metadata.note.json" {
             let json_txt = std::fs::read_to_string(ref_path)?;
-            let metadata = parse_metadata::<NoteMetadata>(&json_txt)?;
+            let metadata = parse_metadata::<NoteMetadata>(&json_txt, "note")?;
             note_ref.metapath = ref_path.to_str().unwrap().to_string();
             note_ref.metadata = Some(metadata);
         }
@@ -140,9 +179,13 @@ fn parse_link_ref(refs: &[PathBuf]) -> Result<Ref, std::io::Error> {
     let mut link_ref = LinkRef::default();
 
     for ref_path in refs {
+        if is_leftover(ref_path) {
+            continue;
+        }
+
         if ref_path.file_name().unwrap() == "metadata.link.json" {
             let json_txt = std::fs::read_to_string(ref_path)?;
-            let metadata = parse_metadata::<LinkMetadata>(&json_txt)?;
+            let metadata = parse_metadata::<LinkMetadata>(&json_txt, "link")?;
             link_ref.metapath = ref_path.to_str().unwrap().to_string();
             link_ref.metadata = Some(metadata);
             continue;
@@ -158,9 +201,13 @@ fn parse_doc_ref(refs: &[PathBuf]) -> Result<Ref, std::io::Error> {
     let mut doc_ref = DocRef::default();
 
     for ref_path in refs {
+        if is_leftover(ref_path) {
+            continue;
+        }
+
         if ref_path.file_name().unwrap() == "metadata.doc.json" {
             let json_txt = std::fs::read_to_string(ref_path)?;
-            let metadata = parse_metadata::<DocMetadata>(&json_txt)?;
+            let metadata = parse_metadata::<DocMetadata>(&json_txt, "doc")?;
             doc_ref.metapath = ref_path.to_str().unwrap().to_string();
             doc_ref.metadata = Some(metadata);
             continue;
@@ -172,8 +219,83 @@ fn parse_doc_ref(refs: &[PathBuf]) -> Result<Ref, std::io::Error> {
     Ok(Ref::Doc(doc_ref))
 }
 
-/// parse a media metadata file
-fn parse_metadata<'de, T: serde::Deserialize<'de>>(str: &'de str) -> Result<T, std::io::Error> {
-    let metadata: T = serde_json::from_str(str)?;
+/// Hidden files and temporary files left by an interrupted atomic write, never the media
+fn is_leftover(ref_path: &Path) -> bool {
+    ref_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.') || name.ends_with(".tmp"))
+}
+
+/// parse a media metadata file, upgrading older schema versions in memory
+fn parse_metadata<T: serde::de::DeserializeOwned>(
+    str: &str,
+    ref_type: &str,
+) -> Result<T, std::io::Error> {
+    let mut value: serde_json::Value = serde_json::from_str(str)?;
+    migration::upgrade(&mut value, ref_type)?;
+    let metadata: T = serde_json::from_value(value)?;
     Ok(metadata)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_skip_leftover_files() {
+        let ref_dir = PathBuf::from("test_skip_leftover_files");
+        fs::create_dir_all(&ref_dir).unwrap();
+        fs::copy("resources/test_image.png", ref_dir.join("test_image.png")).unwrap();
+        fs::copy(
+            "resources/metadata.image.json",
+            ref_dir.join("metadata.image.json"),
+        )
+        .unwrap();
+        // Half written sidecar left behind by a crash
+        fs::write(ref_dir.join(".metadata.image.json.4242-0.tmp"), "{\"id\":").unwrap();
+
+        let mut entries: Vec<PathBuf> = fs::read_dir(&ref_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        // Whatever order the folder is listed in, the temp file comes last
+        entries.sort_by_key(|entry| is_leftover(entry));
+
+        let parsed = parse_refs(&entries);
+        fs::remove_dir_all(&ref_dir).unwrap();
+
+        let Ok(Ref::Image(image_ref)) = parsed else {
+            panic!("Failed to parse the image ref");
+        };
+        assert!(image_ref.image_path.ends_with("test_image.png"));
+    }
+
+    #[test]
+    fn test_unsupported_image_format() {
+        let ref_dir = PathBuf::from("test_unsupported_image_format");
+        fs::create_dir_all(&ref_dir).unwrap();
+        fs::write(ref_dir.join("photo.heic"), b"\0\0\0\x18ftypheic").unwrap();
+        fs::copy(
+            "resources/metadata.image.json",
+            ref_dir.join("metadata.image.json"),
+        )
+        .unwrap();
+
+        let entries: Vec<PathBuf> = fs::read_dir(&ref_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        let parsed = parse_refs(&entries);
+        fs::remove_dir_all(&ref_dir).unwrap();
+
+        let Ok(Ref::Image(image_ref)) = parsed else {
+            panic!("Failed to parse the image ref");
+        };
+        // Without a decoder there is no thumbnail, and the original can't be shown instead
+        let unsupported = !cfg!(feature = "heif");
+        assert_eq!(image_ref.unsupported_format, unsupported);
+        assert_eq!(image_ref.low_res_imagepath.is_empty(), unsupported);
+    }
+}
@@ -0,0 +1,240 @@
+use image::{DynamicImage, ImageFormat};
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use crate::exif::Tiff;
+
+/// Extensions of the camera RAW formats previewed through their embedded JPEG
+pub const RAW_EXTENSIONS: [&str; 9] = [
+    "arw", "cr2", "dng", "nef", "nrw", "pef", "raf", "sr2", "srw",
+];
+
+/// Media type of RAW formats without a registered one
+pub const RAW_MEDIA_TYPE: &str = "image/x-raw";
+
+/// Fujifilm RAW files are not TIFF based, their header points to the preview
+pub const RAF_SIGNATURE: &[u8] = b"FUJIFILMCCD-RAW";
+
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+/// Most IFDs visited in a file, guards against looping chains
+const MAX_IFDS: usize = 64;
+
+/// Whether a file is a camera RAW, from its extension
+pub fn is_raw(file_path: &Path) -> bool {
+    file_path.extension().is_some_and(|extension| {
+        RAW_EXTENSIONS
+            .iter()
+            .any(|raw| extension.eq_ignore_ascii_case(raw))
+    })
+}
+
+/// Largest JPEG preview a RAW file embeds that a regular decoder can read
+pub fn embedded_preview(data: &[u8]) -> Option<&[u8]> {
+    if data.starts_with(RAF_SIGNATURE) {
+        let offset = u32::from_be_bytes(data.get(84..88)?.try_into().ok()?) as usize;
+        let length = u32::from_be_bytes(data.get(88..92)?.try_into().ok()?) as usize;
+        return data
+            .get(offset..offset.checked_add(length)?)
+            .filter(|jpeg| is_displayable_jpeg(jpeg));
+    }
+
+    let tiff = Tiff::new(data)?;
+    let mut pending: Vec<usize> = tiff.first_ifd().into_iter().collect();
+    let mut visited = Vec::new();
+    let mut previews = Vec::new();
+
+    // Previews hide in the IFD chain (Canon, Sony) or in sub IFDs (Nikon, DNG)
+    while let Some(ifd) = pending.pop() {
+        if visited.contains(&ifd) || visited.len() >= MAX_IFDS {
+            continue;
+        }
+        visited.push(ifd);
+        pending.extend(tiff.next_ifd(ifd));
+
+        let entries = tiff.ifd(ifd);
+        let values = |tag: u16| -> Vec<u32> {
+            entries
+                .iter()
+                .find(|entry| entry.tag == tag)
+                .map_or_else(Vec::new, |entry| tiff.uints(entry))
+        };
+
+        pending.extend(
+            values(TAG_SUB_IFDS)
+                .into_iter()
+                .map(|offset| offset as usize),
+        );
+
+        // A preview is either a single strip or a JPEG interchange stream
+        for (offsets, lengths) in [
+            (values(TAG_STRIP_OFFSETS), values(TAG_STRIP_BYTE_COUNTS)),
+            (values(TAG_JPEG_OFFSET), values(TAG_JPEG_LENGTH)),
+        ] {
+            if let ([offset], [length]) = (&offsets[..], &lengths[..]) {
+                let (offset, length) = (*offset as usize, *length as usize);
+                previews.extend(data.get(offset..offset + length));
+            }
+        }
+    }
+
+    previews
+        .into_iter()
+        .filter(|jpeg| is_displayable_jpeg(jpeg))
+        .max_by_key(|jpeg| jpeg.len())
+}
+
+/// Decode the embedded preview of a RAW file
+pub fn decode_preview(data: &[u8]) -> io::Result<DynamicImage> {
+    let preview = embedded_preview(data).ok_or_else(no_preview)?;
+    image::load_from_memory_with_format(preview, ImageFormat::Jpeg).map_err(io::Error::other)
+}
+
+/// Dimensions of the embedded preview of a RAW file
+pub fn preview_dimensions(data: &[u8]) -> io::Result<(u32, u32)> {
+    let preview = embedded_preview(data).ok_or_else(no_preview)?;
+    image::io::Reader::with_format(Cursor::new(preview), ImageFormat::Jpeg)
+        .into_dimensions()
+        .map_err(io::Error::other)
+}
+
+fn no_preview() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The RAW file has no embedded preview",
+    )
+}
+
+/// Whether a buffer is a baseline or progressive JPEG, the sensor data of some RAW formats is
+/// stored as lossless JPEG which image viewers cannot decode
+fn is_displayable_jpeg(data: &[u8]) -> bool {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return false;
+    }
+
+    let mut pos = 2;
+    while let (Some(0xFF), Some(&marker)) = (data.get(pos), data.get(pos + 1)) {
+        match marker {
+            0xC0..=0xC2 => return true,
+            // Other start of frame markers, or the scan started without any
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF | 0xDA => return false,
+            _ => {}
+        }
+        let Some(length) = data.get(pos + 2..pos + 4) else {
+            return false;
+        };
+        pos += 2 + u16::from_be_bytes([length[0], length[1]]) as usize;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::new_rgb8(width, height)
+            .write_to(
+                &mut Cursor::new(&mut bytes),
+                image::ImageOutputFormat::Jpeg(80),
+            )
+            .unwrap();
+        bytes
+    }
+
+    /// Little endian TIFF shaped like a CR2: IFD0 holds a large preview strip and is rotated,
+    /// IFD1 a small JPEG thumbnail, and a sub IFD a lossless JPEG standing for the sensor data
+    fn raw_file() -> Vec<u8> {
+        let large = jpeg(40, 20);
+        let small = jpeg(8, 4);
+        let lossless = vec![0xFF, 0xD8, 0xFF, 0xC3, 0, 2, 0xFF, 0xD9];
+
+        let entry = |tag: u16, kind: u16, value: u32| {
+            let mut bytes = tag.to_le_bytes().to_vec();
+            bytes.extend_from_slice(&kind.to_le_bytes());
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+            bytes
+        };
+
+        // Header, IFD0 with 4 entries at 8, IFD1 with 2 at 62, sub IFD with 2 at 92
+        let ifd0 = 8;
+        let ifd1 = ifd0 + 2 + 4 * 12 + 4;
+        let sub_ifd = ifd1 + 2 + 2 * 12 + 4;
+        let large_offset = sub_ifd + 2 + 2 * 12 + 4;
+        let small_offset = large_offset + large.len();
+        let lossless_offset = small_offset + small.len();
+
+        let mut data = b"II*\0".to_vec();
+        data.extend_from_slice(&(ifd0 as u32).to_le_bytes());
+
+        data.extend_from_slice(&4u16.to_le_bytes());
+        data.extend(entry(0x0112, 3, 6));
+        data.extend(entry(TAG_STRIP_OFFSETS, 4, large_offset as u32));
+        data.extend(entry(TAG_STRIP_BYTE_COUNTS, 4, large.len() as u32));
+        data.extend(entry(TAG_SUB_IFDS, 4, sub_ifd as u32));
+        data.extend_from_slice(&(ifd1 as u32).to_le_bytes());
+
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend(entry(TAG_JPEG_OFFSET, 4, small_offset as u32));
+        data.extend(entry(TAG_JPEG_LENGTH, 4, small.len() as u32));
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend(entry(TAG_STRIP_OFFSETS, 4, lossless_offset as u32));
+        data.extend(entry(TAG_STRIP_BYTE_COUNTS, 4, lossless.len() as u32));
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        data.extend(large);
+        data.extend(small);
+        data.extend(lossless);
+        data
+    }
+
+    #[test]
+    fn test_embedded_preview() {
+        let data = raw_file();
+        assert_eq!(preview_dimensions(&data).unwrap(), (40, 20));
+        assert_eq!(decode_preview(&data).unwrap().width(), 40);
+
+        let mut raf = RAF_SIGNATURE.to_vec();
+        raf.resize(92, 0);
+        let preview = jpeg(16, 8);
+        raf[84..88].copy_from_slice(&92u32.to_be_bytes());
+        raf[88..92].copy_from_slice(&(preview.len() as u32).to_be_bytes());
+        raf.extend(preview);
+        assert_eq!(preview_dimensions(&raf).unwrap(), (16, 8));
+
+        let error = decode_preview(b"II*\0\x08\0\0\0\0\0").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert!(!is_displayable_jpeg(&[0xFF, 0xD8, 0xFF, 0xC3, 0, 2]));
+    }
+
+    #[test]
+    fn test_raw_media() {
+        let base_path = Path::new("test_raw_media");
+        fs::create_dir_all(base_path).unwrap();
+        let raw_path = base_path.join("photo.CR2");
+        fs::write(&raw_path, raw_file()).unwrap();
+
+        assert!(is_raw(&raw_path));
+        assert!(crate::media::determine_media_type(&raw_path).starts_with("image/"));
+        // The preview is shown with the orientation of the RAW file
+        assert_eq!(crate::media::analyze_dimensions(&raw_path), Some((20, 40)));
+        let image = crate::media::open_image(&raw_path).unwrap();
+        assert_eq!((image.width(), image.height()), (20, 40));
+
+        let options = crate::colors::PaletteOptions::default();
+        let colors = crate::media::extract_colors(&raw_path, &options).unwrap();
+        assert_eq!(colors[0].hex, "#000000");
+
+        fs::remove_dir_all(base_path).unwrap();
+    }
+}
@@ -1,15 +1,38 @@
-use crate::config::{get_collection_path, get_settings_path};
+use crate::animation::{self, Animation};
+use crate::audio::{self, AudioInfo};
+use crate::colors::{PaletteColor, PaletteOptions};
+use crate::config::{
+    get_backup_path, get_collection_path, get_index_path, get_jobs_path, get_settings_path,
+    get_trash_path,
+};
+use crate::exif::{self, ExifData};
+use crate::index::LibraryIndex;
+use crate::jobs::{self, JobQueue};
+use crate::migration::{self, SCHEMA_VERSION};
+use crate::thumbnail::{self, Thumbnail, ThumbnailFormat};
+use crate::timeline::Timeline;
 use crate::utils::convert_file_src;
-use crate::{media, utils};
+use crate::video::{self, FrameSource, VideoInfo};
+use crate::{media, trash, utils};
 use chrono::Local;
+use log::info;
 use serde::{Deserialize, Serialize};
 use std::clone::Clone;
 use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use tauri::AppHandle;
+use tauri::{AppHandle, Manager};
 
 pub trait Metadata {
+    fn get_name(&self) -> &str;
+    fn get_collection(&self) -> &str;
+    fn get_tags(&self) -> &[String];
+    fn get_created_at(&self) -> &str;
+    fn get_updated_at(&self) -> &str;
+    /// Hash of the media file, only file backed refs have one
+    fn get_content_hash(&self) -> Option<&str> {
+        None
+    }
     fn set_name(&mut self, new_name: &str);
     fn add_tag(&mut self, tag: &str);
     fn remove_tag(&mut self, tag: &str);
@@ -20,6 +43,14 @@ pub trait Metadata {
 pub struct ImageRef {
     pub image_path: String,
     pub low_res_imagepath: String,
+    #[serde(default)]
+    pub thumbnails: Vec<Thumbnail>,
+    /// Downscaled animation shown on boards for animated images
+    #[serde(default)]
+    pub animated_preview: Option<String>,
+    /// The format can't be decoded by this build, boards show a placeholder instead
+    #[serde(default)]
+    pub unsupported_format: bool,
     pub metadata: Option<ImageMetadata>,
     pub metapath: String,
 }
@@ -27,6 +58,11 @@ pub struct ImageRef {
 #[derive(Serialize, Debug, Deserialize, Clone, Default)]
 pub struct VideoRef {
     pub video_path: String,
+    #[serde(default)]
+    pub thumbnails: Vec<Thumbnail>,
+    /// Frame shown on boards before the video plays
+    #[serde(default)]
+    pub poster: Option<String>,
     pub metadata: Option<VideoMetadata>,
     pub metapath: String,
 }
@@ -34,6 +70,11 @@ pub struct VideoRef {
 #[derive(Serialize, Debug, Deserialize, Clone, Default)]
 pub struct AudioRef {
     pub audio_path: String,
+    #[serde(default)]
+    pub thumbnails: Vec<Thumbnail>,
+    /// Embedded cover art shown on boards
+    #[serde(default)]
+    pub cover: Option<String>,
     pub metadata: Option<AudioMetadata>,
     pub metapath: String,
 }
@@ -61,6 +102,8 @@ pub struct DocRef {
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct ImageMetadata {
+    #[serde(default)]
+    pub schema_version: u32,
     pub id: String,
     pub name: String,
     pub file_name: String,
@@ -69,8 +112,21 @@ pub struct ImageMetadata {
     pub dimensions: Option<(u32, u32)>,
     #[serde(deserialize_with = "utils::deserialize_file_size")]
     pub file_size: String,
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    #[serde(default)]
+    pub perceptual_hash: Option<String>,
+    /// Embedded camera details, `None` until the file has been read
+    #[serde(default)]
+    pub exif: Option<Box<ExifData>>,
+    /// Frames, loops and duration of animated GIF, APNG and WebP images
+    #[serde(default)]
+    pub animation: Option<Animation>,
+    /// Video the image was captured from, for frames grabbed from video refs
+    #[serde(default)]
+    pub source_video: Option<FrameSource>,
     pub collection: String,
-    pub colors: Vec<String>,
+    pub colors: Vec<PaletteColor>,
     pub created_at: String,
     pub updated_at: String,
     #[serde(default)]
@@ -81,6 +137,8 @@ pub struct ImageMetadata {
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct VideoMetadata {
+    #[serde(default)]
+    pub schema_version: u32,
     pub id: String,
     pub name: String,
     pub file_name: String,
@@ -88,7 +146,17 @@ pub struct VideoMetadata {
     pub media_type: String,
     #[serde(deserialize_with = "utils::deserialize_file_size")]
     pub file_size: String,
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// Duration, resolution and codecs, `None` for containers that could not be read
+    #[serde(default)]
+    pub video: Option<VideoInfo>,
     pub collection: String,
+    /// Palette of the poster frame
+    #[serde(default)]
+    pub colors: Vec<PaletteColor>,
+    #[serde(default)]
+    pub timeline: Timeline,
     pub created_at: String,
     pub updated_at: String,
     #[serde(default)]
@@ -99,6 +167,8 @@ pub struct VideoMetadata {
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct AudioMetadata {
+    #[serde(default)]
+    pub schema_version: u32,
     pub id: String,
     pub name: String,
     pub file_name: String,
@@ -106,7 +176,17 @@ pub struct AudioMetadata {
     pub media_type: String,
     #[serde(deserialize_with = "utils::deserialize_file_size")]
     pub file_size: String,
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// Tags, duration and stream details, `None` for files that could not be read
+    #[serde(default)]
+    pub audio: Option<AudioInfo>,
     pub collection: String,
+    /// Palette of the cover art
+    #[serde(default)]
+    pub colors: Vec<PaletteColor>,
+    #[serde(default)]
+    pub timeline: Timeline,
     pub created_at: String,
     pub updated_at: String,
     #[serde(default)]
@@ -117,6 +197,8 @@ pub struct AudioMetadata {
 
 #[derive(Serialize, Debug, Deserialize, Clone, Default)]
 pub struct NoteMetadata {
+    #[serde(default)]
+    pub schema_version: u32,
     pub id: String,
     pub name: String,
     pub ref_type: String,
@@ -129,6 +211,8 @@ pub struct NoteMetadata {
 
 #[derive(Serialize, Debug, Deserialize, Clone, Default)]
 pub struct LinkMetadata {
+    #[serde(default)]
+    pub schema_version: u32,
     pub id: String,
     pub name: String,
     pub ref_type: String,
@@ -142,9 +226,13 @@ pub struct LinkMetadata {
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct DocMetadata {
+    #[serde(default)]
+    pub schema_version: u32,
     pub id: String,
     pub name: String,
     pub ref_type: String,
+    #[serde(default)]
+    pub content_hash: Option<String>,
     pub collection: String,
     pub created_at: String,
     pub updated_at: String,
@@ -155,13 +243,27 @@ pub struct DocMetadata {
 impl ImageRef {
     pub fn new(
         imagepath: &Path,
-        lw_rs_imagpath: String,
+        thumbnails: Vec<Thumbnail>,
         metadata: ImageMetadata,
         metapath: PathBuf,
     ) -> Result<Self, String> {
+        let image_path = convert_file_src(imagepath);
+        let unsupported_format = imagepath
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .is_some_and(thumbnail::is_unsupported_image);
+        let low_res_imagepath = match thumbnail::preview(&thumbnails) {
+            Some(preview) => preview.path.clone(),
+            None if unsupported_format => String::new(),
+            None => image_path.clone(),
+        };
+
         Ok(Self {
-            image_path: convert_file_src(imagepath),
-            low_res_imagepath: lw_rs_imagpath,
+            image_path,
+            low_res_imagepath,
+            thumbnails,
+            animated_preview: imagepath.parent().and_then(thumbnail::animated_preview),
+            unsupported_format,
             metadata: Some(metadata),
             metapath: metapath.to_str().unwrap().to_string(),
         })
@@ -171,11 +273,14 @@ impl ImageRef {
 impl VideoRef {
     pub fn new(
         videopath: &Path,
+        thumbnails: Vec<Thumbnail>,
         metadata: VideoMetadata,
         metapath: PathBuf,
     ) -> Result<Self, String> {
         Ok(Self {
             video_path: convert_file_src(videopath),
+            poster: thumbnail::preview(&thumbnails).map(|preview| preview.path.clone()),
+            thumbnails,
             metadata: Some(metadata),
             metapath: metapath.to_str().unwrap().to_string(),
         })
@@ -185,11 +290,14 @@ impl VideoRef {
 impl AudioRef {
     pub fn new(
         audiopath: &Path,
+        thumbnails: Vec<Thumbnail>,
         metadata: AudioMetadata,
         metapath: PathBuf,
     ) -> Result<Self, String> {
         Ok(Self {
             audio_path: convert_file_src(audiopath),
+            cover: thumbnail::preview(&thumbnails).map(|preview| preview.path.clone()),
+            thumbnails,
             metadata: Some(metadata),
             metapath: metapath.to_str().unwrap().to_string(),
         })
@@ -233,6 +341,7 @@ impl ImageMetadata {
         collection: &str,
     ) -> Result<Self, String> {
         Ok(Self {
+            schema_version: SCHEMA_VERSION,
             id,
             name: String::new(),
             file_name: file_name.to_string(),
@@ -240,6 +349,11 @@ impl ImageMetadata {
             media_type: media::determine_media_type(file_name),
             dimensions: media::analyze_dimensions(media_path),
             file_size: utils::analyze_file_size(media_path),
+            content_hash: None,
+            perceptual_hash: media::perceptual_hash(media_path),
+            exif: exif::read_metadata(media_path).ok().map(Box::new),
+            animation: animation::read_animation(media_path).ok().flatten(),
+            source_video: None,
             collection: collection.to_string(),
             colors: Vec::new(),
             note_text: String::new(),
@@ -248,6 +362,15 @@ impl ImageMetadata {
             tags: Vec::new(),
         })
     }
+
+    /// Add embedded keywords to the tags, keywords already used as tags are skipped
+    pub fn add_keywords(&mut self, keywords: &[String]) {
+        for keyword in keywords {
+            if !self.tags.contains(keyword) {
+                self.tags.push(keyword.clone());
+            }
+        }
+    }
 }
 
 impl VideoMetadata {
@@ -258,19 +381,29 @@ impl VideoMetadata {
         collection: &str,
     ) -> Result<Self, String> {
         Ok(Self {
+            schema_version: SCHEMA_VERSION,
             id,
             name: String::new(),
             file_name: file_name.to_string(),
             ref_type: "video".to_string(),
             media_type: media::determine_media_type(file_name),
             file_size: utils::analyze_file_size(media_path),
+            content_hash: None,
+            video: video::read_video_info(media_path).ok(),
             collection: collection.to_string(),
+            colors: Vec::new(),
+            timeline: Timeline::default(),
             note_text: String::new(),
             created_at: Local::now().to_string(),
             updated_at: Local::now().to_string(),
             tags: Vec::new(),
         })
     }
+
+    /// Duration in seconds, when the container could be read
+    pub fn duration(&self) -> Option<f64> {
+        self.video.as_ref().and_then(|info| info.duration)
+    }
 }
 
 impl AudioMetadata {
@@ -281,24 +414,49 @@ impl AudioMetadata {
         collection: &str,
     ) -> Result<Self, String> {
         Ok(Self {
+            schema_version: SCHEMA_VERSION,
             id,
             name: String::new(),
             file_name: file_name.to_string(),
             ref_type: "audio".to_string(),
             media_type: media::determine_media_type(file_name),
             file_size: utils::analyze_file_size(media_path),
+            content_hash: None,
+            audio: audio::read_audio(media_path).ok().map(|tags| tags.info),
             collection: collection.to_string(),
+            colors: Vec::new(),
+            timeline: Timeline::default(),
             note_text: String::new(),
             created_at: Local::now().to_string(),
             updated_at: Local::now().to_string(),
             tags: Vec::new(),
         })
     }
+
+    /// Duration in seconds, when the file could be read
+    pub fn duration(&self) -> Option<f64> {
+        self.audio.as_ref().and_then(|info| info.duration)
+    }
+
+    /// Add the genres of the file to the tags, genres already used as tags are skipped
+    pub fn add_genres(&mut self) {
+        let genres = self
+            .audio
+            .as_ref()
+            .map(|info| info.genres.clone())
+            .unwrap_or_default();
+        for genre in genres {
+            if !self.tags.contains(&genre) {
+                self.tags.push(genre);
+            }
+        }
+    }
 }
 
 impl LinkMetadata {
     pub fn new(id: &str, source_uri: &str, collection: &str) -> Result<Self, String> {
         Ok(Self {
+            schema_version: SCHEMA_VERSION,
             id: id.to_string(),
             name: String::new(),
             source_uri: source_uri.to_string(),
@@ -315,9 +473,11 @@ impl LinkMetadata {
 impl DocMetadata {
     pub fn new(id: String, collection: &str) -> Result<Self, String> {
         Ok(Self {
+            schema_version: SCHEMA_VERSION,
             id,
             name: String::new(),
-            ref_type: "link".to_string(),
+            ref_type: "doc".to_string(),
+            content_hash: None,
             collection: collection.to_string(),
             note_text: String::new(),
             created_at: Local::now().to_string(),
@@ -328,6 +488,30 @@ impl DocMetadata {
 }
 
 impl Metadata for ImageMetadata {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_collection(&self) -> &str {
+        &self.collection
+    }
+
+    fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn get_created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    fn get_updated_at(&self) -> &str {
+        &self.updated_at
+    }
+
+    fn get_content_hash(&self) -> Option<&str> {
+        self.content_hash.as_deref()
+    }
+
     fn set_name(&mut self, new_name: &str) {
         self.name = new_name.to_string();
         self.updated_at = Local::now().to_string();
@@ -349,6 +533,30 @@ impl Metadata for ImageMetadata {
 }
 
 impl Metadata for VideoMetadata {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_collection(&self) -> &str {
+        &self.collection
+    }
+
+    fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn get_created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    fn get_updated_at(&self) -> &str {
+        &self.updated_at
+    }
+
+    fn get_content_hash(&self) -> Option<&str> {
+        self.content_hash.as_deref()
+    }
+
     fn set_name(&mut self, new_name: &str) {
         self.name = new_name.to_string();
         self.updated_at = Local::now().to_string();
@@ -370,6 +578,30 @@ impl Metadata for VideoMetadata {
 }
 
 impl Metadata for AudioMetadata {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_collection(&self) -> &str {
+        &self.collection
+    }
+
+    fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn get_created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    fn get_updated_at(&self) -> &str {
+        &self.updated_at
+    }
+
+    fn get_content_hash(&self) -> Option<&str> {
+        self.content_hash.as_deref()
+    }
+
     fn set_name(&mut self, new_name: &str) {
         self.name = new_name.to_string();
         self.updated_at = Local::now().to_string();
@@ -393,6 +625,7 @@ impl Metadata for AudioMetadata {
 impl NoteMetadata {
     pub fn new(id: &str, collection: &str) -> Result<Self, String> {
         Ok(Self {
+            schema_version: SCHEMA_VERSION,
             id: id.to_string(),
             name: String::new(),
             ref_type: "note".to_string(),
@@ -406,6 +639,26 @@ impl NoteMetadata {
 }
 
 impl Metadata for NoteMetadata {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_collection(&self) -> &str {
+        &self.collection
+    }
+
+    fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn get_created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    fn get_updated_at(&self) -> &str {
+        &self.updated_at
+    }
+
     fn set_name(&mut self, new_name: &str) {
         self.name = new_name.to_string();
         self.updated_at = Local::now().to_string();
@@ -427,6 +680,26 @@ impl Metadata for NoteMetadata {
 }
 
 impl Metadata for LinkMetadata {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_collection(&self) -> &str {
+        &self.collection
+    }
+
+    fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn get_created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    fn get_updated_at(&self) -> &str {
+        &self.updated_at
+    }
+
     fn set_name(&mut self, new_name: &str) {
         self.name = new_name.to_string();
         self.updated_at = Local::now().to_string();
@@ -448,6 +721,30 @@ impl Metadata for LinkMetadata {
 }
 
 impl Metadata for DocMetadata {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_collection(&self) -> &str {
+        &self.collection
+    }
+
+    fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn get_created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    fn get_updated_at(&self) -> &str {
+        &self.updated_at
+    }
+
+    fn get_content_hash(&self) -> Option<&str> {
+        self.content_hash.as_deref()
+    }
+
     fn set_name(&mut self, new_name: &str) {
         self.name = new_name.to_string();
         self.updated_at = Local::now().to_string();
@@ -504,6 +801,39 @@ impl Ref {
         }
     }
 
+    pub fn get_ref_type(&self) -> &'static str {
+        match self {
+            Ref::Image(_) => "image",
+            Ref::Video(_) => "video",
+            Ref::Audio(_) => "audio",
+            Ref::Note(_) => "note",
+            Ref::Link(_) => "link",
+            Ref::Doc(_) => "doc",
+        }
+    }
+
+    pub fn get_metapath(&self) -> &str {
+        match self {
+            Ref::Image(ref image_ref) => &image_ref.metapath,
+            Ref::Video(ref video_ref) => &video_ref.metapath,
+            Ref::Audio(ref audio_ref) => &audio_ref.metapath,
+            Ref::Note(ref note_ref) => &note_ref.metapath,
+            Ref::Link(ref link_ref) => &link_ref.metapath,
+            Ref::Doc(ref doc_ref) => &doc_ref.metapath,
+        }
+    }
+
+    pub fn get_meta(&self) -> &dyn Metadata {
+        match self {
+            Ref::Image(ref image_ref) => image_ref.metadata.as_ref().unwrap(),
+            Ref::Video(ref video_ref) => video_ref.metadata.as_ref().unwrap(),
+            Ref::Audio(ref audio_ref) => audio_ref.metadata.as_ref().unwrap(),
+            Ref::Note(ref note_ref) => note_ref.metadata.as_ref().unwrap(),
+            Ref::Link(ref link_ref) => link_ref.metadata.as_ref().unwrap(),
+            Ref::Doc(ref doc_ref) => doc_ref.metadata.as_ref().unwrap(),
+        }
+    }
+
     pub fn get_ref_meta(&mut self) -> Result<&mut dyn Metadata, String> {
         match self {
             Ref::Image(ref mut image_ref) => Ok(image_ref.metadata.as_mut().unwrap()),
@@ -528,30 +858,127 @@ pub struct AppearanceSettings {
     pub video_ref_autoplay: bool,
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BehaviorSettings {
     pub sort_by: SortBy,
+    /// Days a removed ref stays in the trash before being purged, `0` keeps it forever
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
+    #[serde(default)]
+    pub thumbnail_format: ThumbnailFormat,
+    #[serde(default)]
+    pub palette: PaletteOptions,
+    /// Add the IPTC and XMP keywords of imported images to their tags
+    #[serde(default)]
+    pub import_keywords_as_tags: bool,
+    /// Add the genres of imported audio files to their tags
+    #[serde(default)]
+    pub import_genres_as_tags: bool,
+}
+
+impl Default for BehaviorSettings {
+    fn default() -> Self {
+        Self {
+            sort_by: SortBy::default(),
+            trash_retention_days: default_trash_retention_days(),
+            thumbnail_format: ThumbnailFormat::default(),
+            palette: PaletteOptions::default(),
+            import_keywords_as_tags: false,
+            import_genres_as_tags: false,
+        }
+    }
+}
+
+fn default_trash_retention_days() -> u32 {
+    30
 }
 
 #[derive(Clone, Serialize, Default, Deserialize, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum SortBy {
     #[default]
     CreationTime,
     ModificationTime,
+    /// Capture date of images, other refs use their creation date
+    CaptureTime,
 }
 
-pub fn init_media_ref(app_handle: &AppHandle) -> Mutex<Vec<Ref>> {
+pub fn init_library_index(app_handle: &AppHandle) -> LibraryIndex {
     let collections_dir = get_collection_path(app_handle);
-    match utils::fetch_refs(&collections_dir) {
-        Ok(refs) => refs,
-        Err(e) => {
-            eprintln!("Error initializing media references: {}", e);
-            Mutex::new(Vec::new())
+    let index = LibraryIndex::open(&get_index_path(app_handle)).unwrap_or_else(|e| {
+        eprintln!("Error opening library index, falling back to memory: {}", e);
+        LibraryIndex::open_in_memory().expect("Failed to create in-memory library index")
+    });
+
+    // Upgrade the sidecars once per schema version, the index remembers the last one applied
+    let library_version = index
+        .get_property("schema_version")
+        .ok()
+        .flatten()
+        .and_then(|version| version.parse::<u32>().ok())
+        .unwrap_or(0);
+
+    if library_version < SCHEMA_VERSION {
+        let backups_dir = get_backup_path(app_handle);
+        match migration::migrate_library(&collections_dir, &backups_dir, false) {
+            Ok(report) if report.failed.is_empty() => {
+                info!("Migrated {} metadata files", report.migrated.len());
+                let _ = index.set_property("schema_version", &SCHEMA_VERSION.to_string());
+            }
+            Ok(report) => {
+                eprintln!("Errors encountered during metadata migration:");
+                for failure in &report.failed {
+                    eprintln!(" - {}: {}", failure.path, failure.error);
+                }
+            }
+            Err(e) => eprintln!("Error migrating metadata files: {}", e),
         }
     }
+
+    if let Err(e) = index.sync(&collections_dir) {
+        eprintln!("Error initializing media references: {}", e);
+    }
+
+    index
 }
 
 pub fn init_settings(app_handle: &AppHandle) -> Mutex<Settings> {
     let settings = get_settings_path(app_handle);
     utils::fetch_settings(&settings)
 }
+
+pub fn init_job_queue(app_handle: &AppHandle) -> JobQueue {
+    let notify_handle = app_handle.clone();
+    let notify = move |job: &jobs::Job| {
+        let _ = notify_handle.emit_all("job-updated", job);
+    };
+
+    let jobs_dir = get_jobs_path(app_handle);
+    let queue = JobQueue::open(&jobs_dir, notify.clone()).unwrap_or_else(|e| {
+        eprintln!("Error loading the job queue, jobs won't be resumed: {}", e);
+        let fallback_dir =
+            std::env::temp_dir().join(format!("onlyrefs-jobs-{}", utils::random_id(8)));
+        JobQueue::open(&fallback_dir, notify).expect("Failed to create the job queue")
+    });
+
+    let run_handle = app_handle.clone();
+    queue.start(jobs::WORKERS, move |job, context| {
+        jobs::run_job(&run_handle, job, context)
+    });
+
+    queue
+}
+
+pub fn init_trash(app_handle: &AppHandle) {
+    let settings = app_handle.state::<Mutex<Settings>>();
+    let retention_days = settings
+        .lock()
+        .map(|settings| settings.behavior.trash_retention_days)
+        .unwrap_or_else(|_| default_trash_retention_days());
+
+    match trash::purge_expired(&get_trash_path(app_handle), retention_days) {
+        Ok(0) => {}
+        Ok(purged) => info!("Purged {} expired refs from the trash", purged),
+        Err(e) => eprintln!("Error purging the trash: {}", e),
+    }
+}
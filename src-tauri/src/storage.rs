@@ -0,0 +1,153 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+static REF_LOCKS: OnceLock<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> = OnceLock::new();
+
+/// Write a file so that readers either see the old or the new content, never a truncated one.
+///
+/// The content goes to a temporary file in the same directory, is flushed to disk,
+/// then renamed over the destination.
+pub fn write_atomic<C: AsRef<[u8]>>(path: &Path, contents: C) -> io::Result<()> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file path"))?;
+
+    let temp_path = dir.join(format!(
+        ".{}.{}-{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents.as_ref())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        sync_dir(dir);
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// Serialize `value` as pretty JSON and write it atomically
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let json_data = serde_json::to_string_pretty(value)?;
+    write_atomic(path, json_data)
+}
+
+/// Run `f` while holding the lock of the ref folder containing `path`.
+///
+/// Every read-modify-write of a ref's files goes through here so concurrent
+/// commands and background threads don't overwrite each other's changes.
+pub fn with_ref_lock<T, F>(path: &Path, f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T>,
+{
+    let key = ref_lock_key(path);
+    let locks = REF_LOCKS.get_or_init(|| Mutex::new(HashMap::new()));
+
+    let ref_lock = {
+        let mut locks = locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry(key.clone()).or_default().clone()
+    };
+
+    let result = {
+        let _guard = ref_lock.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    };
+
+    // Forget the lock once nobody else holds or waits on it
+    let mut locks = locks.lock().unwrap_or_else(|e| e.into_inner());
+    if Arc::strong_count(&ref_lock) == 2 {
+        locks.remove(&key);
+    }
+
+    result
+}
+
+fn ref_lock_key(path: &Path) -> PathBuf {
+    let folder = if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(path)
+    };
+    folder
+        .canonicalize()
+        .unwrap_or_else(|_| folder.to_path_buf())
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) {
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = Path::new("test_write_atomic");
+        fs::create_dir_all(dir).unwrap();
+        let file_path = dir.join("metadata.note.json");
+
+        write_atomic(&file_path, "first").unwrap();
+        write_atomic(&file_path, "second").unwrap();
+
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "second");
+        // No temporary file is left behind
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_ref_lock_serializes_updates() {
+        let dir = Path::new("test_ref_lock");
+        fs::create_dir_all(dir).unwrap();
+        let file_path = dir.join("counter.txt");
+        write_atomic(&file_path, "0").unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let file_path = file_path.clone();
+                thread::spawn(move || {
+                    with_ref_lock(&file_path, || {
+                        let count: u32 = fs::read_to_string(&file_path)?.parse().unwrap();
+                        write_atomic(&file_path, (count + 1).to_string())
+                    })
+                    .unwrap();
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "8");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+use palette::color_difference::Ciede2000;
+use palette::{white_point::D65, FromColor, Hsv, Lab, Srgb};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io;
+use std::path::Path;
+
+use crate::colors::{parse_hex, PaletteColor};
+use crate::storage;
+
+/// Colors closer than this CIEDE2000 distance are merged when combining palettes
+const MERGE_DISTANCE: f32 = 2.3;
+
+/// Most swatches a Procreate palette can hold
+const PROCREATE_MAX_SWATCHES: usize = 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PaletteFormat {
+    /// Adobe Swatch Exchange
+    Ase,
+    /// GIMP palette
+    Gpl,
+    Procreate,
+    /// CSS custom properties
+    Css,
+    Json,
+}
+
+impl PaletteFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            PaletteFormat::Ase => "ase",
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::Procreate => "swatches",
+            PaletteFormat::Css => "css",
+            PaletteFormat::Json => "json",
+        }
+    }
+}
+
+/// Combine palettes into one, near identical colors are merged.
+///
+/// Shares are averaged over the palettes so the result still adds up to 100.
+pub fn merge_palettes(palettes: &[&[PaletteColor]]) -> Vec<PaletteColor> {
+    let mut merged: Vec<(Lab<D65, f32>, PaletteColor)> = Vec::new();
+    let count = palettes.len().max(1) as f32;
+
+    for color in palettes.iter().flat_map(|palette| palette.iter()) {
+        let Some(lab) = parse_hex(&color.hex) else {
+            continue;
+        };
+        let share = color.percentage / count;
+
+        match merged
+            .iter_mut()
+            .find(|(other, _)| lab.difference(*other) < MERGE_DISTANCE)
+        {
+            Some((_, existing)) => existing.percentage += share,
+            None => merged.push((
+                lab,
+                PaletteColor {
+                    hex: color.hex.to_lowercase(),
+                    percentage: share,
+                },
+            )),
+        }
+    }
+
+    let mut merged: Vec<PaletteColor> = merged.into_iter().map(|(_, color)| color).collect();
+    merged.sort_by(|a, b| b.percentage.total_cmp(&a.percentage));
+    merged
+}
+
+/// Encode a palette in one of the export formats
+pub fn encode_palette(
+    name: &str,
+    colors: &[PaletteColor],
+    format: PaletteFormat,
+) -> io::Result<Vec<u8>> {
+    let rgb: Vec<(&PaletteColor, Srgb<u8>)> = colors
+        .iter()
+        .map(|color| {
+            color
+                .hex
+                .parse::<Srgb<u8>>()
+                .map(|rgb| (color, rgb))
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid color '{}'", color.hex),
+                    )
+                })
+        })
+        .collect::<io::Result<_>>()?;
+
+    match format {
+        PaletteFormat::Ase => Ok(encode_ase(&rgb)),
+        PaletteFormat::Gpl => Ok(encode_gpl(name, &rgb).into_bytes()),
+        PaletteFormat::Procreate => encode_procreate(name, &rgb),
+        PaletteFormat::Css => Ok(encode_css(&rgb).into_bytes()),
+        PaletteFormat::Json => {
+            let palette = json!({ "name": name, "colors": colors });
+            Ok(serde_json::to_vec_pretty(&palette)?)
+        }
+    }
+}
+
+/// Write a palette to `path`
+pub fn export_palette(
+    path: &Path,
+    name: &str,
+    colors: &[PaletteColor],
+    format: PaletteFormat,
+) -> io::Result<()> {
+    let bytes = encode_palette(name, colors, format)?;
+    storage::write_atomic(path, bytes)
+}
+
+fn encode_ase(colors: &[(&PaletteColor, Srgb<u8>)]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"ASEF");
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes.extend_from_slice(&0u16.to_be_bytes());
+    bytes.extend_from_slice(&(colors.len() as u32).to_be_bytes());
+
+    for (color, rgb) in colors {
+        // Names are null terminated UTF-16, their length counts the terminator
+        let name: Vec<u16> = color.hex.encode_utf16().chain([0]).collect();
+        let rgb: Srgb<f32> = rgb.into_format();
+
+        let mut block = Vec::new();
+        block.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        for unit in name {
+            block.extend_from_slice(&unit.to_be_bytes());
+        }
+        block.extend_from_slice(b"RGB ");
+        for channel in [rgb.red, rgb.green, rgb.blue] {
+            block.extend_from_slice(&channel.to_be_bytes());
+        }
+        // Global color
+        block.extend_from_slice(&0u16.to_be_bytes());
+
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&(block.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&block);
+    }
+
+    bytes
+}
+
+fn encode_gpl(name: &str, colors: &[(&PaletteColor, Srgb<u8>)]) -> String {
+    let mut gpl = format!(
+        "GIMP Palette\nName: {}\nColumns: {}\n#\n",
+        name,
+        colors.len().min(16)
+    );
+    for (color, rgb) in colors {
+        gpl.push_str(&format!(
+            "{:3} {:3} {:3}\t{}\n",
+            rgb.red, rgb.green, rgb.blue, color.hex
+        ));
+    }
+    gpl
+}
+
+fn encode_css(colors: &[(&PaletteColor, Srgb<u8>)]) -> String {
+    let mut css = String::from(":root {\n");
+    for (i, (color, _)) in colors.iter().enumerate() {
+        css.push_str(&format!("  --palette-{}: {};\n", i + 1, color.hex));
+    }
+    css.push_str("}\n");
+    css
+}
+
+/// Procreate swatches are a zip holding a `Swatches.json` with HSB colors
+fn encode_procreate(name: &str, colors: &[(&PaletteColor, Srgb<u8>)]) -> io::Result<Vec<u8>> {
+    let swatches: Vec<_> = colors
+        .iter()
+        .take(PROCREATE_MAX_SWATCHES)
+        .map(|(_, rgb)| {
+            let hsv = Hsv::from_color(rgb.into_format::<f32>());
+            json!({
+                "hue": hsv.hue.into_positive_degrees() / 360.0,
+                "saturation": hsv.saturation,
+                "brightness": hsv.value,
+                "alpha": 1,
+                "colorSpace": 0,
+            })
+        })
+        .collect();
+
+    let swatches_json = serde_json::to_vec(&json!([{ "name": name, "swatches": swatches }]))?;
+    Ok(stored_zip("Swatches.json", &swatches_json))
+}
+
+/// Zip archive holding a single uncompressed file
+fn stored_zip(file_name: &str, contents: &[u8]) -> Vec<u8> {
+    let crc = crc32fast::hash(contents);
+    let size = contents.len() as u32;
+    let name = file_name.as_bytes();
+
+    // Fields shared by the local and central headers: version, flags, method, time, date,
+    // crc, sizes and name length
+    let mut common = Vec::new();
+    common.extend_from_slice(&20u16.to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes());
+    common.extend_from_slice(&0x21u16.to_le_bytes());
+    common.extend_from_slice(&crc.to_le_bytes());
+    common.extend_from_slice(&size.to_le_bytes());
+    common.extend_from_slice(&size.to_le_bytes());
+    common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes());
+
+    let mut zip = Vec::new();
+    zip.extend_from_slice(&0x04034b50u32.to_le_bytes());
+    zip.extend_from_slice(&common);
+    zip.extend_from_slice(name);
+    zip.extend_from_slice(contents);
+
+    let central_offset = zip.len() as u32;
+    zip.extend_from_slice(&0x02014b50u32.to_le_bytes());
+    zip.extend_from_slice(&20u16.to_le_bytes());
+    zip.extend_from_slice(&common);
+    // Comment length, disk number, internal and external attributes, local header offset
+    zip.extend_from_slice(&0u16.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes());
+    zip.extend_from_slice(&0u32.to_le_bytes());
+    zip.extend_from_slice(&0u32.to_le_bytes());
+    zip.extend_from_slice(name);
+    let central_size = zip.len() as u32 - central_offset;
+
+    zip.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes());
+    zip.extend_from_slice(&1u16.to_le_bytes());
+    zip.extend_from_slice(&1u16.to_le_bytes());
+    zip.extend_from_slice(&central_size.to_le_bytes());
+    zip.extend_from_slice(&central_offset.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes());
+
+    zip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(hex: &str, percentage: f32) -> PaletteColor {
+        PaletteColor {
+            hex: hex.to_string(),
+            percentage,
+        }
+    }
+
+    #[test]
+    fn test_merge_palettes() {
+        let first = [color("#ff0000", 60.0), color("#0000ff", 40.0)];
+        let second = [color("#FE0000", 100.0)];
+
+        let merged = merge_palettes(&[&first, &second]);
+        assert_eq!(merged, vec![color("#ff0000", 80.0), color("#0000ff", 20.0)]);
+    }
+
+    #[test]
+    fn test_encode_palette() {
+        let colors = [color("#ff0000", 75.0), color("#00ff80", 25.0)];
+
+        let gpl = encode_palette("Test", &colors, PaletteFormat::Gpl).unwrap();
+        let gpl = String::from_utf8(gpl).unwrap();
+        assert!(gpl.starts_with("GIMP Palette\nName: Test\n"));
+        assert!(gpl.contains("255   0   0\t#ff0000"));
+
+        let css = encode_palette("Test", &colors, PaletteFormat::Css).unwrap();
+        assert!(String::from_utf8(css)
+            .unwrap()
+            .contains("--palette-2: #00ff80;"));
+
+        let ase = encode_palette("Test", &colors, PaletteFormat::Ase).unwrap();
+        assert_eq!(&ase[..4], b"ASEF");
+        assert_eq!(u32::from_be_bytes(ase[8..12].try_into().unwrap()), 2);
+        // Header, then per block: type, length, name length, "#rrggbb\0", model, 3 floats, type
+        assert_eq!(ase.len(), 12 + 2 * (2 + 4 + 2 + 16 + 4 + 12 + 2));
+
+        let swatches = encode_palette("Test", &colors, PaletteFormat::Procreate).unwrap();
+        assert_eq!(&swatches[..4], &0x04034b50u32.to_le_bytes());
+        let json_start = 30 + "Swatches.json".len();
+        let json_size = u32::from_le_bytes(swatches[18..22].try_into().unwrap()) as usize;
+        let json: serde_json::Value =
+            serde_json::from_slice(&swatches[json_start..json_start + json_size]).unwrap();
+        assert_eq!(json[0]["swatches"][0]["saturation"], 1.0);
+
+        assert!(encode_palette("Test", &[color("red", 1.0)], PaletteFormat::Css).is_err());
+    }
+}
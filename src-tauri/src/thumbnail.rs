@@ -0,0 +1,281 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::animation;
+use crate::formats::ExtraFormat;
+use crate::media::{self, determine_media_type};
+use crate::storage;
+use crate::utils::convert_file_src;
+
+/// Folder of a ref holding its generated thumbnails
+pub const THUMBNAIL_DIR: &str = "thumbnails";
+
+/// Longest side in pixels and byte budget of every thumbnail, smallest first
+const THUMBNAIL_SIZES: [(u32, usize); 3] = [(256, 24 * 1024), (512, 64 * 1024), (1024, 192 * 1024)];
+
+/// Animated preview of animated images, next to the thumbnails
+const ANIMATED_PREVIEW: &str = "animated.gif";
+
+/// Thumbnail size shown on boards
+const PREVIEW_SIZE: u32 = 512;
+
+const MIN_QUALITY: u8 = 30;
+const MAX_QUALITY: u8 = 90;
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailFormat {
+    #[default]
+    Webp,
+    Jpeg,
+}
+
+impl ThumbnailFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Webp => "webp",
+            ThumbnailFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    /// Longest side in pixels
+    pub size: u32,
+    pub path: String,
+}
+
+/// Image types decoded through libheif, only with the `heif` feature
+const HEIF_TYPES: [&str; 3] = ["heic", "heif", "avif"];
+
+/// Whether thumbnails are generated for a file, images this build can't decode are shown as is
+pub fn supports_thumbnails(file_name: &str) -> bool {
+    let media_type = determine_media_type(file_name);
+    let needs_heif = HEIF_TYPES
+        .iter()
+        .any(|subtype| media_type.contains(subtype));
+    let needs_jxl = media_type.contains("jxl");
+
+    media_type.starts_with("image/")
+        && (!needs_heif || ExtraFormat::Heif.is_decodable())
+        && (!needs_jxl || ExtraFormat::JpegXl.is_decodable())
+}
+
+/// Whether an image is in a format this build can't decode, the webview can't show those either
+pub fn is_unsupported_image(file_name: &str) -> bool {
+    determine_media_type(file_name).starts_with("image/") && !supports_thumbnails(file_name)
+}
+
+/// Generate every thumbnail size of an image into `<ref_dir>/thumbnails`.
+///
+/// Images are never upscaled, an image smaller than every size only gets the smallest one.
+/// Animated images get still thumbnails of their first frame and an animated preview.
+/// Previous thumbnails are replaced. Returns the thumbnails smallest first.
+pub fn generate_thumbnails(
+    media_path: &Path,
+    ref_dir: &Path,
+    format: ThumbnailFormat,
+) -> io::Result<Vec<Thumbnail>> {
+    let image = media::open_image(media_path)?;
+    let longest_side = image.width().max(image.height());
+
+    let thumbnail_dir = ref_dir.join(THUMBNAIL_DIR);
+    if thumbnail_dir.exists() {
+        fs::remove_dir_all(&thumbnail_dir)?;
+    }
+    fs::create_dir_all(&thumbnail_dir)?;
+
+    let mut thumbnails = Vec::new();
+
+    for (i, (size, budget)) in THUMBNAIL_SIZES.into_iter().enumerate() {
+        if i > 0 && size >= longest_side {
+            break;
+        }
+
+        let resized = if longest_side > size {
+            image.resize(size, size, FilterType::Lanczos3)
+        } else {
+            image.clone()
+        };
+
+        let bytes = encode_within_budget(&resized, format, budget)?;
+        let path = thumbnail_dir.join(format!("{}.{}", size, format.extension()));
+        storage::write_atomic(&path, bytes)?;
+
+        thumbnails.push(Thumbnail {
+            size,
+            path: convert_file_src(&path),
+        });
+    }
+
+    if animation::read_animation(media_path)?.is_some() {
+        animation::generate_preview(media_path, &thumbnail_dir.join(ANIMATED_PREVIEW))?;
+    }
+
+    Ok(thumbnails)
+}
+
+/// Animated preview generated in a ref folder
+pub fn animated_preview(ref_dir: &Path) -> Option<String> {
+    let path = ref_dir.join(THUMBNAIL_DIR).join(ANIMATED_PREVIEW);
+    path.exists().then(|| convert_file_src(&path))
+}
+
+/// Thumbnails already generated in a ref folder, smallest first
+pub fn list_thumbnails(ref_dir: &Path) -> Vec<Thumbnail> {
+    thumbnail_files(ref_dir)
+        .into_iter()
+        .map(|(size, path)| Thumbnail {
+            size,
+            path: convert_file_src(&path),
+        })
+        .collect()
+}
+
+/// File of the largest thumbnail generated in a ref folder
+pub fn largest_thumbnail(ref_dir: &Path) -> Option<PathBuf> {
+    thumbnail_files(ref_dir).pop().map(|(_, path)| path)
+}
+
+/// Sizes and files of the thumbnails in a ref folder, smallest first
+fn thumbnail_files(ref_dir: &Path) -> Vec<(u32, PathBuf)> {
+    let Ok(entries) = fs::read_dir(ref_dir.join(THUMBNAIL_DIR)) else {
+        return Vec::new();
+    };
+
+    let mut thumbnails: Vec<(u32, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            let size = path.file_stem()?.to_str()?.parse().ok()?;
+            Some((size, path))
+        })
+        .collect();
+    thumbnails.sort();
+    thumbnails
+}
+
+/// Thumbnail shown on boards, the preview size or the closest one below it
+pub fn preview(thumbnails: &[Thumbnail]) -> Option<&Thumbnail> {
+    thumbnails
+        .iter()
+        .rev()
+        .find(|thumbnail| thumbnail.size <= PREVIEW_SIZE)
+        .or(thumbnails.first())
+}
+
+/// Encode at the highest quality fitting in `budget` bytes, or at the lowest quality if none does
+pub fn encode_within_budget(
+    image: &DynamicImage,
+    format: ThumbnailFormat,
+    budget: usize,
+) -> io::Result<Vec<u8>> {
+    let (mut low, mut high) = (MIN_QUALITY, MAX_QUALITY);
+    let mut best = None;
+
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let bytes = encode(image, format, quality)?;
+
+        if bytes.len() <= budget {
+            best = Some(bytes);
+            low = quality + 1;
+        } else if quality == MIN_QUALITY {
+            break;
+        } else {
+            high = quality - 1;
+        }
+    }
+
+    match best {
+        Some(bytes) => Ok(bytes),
+        None => encode(image, format, MIN_QUALITY),
+    }
+}
+
+fn encode(image: &DynamicImage, format: ThumbnailFormat, quality: u8) -> io::Result<Vec<u8>> {
+    match format {
+        ThumbnailFormat::Webp => {
+            let rgba = image.to_rgba8();
+            let encoded =
+                webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode(quality as f32);
+            Ok(encoded.to_vec())
+        }
+        ThumbnailFormat::Jpeg => {
+            let mut bytes = Vec::new();
+            JpegEncoder::new_with_quality(&mut bytes, quality)
+                .encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))
+                .map_err(io::Error::other)?;
+            Ok(bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_PATH: &str = "resources/test_image.png";
+
+    #[test]
+    fn test_generate_thumbnails() {
+        let ref_dir = Path::new("test_thumbnails");
+        fs::create_dir_all(ref_dir).unwrap();
+
+        for format in [ThumbnailFormat::Webp, ThumbnailFormat::Jpeg] {
+            let thumbnails = generate_thumbnails(Path::new(IMAGE_PATH), ref_dir, format).unwrap();
+            let sizes: Vec<u32> = thumbnails.iter().map(|thumbnail| thumbnail.size).collect();
+            assert_eq!(sizes, vec![256, 512, 1024]);
+            assert_eq!(list_thumbnails(ref_dir), thumbnails);
+            assert_eq!(preview(&thumbnails).unwrap().size, 512);
+
+            for (size, budget) in THUMBNAIL_SIZES {
+                let path =
+                    ref_dir
+                        .join(THUMBNAIL_DIR)
+                        .join(format!("{}.{}", size, format.extension()));
+                let encoded = image::open(&path).unwrap();
+                assert_eq!(encoded.width().max(encoded.height()), size);
+                assert!(fs::metadata(&path).unwrap().len() as usize <= budget);
+            }
+        }
+
+        fs::remove_dir_all(ref_dir).unwrap();
+    }
+
+    #[test]
+    fn test_encode_within_budget() {
+        let image = image::open(IMAGE_PATH).unwrap();
+
+        let roomy = encode_within_budget(&image, ThumbnailFormat::Jpeg, usize::MAX).unwrap();
+        let lowest = encode(&image, ThumbnailFormat::Jpeg, MIN_QUALITY).unwrap();
+        assert!(lowest.len() < roomy.len());
+
+        let budget = (lowest.len() + roomy.len()) / 2;
+        let fitted = encode_within_budget(&image, ThumbnailFormat::Jpeg, budget).unwrap();
+        assert!(fitted.len() <= budget);
+        assert!(fitted.len() >= lowest.len());
+
+        // An impossible budget still produces the smallest encoding
+        let smallest = encode_within_budget(&image, ThumbnailFormat::Jpeg, 1).unwrap();
+        assert!(!smallest.is_empty());
+    }
+
+    #[test]
+    fn test_supports_thumbnails() {
+        assert!(supports_thumbnails("image.png"));
+        assert!(supports_thumbnails("animation.gif"));
+        assert!(supports_thumbnails("logo.svg"));
+        assert_eq!(supports_thumbnails("photo.heic"), cfg!(feature = "heif"));
+        assert!(supports_thumbnails("mockup.psd"));
+        assert!(!supports_thumbnails("video.mp4"));
+
+        assert_eq!(is_unsupported_image("photo.heic"), !cfg!(feature = "heif"));
+        assert!(!is_unsupported_image("image.png"));
+        assert!(!is_unsupported_image("video.mp4"));
+    }
+}
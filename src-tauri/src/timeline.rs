@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+
+use crate::utils;
+
+/// Length of the ids of markers and ranges, unique within a ref
+const ITEM_ID_LENGTH: usize = 8;
+
+/// Point of interest in a video or audio ref
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub id: String,
+    /// Seconds from the start of the media
+    pub timestamp: f64,
+    pub label: String,
+    #[serde(default)]
+    pub note: String,
+}
+
+/// Named in/out range of a video or audio ref
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ClipRange {
+    pub id: String,
+    pub name: String,
+    /// Seconds from the start of the media
+    pub start: f64,
+    pub end: f64,
+    #[serde(default)]
+    pub note: String,
+}
+
+/// Markers and ranges of a time based ref, both kept in chronological order
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Timeline {
+    #[serde(default)]
+    pub markers: Vec<Marker>,
+    #[serde(default)]
+    pub ranges: Vec<ClipRange>,
+}
+
+/// Change made to a timeline
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimelineEdit {
+    AddMarker(Marker),
+    UpdateMarker(Marker),
+    RemoveMarker(String),
+    AddRange(ClipRange),
+    UpdateRange(ClipRange),
+    RemoveRange(String),
+}
+
+impl Marker {
+    pub fn new(timestamp: f64, label: &str, note: &str) -> Self {
+        Self {
+            id: utils::random_id(ITEM_ID_LENGTH),
+            timestamp,
+            label: label.trim().to_string(),
+            note: note.to_string(),
+        }
+    }
+}
+
+impl ClipRange {
+    pub fn new(name: &str, start: f64, end: f64, note: &str) -> Self {
+        Self {
+            id: utils::random_id(ITEM_ID_LENGTH),
+            name: name.trim().to_string(),
+            start,
+            end,
+            note: note.to_string(),
+        }
+    }
+}
+
+impl Timeline {
+    /// Apply an edit, the timeline is left untouched when it is invalid.
+    ///
+    /// Times are checked against `duration` when the length of the media is known.
+    pub fn apply(&mut self, edit: &TimelineEdit, duration: Option<f64>) -> io::Result<()> {
+        match edit {
+            TimelineEdit::AddMarker(marker) | TimelineEdit::UpdateMarker(marker) => {
+                check_time(marker.timestamp, duration)?;
+                check_name(&marker.label, "Marker label")?;
+            }
+            TimelineEdit::AddRange(range) | TimelineEdit::UpdateRange(range) => {
+                check_time(range.start, duration)?;
+                check_time(range.end, duration)?;
+                check_name(&range.name, "Range name")?;
+                if range.start >= range.end {
+                    return Err(invalid("A range must end after it starts".to_string()));
+                }
+            }
+            TimelineEdit::RemoveMarker(_) | TimelineEdit::RemoveRange(_) => {}
+        }
+
+        match edit {
+            TimelineEdit::AddMarker(marker) => {
+                if self.markers.iter().any(|found| found.id == marker.id) {
+                    return Err(invalid(format!("Marker '{}' already exists", marker.id)));
+                }
+                self.markers.push(marker.clone());
+            }
+            TimelineEdit::UpdateMarker(marker) => {
+                *find(&mut self.markers, |found| found.id == marker.id, "Marker")? = marker.clone()
+            }
+            TimelineEdit::RemoveMarker(id) => {
+                let position = position(&self.markers, |found| &found.id == id, "Marker")?;
+                self.markers.remove(position);
+            }
+            TimelineEdit::AddRange(range) => {
+                if self.ranges.iter().any(|found| found.id == range.id) {
+                    return Err(invalid(format!("Range '{}' already exists", range.id)));
+                }
+                self.ranges.push(range.clone());
+            }
+            TimelineEdit::UpdateRange(range) => {
+                *find(&mut self.ranges, |found| found.id == range.id, "Range")? = range.clone()
+            }
+            TimelineEdit::RemoveRange(id) => {
+                let position = position(&self.ranges, |found| &found.id == id, "Range")?;
+                self.ranges.remove(position);
+            }
+        }
+
+        self.markers
+            .sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+        self.ranges
+            .sort_by(|a, b| a.start.total_cmp(&b.start).then(a.end.total_cmp(&b.end)));
+
+        Ok(())
+    }
+}
+
+fn check_time(time: f64, duration: Option<f64>) -> io::Result<()> {
+    if !time.is_finite() || time < 0.0 || duration.is_some_and(|duration| time > duration) {
+        return Err(invalid(format!("{}s is outside of the media", time)));
+    }
+    Ok(())
+}
+
+fn check_name(name: &str, what: &str) -> io::Result<()> {
+    if name.trim().is_empty() {
+        return Err(invalid(format!("{} can't be empty", what)));
+    }
+    Ok(())
+}
+
+fn position<T>(items: &[T], predicate: impl Fn(&T) -> bool, what: &str) -> io::Result<usize> {
+    items
+        .iter()
+        .position(predicate)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found", what)))
+}
+
+fn find<'a, T>(
+    items: &'a mut [T],
+    predicate: impl Fn(&T) -> bool,
+    what: &str,
+) -> io::Result<&'a mut T> {
+    let position = position(items, predicate, what)?;
+    Ok(&mut items[position])
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_markers() {
+        let mut timeline = Timeline::default();
+        let camera_move = Marker::new(83.0, " Camera move ", "Slow push in");
+        let opening = Marker::new(2.5, "Opening", "");
+
+        timeline
+            .apply(&TimelineEdit::AddMarker(camera_move.clone()), Some(120.0))
+            .unwrap();
+        timeline
+            .apply(&TimelineEdit::AddMarker(opening.clone()), Some(120.0))
+            .unwrap();
+        assert_eq!(timeline.markers, vec![opening.clone(), camera_move.clone()]);
+        assert_eq!(timeline.markers[1].label, "Camera move");
+
+        // Moving a marker keeps the markers in order
+        let moved = Marker {
+            timestamp: 1.0,
+            ..camera_move.clone()
+        };
+        timeline
+            .apply(&TimelineEdit::UpdateMarker(moved.clone()), Some(120.0))
+            .unwrap();
+        assert_eq!(timeline.markers, vec![moved.clone(), opening.clone()]);
+
+        // Invalid edits leave the timeline untouched
+        let before = timeline.clone();
+        let late = Marker::new(121.0, "Late", "");
+        let unnamed = Marker::new(10.0, "  ", "");
+        for edit in [
+            TimelineEdit::AddMarker(late.clone()),
+            TimelineEdit::AddMarker(unnamed),
+            TimelineEdit::AddMarker(opening.clone()),
+            TimelineEdit::UpdateMarker(late.clone()),
+            TimelineEdit::RemoveMarker("MISSING".to_string()),
+        ] {
+            assert!(timeline.apply(&edit, Some(120.0)).is_err());
+        }
+        assert_eq!(timeline, before);
+
+        // Without a known duration any time from the start is accepted
+        timeline
+            .apply(&TimelineEdit::AddMarker(late.clone()), None)
+            .unwrap();
+        timeline
+            .apply(&TimelineEdit::RemoveMarker(moved.id), None)
+            .unwrap();
+        assert_eq!(timeline.markers, vec![opening, late]);
+    }
+
+    #[test]
+    fn test_edit_ranges() {
+        let mut timeline = Timeline::default();
+        let camera_move = ClipRange::new("Camera move", 83.0, 91.0, "");
+        let intro = ClipRange::new("Intro", 0.0, 12.0, "Title cards");
+
+        timeline
+            .apply(&TimelineEdit::AddRange(camera_move.clone()), Some(120.0))
+            .unwrap();
+        timeline
+            .apply(&TimelineEdit::AddRange(intro.clone()), Some(120.0))
+            .unwrap();
+        assert_eq!(timeline.ranges, vec![intro.clone(), camera_move.clone()]);
+
+        let reversed = ClipRange {
+            start: 91.0,
+            end: 83.0,
+            ..camera_move.clone()
+        };
+        assert_eq!(
+            timeline
+                .apply(&TimelineEdit::UpdateRange(reversed), Some(120.0))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(timeline
+            .apply(
+                &TimelineEdit::AddRange(ClipRange::new("Outro", 110.0, 130.0, "")),
+                Some(120.0)
+            )
+            .is_err());
+
+        let renamed = ClipRange {
+            name: "Dolly".to_string(),
+            ..camera_move
+        };
+        timeline
+            .apply(&TimelineEdit::UpdateRange(renamed.clone()), Some(120.0))
+            .unwrap();
+        timeline
+            .apply(&TimelineEdit::RemoveRange(intro.id), Some(120.0))
+            .unwrap();
+        assert_eq!(timeline.ranges, vec![renamed]);
+
+        // Sidecars written before timelines existed have an empty one
+        let timeline: Timeline = serde_json::from_str("{}").unwrap();
+        assert_eq!(timeline, Timeline::default());
+    }
+}
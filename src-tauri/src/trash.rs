@@ -0,0 +1,280 @@
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::parser::parse_refs;
+use crate::state::Ref;
+use crate::{storage, utils};
+
+const TRASH_INFO: &str = "trash.json";
+const TRASHED_REF_DIR: &str = "ref";
+
+/// Bookkeeping stored next to every trashed ref folder
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrashInfo {
+    pub ref_id: String,
+    pub original_path: String,
+    pub deleted_at: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TrashEntry {
+    #[serde(flatten)]
+    pub info: TrashInfo,
+    pub item: Option<Ref>,
+}
+
+/// Move a ref folder out of the collections directory into the trash.
+///
+/// The trash keeps `<ref_id>/ref/` with the original files and `<ref_id>/trash.json`.
+pub fn move_to_trash(collections_dir: &Path, trash_dir: &Path, ref_id: &str) -> io::Result<()> {
+    check_ref_id(ref_id)?;
+    let ref_dir = collections_dir.join(ref_id);
+    if !ref_dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Reference with ID '{}' not found", ref_id),
+        ));
+    }
+
+    // Never overwrite an earlier trash entry of the same id
+    let entry_dir = trash_dir.join(ref_id);
+    if entry_dir.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "Reference with ID '{}' is already in the trash, restore or purge it first",
+                ref_id
+            ),
+        ));
+    }
+    fs::create_dir_all(&entry_dir)?;
+
+    let info = TrashInfo {
+        ref_id: ref_id.to_string(),
+        original_path: ref_dir.to_string_lossy().to_string(),
+        deleted_at: Local::now().to_rfc3339(),
+    };
+
+    // The info goes first so a trashed folder always knows where it came from
+    let trashed_dir = entry_dir.join(TRASHED_REF_DIR);
+    let result = storage::write_json(&entry_dir.join(TRASH_INFO), &info)
+        .and_then(|_| storage::with_ref_lock(&ref_dir, || move_dir(&ref_dir, &trashed_dir)));
+
+    // Roll back unless files already made it into the trash
+    if result.is_err() && !trashed_dir.exists() {
+        let _ = fs::remove_dir_all(&entry_dir);
+    }
+
+    result
+}
+
+/// Move a trashed ref back into the collections directory and parse it again
+pub fn restore(collections_dir: &Path, trash_dir: &Path, ref_id: &str) -> io::Result<Ref> {
+    check_ref_id(ref_id)?;
+    let entry_dir = trash_dir.join(ref_id);
+    let ref_dir = collections_dir.join(ref_id);
+
+    if !entry_dir.join(TRASHED_REF_DIR).is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Reference with ID '{}' is not in the trash", ref_id),
+        ));
+    }
+
+    if ref_dir.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Reference with ID '{}' already exists", ref_id),
+        ));
+    }
+
+    move_dir(&entry_dir.join(TRASHED_REF_DIR), &ref_dir)?;
+    fs::remove_dir_all(&entry_dir)?;
+
+    parse_refs(&utils::get_ref_entries(&ref_dir.canonicalize()?))
+}
+
+/// List every trashed ref, most recently deleted first
+pub fn list(trash_dir: &Path) -> io::Result<Vec<TrashEntry>> {
+    if !trash_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries: Vec<TrashEntry> = utils::get_ref_dirs(trash_dir)?
+        .into_iter()
+        .filter_map(|entry_dir| {
+            let info = read_info(&entry_dir).ok()?;
+            let item = parse_refs(&utils::get_ref_entries(&entry_dir.join(TRASHED_REF_DIR))).ok();
+            Some(TrashEntry { info, item })
+        })
+        .collect();
+
+    entries.sort_by(|a, b| b.info.deleted_at.cmp(&a.info.deleted_at));
+    Ok(entries)
+}
+
+/// Permanently delete trashed refs, every one of them when `ref_ids` is `None`
+pub fn purge(trash_dir: &Path, ref_ids: Option<&[String]>) -> io::Result<usize> {
+    if !trash_dir.exists() {
+        return Ok(0);
+    }
+
+    let targets: Vec<PathBuf> = match ref_ids {
+        Some(ids) => ids
+            .iter()
+            .filter(|id| check_ref_id(id).is_ok())
+            .map(|id| trash_dir.join(id))
+            .collect(),
+        None => utils::get_ref_dirs(trash_dir)?,
+    };
+
+    let mut purged = 0;
+    for entry_dir in targets.iter().filter(|dir| dir.is_dir()) {
+        fs::remove_dir_all(entry_dir)?;
+        purged += 1;
+    }
+
+    Ok(purged)
+}
+
+/// Permanently delete refs trashed more than `retention_days` ago, `0` keeps them forever
+pub fn purge_expired(trash_dir: &Path, retention_days: u32) -> io::Result<usize> {
+    if retention_days == 0 || !trash_dir.exists() {
+        return Ok(0);
+    }
+
+    let limit = Local::now() - Duration::days(retention_days as i64);
+    let expired: Vec<String> = list(trash_dir)?
+        .into_iter()
+        .filter(|entry| {
+            DateTime::parse_from_rfc3339(&entry.info.deleted_at)
+                .map(|deleted_at| deleted_at < limit)
+                .unwrap_or(false)
+        })
+        .map(|entry| entry.info.ref_id)
+        .collect();
+
+    purge(trash_dir, Some(&expired))
+}
+
+/// Ref ids are folder names, refuse anything that could escape the library or trash directory
+pub(crate) fn check_ref_id(ref_id: &str) -> io::Result<()> {
+    if ref_id.is_empty() || ref_id.contains(['/', '\\']) || ref_id.starts_with('.') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid reference ID '{}'", ref_id),
+        ));
+    }
+    Ok(())
+}
+
+fn read_info(entry_dir: &Path) -> io::Result<TrashInfo> {
+    let info_json = fs::read_to_string(entry_dir.join(TRASH_INFO))?;
+    Ok(serde_json::from_str(&info_json)?)
+}
+
+/// Rename a directory, falling back to copy and delete across filesystems
+fn move_dir(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    copy_dir(from, to)?;
+    fs::remove_dir_all(from)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE_METADATA_PATH: &str = "resources/metadata.note.json";
+    const NOTE_ID: &str = "MXUQSL97EOQIX";
+
+    fn setup(test_name: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let root = PathBuf::from(format!("test_trash_{}", test_name));
+        let collections_dir = root.join("collections");
+        let trash_dir = root.join(".trash");
+        let ref_dir = collections_dir.join(NOTE_ID);
+
+        fs::create_dir_all(&ref_dir).expect("Failed to create ref directory");
+        fs::copy(NOTE_METADATA_PATH, ref_dir.join("metadata.note.json"))
+            .expect("Failed to copy note metadata file");
+
+        (root, collections_dir, trash_dir)
+    }
+
+    #[test]
+    fn test_trash_and_restore() {
+        let (root, collections_dir, trash_dir) = setup("restore");
+
+        move_to_trash(&collections_dir, &trash_dir, NOTE_ID).unwrap();
+        assert!(!collections_dir.join(NOTE_ID).exists());
+
+        let entries = list(&trash_dir).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].info.ref_id, NOTE_ID);
+        assert!(entries[0].item.is_some());
+
+        let restored = restore(&collections_dir, &trash_dir, NOTE_ID).unwrap();
+        assert_eq!(restored.get_id(), NOTE_ID);
+        assert!(collections_dir.join(NOTE_ID).exists());
+        assert!(list(&trash_dir).unwrap().is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_trash_id_collision() {
+        let (root, collections_dir, trash_dir) = setup("collision");
+
+        move_to_trash(&collections_dir, &trash_dir, NOTE_ID).unwrap();
+
+        // A new ref reusing the id of a trashed one
+        let ref_dir = collections_dir.join(NOTE_ID);
+        fs::create_dir_all(&ref_dir).unwrap();
+        fs::copy(NOTE_METADATA_PATH, ref_dir.join("metadata.note.json")).unwrap();
+
+        let err = move_to_trash(&collections_dir, &trash_dir, NOTE_ID).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(ref_dir.exists());
+        assert_eq!(list(&trash_dir).unwrap().len(), 1);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let (root, collections_dir, trash_dir) = setup("purge");
+
+        move_to_trash(&collections_dir, &trash_dir, NOTE_ID).unwrap();
+        assert_eq!(purge_expired(&trash_dir, 30).unwrap(), 0);
+
+        // Pretend the ref was deleted two months ago
+        let entry_dir = trash_dir.join(NOTE_ID);
+        let mut info = read_info(&entry_dir).unwrap();
+        info.deleted_at = (Local::now() - Duration::days(60)).to_rfc3339();
+        storage::write_json(&entry_dir.join(TRASH_INFO), &info).unwrap();
+
+        assert_eq!(purge_expired(&trash_dir, 0).unwrap(), 0);
+        assert_eq!(purge_expired(&trash_dir, 30).unwrap(), 1);
+        assert!(list(&trash_dir).unwrap().is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use log::info;
 use palette::{white_point::D65, IntoColor, Lab, Srgba};
+use rand::Rng;
 use serde::{de::Error, Deserialize, Deserializer};
 use serde_json::Value;
 use std::collections::{HashMap, VecDeque};
@@ -7,10 +8,28 @@ use std::fs::read_to_string;
 use std::panic::PanicInfo;
 use std::{fs, io, path::Path, path::PathBuf, sync::Mutex};
 
-use crate::parser::parse_refs;
+use crate::colors::PaletteColor;
+use crate::exif::ExifData;
+use crate::migration;
 use crate::state::{AudioMetadata, AudioRef, LinkMetadata, LinkRef, Metadata, RefMeta};
 use crate::state::{DocMetadata, DocRef, NoteMetadata, NoteRef};
 use crate::state::{ImageMetadata, ImageRef, Ref, Settings, VideoMetadata, VideoRef};
+use crate::storage;
+use crate::timeline::{Timeline, TimelineEdit};
+use crate::video::FrameSource;
+
+/// Random id made of uppercase letters and digits, used as ref folder name
+pub fn random_id(length: usize) -> String {
+    let characters = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    let mut rng = rand::thread_rng();
+
+    (0..length)
+        .map(|_| {
+            let idx = rng.gen_range(0..characters.len());
+            characters.chars().nth(idx).unwrap()
+        })
+        .collect()
+}
 
 /// Return the size of a file in human readable format
 pub fn analyze_file_size<P>(file_path: P) -> String
@@ -21,49 +40,27 @@ where
     human_size(size)
 }
 
-/// Get all subdirectories in the given path
-fn get_all_refs(collections_dir: &Path) -> io::Result<Vec<Vec<PathBuf>>> {
+/// Get every ref folder in the collections directory
+pub fn get_ref_dirs(collections_dir: &Path) -> io::Result<Vec<PathBuf>> {
     let result = fs::read_dir(collections_dir)?
         .filter_map(|entry| entry.ok())
         .filter_map(|entry| entry.path().canonicalize().ok())
         .filter(|path| path.is_dir())
-        .map(|dir| {
-            fs::read_dir(&dir)
-                .map(|entries| {
-                    entries
-                        .filter_map(|entry| entry.ok())
-                        .map(|entry| entry.path())
-                        .collect::<Vec<_>>()
-                })
-                .unwrap_or_else(|_| Vec::new())
-        })
         .collect::<Vec<_>>();
 
     Ok(result)
 }
 
-/// Get all references in the collections directory
-pub fn fetch_refs(collections_dir: &Path) -> io::Result<Mutex<Vec<Ref>>> {
-    let all_refs = get_all_refs(collections_dir)?;
-
-    let mut ref_vec = Vec::new();
-    let mut errors = Vec::new();
-
-    for ref_paths in all_refs {
-        match parse_refs(&ref_paths) {
-            Ok(ref_data) => ref_vec.push(ref_data),
-            Err(err) => errors.push(err),
-        }
-    }
-
-    if !errors.is_empty() {
-        eprintln!("Errors encountered during reference parsing:");
-        for err in &errors {
-            eprintln!(" - {}", err);
-        }
-    }
-
-    Ok(Mutex::new(ref_vec))
+/// Get the files of a single ref folder
+pub fn get_ref_entries(ref_dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(ref_dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_else(|_| Vec::new())
 }
 
 pub fn fetch_settings(settings_path: &Path) -> Mutex<Settings> {
@@ -71,92 +68,180 @@ pub fn fetch_settings(settings_path: &Path) -> Mutex<Settings> {
     Mutex::new(serde_json::from_str(&settings).unwrap_or_else(|_| Settings::default()))
 }
 
-/// Change name of a ref
-pub fn change_name(metadata_path: &Path, new_name: &str) -> Result<(), std::io::Error> {
+/// Read a metadata file into the type matching its `metadata.<type>.json` name
+pub fn read_metadata(metadata_path: &Path) -> Result<RefMeta, std::io::Error> {
     let metadata_json = fs::read_to_string(metadata_path)?;
-    let mut ref_data: RefMeta = serde_json::from_str(&metadata_json)?;
-
-    match ref_data {
-        RefMeta::Image(ref mut image_ref) => (*image_ref).set_name(new_name),
-        RefMeta::Video(ref mut video_ref) => (*video_ref).set_name(new_name),
-        RefMeta::Audio(ref mut audio_ref) => (*audio_ref).set_name(new_name),
-        RefMeta::Note(ref mut note_ref) => (*note_ref).set_name(new_name),
-        RefMeta::Doc(ref mut doc_ref) => (*doc_ref).set_name(new_name),
-        RefMeta::Link(ref mut link_ref) => (*link_ref).set_name(new_name),
-    }
+    let mut value: Value = serde_json::from_str(&metadata_json)?;
 
-    // Convert the updated Ref back to JSON
-    let updated_json = serde_json::to_string_pretty(&ref_data)?;
-    info!("Updated JSON: {}", updated_json);
+    let Some(ref_type) = migration::sidecar_kind(metadata_path) else {
+        return Ok(serde_json::from_value(value)?);
+    };
+
+    migration::upgrade(&mut value, ref_type)?;
+
+    let ref_data = match ref_type {
+        "image" => RefMeta::Image(serde_json::from_value(value)?),
+        "video" => RefMeta::Video(serde_json::from_value(value)?),
+        "audio" => RefMeta::Audio(serde_json::from_value(value)?),
+        "note" => RefMeta::Note(serde_json::from_value(value)?),
+        "link" => RefMeta::Link(serde_json::from_value(value)?),
+        "doc" => RefMeta::Doc(serde_json::from_value(value)?),
+        _ => serde_json::from_value(value)?,
+    };
 
-    fs::write(metadata_path, updated_json)?;
+    Ok(ref_data)
+}
+
+/// Read, mutate and atomically rewrite a metadata file while holding its ref lock
+pub fn update_metadata<F>(metadata_path: &Path, mutate: F) -> Result<(), std::io::Error>
+where
+    F: FnOnce(&mut RefMeta),
+{
+    storage::with_ref_lock(metadata_path, || {
+        let mut ref_data = read_metadata(metadata_path)?;
+        mutate(&mut ref_data);
+        storage::write_json(metadata_path, &ref_data)
+    })
+}
 
-    Ok(())
+/// Change name of a ref
+pub fn change_name(metadata_path: &Path, new_name: &str) -> Result<(), std::io::Error> {
+    update_metadata(metadata_path, |ref_data| match ref_data {
+        RefMeta::Image(image_ref) => image_ref.set_name(new_name),
+        RefMeta::Video(video_ref) => video_ref.set_name(new_name),
+        RefMeta::Audio(audio_ref) => audio_ref.set_name(new_name),
+        RefMeta::Note(note_ref) => note_ref.set_name(new_name),
+        RefMeta::Doc(doc_ref) => doc_ref.set_name(new_name),
+        RefMeta::Link(link_ref) => link_ref.set_name(new_name),
+    })
 }
 
 /// Add tags to a ref
 pub fn add_tag(metadata_path: &Path, tag: &str) -> Result<(), std::io::Error> {
-    let metadata_json = fs::read_to_string(metadata_path)?;
-    let mut ref_data: RefMeta = serde_json::from_str(&metadata_json)?;
-
-    match ref_data {
-        RefMeta::Image(ref mut image_ref) => (*image_ref).add_tag(tag),
-        RefMeta::Video(ref mut video_ref) => (*video_ref).add_tag(tag),
-        RefMeta::Audio(ref mut audio_ref) => (*audio_ref).add_tag(tag),
-        RefMeta::Note(ref mut note_ref) => (*note_ref).add_tag(tag),
-        RefMeta::Doc(ref mut doc_ref) => (*doc_ref).add_tag(tag),
-        RefMeta::Link(ref mut link_ref) => (*link_ref).add_tag(tag),
-    }
+    update_metadata(metadata_path, |ref_data| match ref_data {
+        RefMeta::Image(image_ref) => image_ref.add_tag(tag),
+        RefMeta::Video(video_ref) => video_ref.add_tag(tag),
+        RefMeta::Audio(audio_ref) => audio_ref.add_tag(tag),
+        RefMeta::Note(note_ref) => note_ref.add_tag(tag),
+        RefMeta::Doc(doc_ref) => doc_ref.add_tag(tag),
+        RefMeta::Link(link_ref) => link_ref.add_tag(tag),
+    })
+}
 
-    // Convert the updated Ref back to JSON
-    let updated_json = serde_json::to_string_pretty(&ref_data)?;
-    fs::write(metadata_path, updated_json)?;
+pub fn remove_tag(metadata_path: &Path, tag: &str) -> Result<(), std::io::Error> {
+    update_metadata(metadata_path, |ref_data| match ref_data {
+        RefMeta::Image(image_ref) => image_ref.remove_tag(tag),
+        RefMeta::Video(video_ref) => video_ref.remove_tag(tag),
+        RefMeta::Audio(audio_ref) => audio_ref.remove_tag(tag),
+        RefMeta::Note(note_ref) => note_ref.remove_tag(tag),
+        RefMeta::Doc(doc_ref) => doc_ref.remove_tag(tag),
+        RefMeta::Link(link_ref) => link_ref.remove_tag(tag),
+    })
+}
 
-    Ok(())
+/// Mutate note component from a ref
+pub fn mutate_note(metadata_path: &Path, text: &str) -> Result<(), std::io::Error> {
+    update_metadata(metadata_path, |ref_data| match ref_data {
+        RefMeta::Image(image_ref) => image_ref.update_note(text),
+        RefMeta::Video(video_ref) => video_ref.update_note(text),
+        RefMeta::Audio(audio_ref) => audio_ref.update_note(text),
+        RefMeta::Note(note_ref) => note_ref.update_note(text),
+        RefMeta::Doc(doc_ref) => doc_ref.update_note(text),
+        RefMeta::Link(link_ref) => link_ref.update_note(text),
+    })
 }
 
-pub fn remove_tag(metadata_path: &Path, tag: &str) -> Result<(), std::io::Error> {
-    let metadata_json = fs::read_to_string(metadata_path)?;
-    let mut ref_data: RefMeta = serde_json::from_str(&metadata_json)?;
-
-    match ref_data {
-        RefMeta::Image(ref mut image_ref) => (*image_ref).remove_tag(tag),
-        RefMeta::Video(ref mut video_ref) => (*video_ref).remove_tag(tag),
-        RefMeta::Audio(ref mut audio_ref) => (*audio_ref).remove_tag(tag),
-        RefMeta::Note(ref mut note_ref) => (*note_ref).remove_tag(tag),
-        RefMeta::Doc(ref mut doc_ref) => (*doc_ref).remove_tag(tag),
-        RefMeta::Link(ref mut link_ref) => (*link_ref).remove_tag(tag),
-    }
+/// Store the extracted palette of an image, video or audio ref
+pub fn set_colors(metadata_path: &Path, colors: &[PaletteColor]) -> Result<(), std::io::Error> {
+    update_metadata(metadata_path, |ref_data| match ref_data {
+        RefMeta::Image(image_ref) => image_ref.colors = colors.to_vec(),
+        RefMeta::Video(video_ref) => video_ref.colors = colors.to_vec(),
+        RefMeta::Audio(audio_ref) => audio_ref.colors = colors.to_vec(),
+        _ => {}
+    })
+}
 
-    // Convert the updated Ref back to JSON
-    let updated_json = serde_json::to_string_pretty(&ref_data)?;
-    fs::write(metadata_path, updated_json)?;
+/// Store the hash of the media file of a file backed ref
+pub fn set_content_hash(metadata_path: &Path, content_hash: &str) -> Result<(), std::io::Error> {
+    update_metadata(metadata_path, |ref_data| {
+        let hash = Some(content_hash.to_string());
+        match ref_data {
+            RefMeta::Image(image_ref) => image_ref.content_hash = hash,
+            RefMeta::Video(video_ref) => video_ref.content_hash = hash,
+            RefMeta::Audio(audio_ref) => audio_ref.content_hash = hash,
+            RefMeta::Doc(doc_ref) => doc_ref.content_hash = hash,
+            RefMeta::Note(_) | RefMeta::Link(_) => {}
+        }
+    })
+}
 
-    Ok(())
+/// Store the perceptual hash of an image ref
+pub fn set_perceptual_hash(
+    metadata_path: &Path,
+    perceptual_hash: &str,
+) -> Result<(), std::io::Error> {
+    update_metadata(metadata_path, |ref_data| {
+        if let RefMeta::Image(image_ref) = ref_data {
+            image_ref.perceptual_hash = Some(perceptual_hash.to_string());
+        }
+    })
 }
 
-/// Mutate note component from a ref
-pub fn mutate_note(metadata_path: &Path, text: &str) -> Result<(), std::io::Error> {
-    let metadata_json = fs::read_to_string(metadata_path)?;
-    let mut ref_data: RefMeta = serde_json::from_str(&metadata_json)?;
-
-    match ref_data {
-        RefMeta::Image(ref mut image_ref) => (*image_ref).update_note(text),
-        RefMeta::Video(ref mut video_ref) => (*video_ref).update_note(text),
-        RefMeta::Audio(ref mut audio_ref) => (*audio_ref).update_note(text),
-        RefMeta::Note(ref mut note_ref) => (*note_ref).update_note(text),
-        RefMeta::Doc(ref mut doc_ref) => (*doc_ref).update_note(text),
-        RefMeta::Link(ref mut link_ref) => (*link_ref).update_note(text),
-    }
+/// Apply an edit to the timeline of a video or audio ref, returns the updated timeline
+pub fn edit_timeline(metadata_path: &Path, edit: &TimelineEdit) -> io::Result<Timeline> {
+    // Unlike `update_metadata` the sidecar is only rewritten when the edit is accepted
+    storage::with_ref_lock(metadata_path, || {
+        let mut ref_data = read_metadata(metadata_path)?;
+        let (timeline, duration) = match &mut ref_data {
+            RefMeta::Video(video_ref) => {
+                let duration = video_ref.duration();
+                (&mut video_ref.timeline, duration)
+            }
+            RefMeta::Audio(audio_ref) => {
+                let duration = audio_ref.duration();
+                (&mut audio_ref.timeline, duration)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Only video and audio refs have a timeline",
+                ))
+            }
+        };
 
-    // Convert the updated Ref back to JSON
-    let updated_json = serde_json::to_string_pretty(&ref_data)?;
-    fs::write(metadata_path, updated_json)?;
+        timeline.apply(edit, duration)?;
+        let timeline = timeline.clone();
+        storage::write_json(metadata_path, &ref_data)?;
+        Ok(timeline)
+    })
+}
+
+/// Store the video an image ref was captured from
+pub fn set_source_video(metadata_path: &Path, source: &FrameSource) -> Result<(), std::io::Error> {
+    update_metadata(metadata_path, |ref_data| {
+        if let RefMeta::Image(image_ref) = ref_data {
+            image_ref.source_video = Some(source.clone());
+        }
+    })
+}
 
-    Ok(())
+/// Store the embedded metadata of an image ref, optionally adding its keywords to the tags
+pub fn set_exif(
+    metadata_path: &Path,
+    exif: &ExifData,
+    import_keywords: bool,
+) -> Result<(), std::io::Error> {
+    update_metadata(metadata_path, |ref_data| {
+        if let RefMeta::Image(image_ref) = ref_data {
+            if import_keywords {
+                image_ref.add_keywords(&exif.keywords);
+            }
+            image_ref.exif = Some(Box::new(exif.clone()));
+        }
+    })
 }
 
-fn human_size(size: u64) -> String {
+pub fn human_size(size: u64) -> String {
     let multiplier = 1000f64;
     let units = ["KB", "MB", "GB", "TB", "PB", "EB", "ZB"];
     let mut size = size as f64;
@@ -269,15 +354,15 @@ mod tests {
     }
 
     #[test]
-    fn test_get_all_refs() {
+    fn test_get_ref_dirs() {
         let collection_dir = Path::new("test_all_refs");
         let _ = fs::create_dir_all(collection_dir.join("dir1"));
         let _ = fs::create_dir_all(collection_dir.join("dir2"));
 
-        let refs = get_all_refs(collection_dir).unwrap_or_default();
+        let refs = get_ref_dirs(collection_dir).unwrap_or_default();
         assert_eq!(refs.len(), 2);
-        assert_eq!(refs[0].len(), 0);
-        assert_eq!(refs[1].len(), 0);
+        assert_eq!(get_ref_entries(&refs[0]).len(), 0);
+        assert_eq!(get_ref_entries(&refs[1]).len(), 0);
 
         teardown(collection_dir.to_path_buf())
     }
@@ -338,6 +423,50 @@ mod tests {
         teardown(f);
     }
 
+    #[test]
+    fn test_edit_timeline() {
+        let temp_dir = Path::new("test_edit_timeline");
+        fs::create_dir_all(temp_dir).unwrap();
+        let meta_path = temp_dir.join("metadata.video.json");
+        let video_ref = VideoMetadata {
+            video: Some(crate::video::VideoInfo {
+                duration: Some(10.0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        storage::write_json(&meta_path, &video_ref).unwrap();
+        let written = fs::metadata(&meta_path).unwrap().modified().unwrap();
+        let sidecar = fs::read(&meta_path).unwrap();
+
+        // Rejected edits leave the sidecar untouched
+        let marker = crate::timeline::Marker::new(20.0, "Past the end", "");
+        assert!(edit_timeline(&meta_path, &TimelineEdit::AddMarker(marker)).is_err());
+        assert_eq!(fs::read(&meta_path).unwrap(), sidecar);
+        assert_eq!(
+            fs::metadata(&meta_path).unwrap().modified().unwrap(),
+            written
+        );
+
+        let marker = crate::timeline::Marker::new(5.0, "Middle", "");
+        let timeline = edit_timeline(&meta_path, &TimelineEdit::AddMarker(marker)).unwrap();
+        assert_eq!(timeline.markers.len(), 1);
+        let RefMeta::Video(video_ref) = read_metadata(&meta_path).unwrap() else {
+            panic!("The sidecar is not a video");
+        };
+        assert_eq!(video_ref.timeline, timeline);
+
+        // Images have no timeline
+        let (meta_path, _, f) = setup("timeline_1", IMAGE_METADATA_PATH, "metadata.image.json");
+        let sidecar = fs::read(&meta_path).unwrap();
+        let edit = TimelineEdit::RemoveMarker("missing".to_string());
+        assert!(edit_timeline(&meta_path, &edit).is_err());
+        assert_eq!(fs::read(&meta_path).unwrap(), sidecar);
+        teardown(f);
+
+        teardown(temp_dir.to_path_buf());
+    }
+
     #[test]
     fn test_mutate_note() {
         let (meta_path, new_content, f) =
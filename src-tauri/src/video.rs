@@ -0,0 +1,622 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::Command;
+
+use crate::bmff::{boxes, find_box, read_u32, read_u64};
+use crate::thumbnail::{self, Thumbnail, ThumbnailFormat};
+
+/// Executable used to decode video frames, looked up in `PATH`
+const FFMPEG: &str = "ffmpeg";
+
+/// Largest `moov` box or Matroska element read into memory
+const MAX_HEADER_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The poster is taken a tenth into the video, at most this many seconds in
+const MAX_POSTER_OFFSET: f64 = 10.0;
+
+const EBML_HEADER: u32 = 0x1A45DFA3;
+const MKV_SEGMENT: u32 = 0x18538067;
+const MKV_INFO: u32 = 0x1549A966;
+const MKV_TIMECODE_SCALE: u32 = 0x2AD7B1;
+const MKV_DURATION: u32 = 0x4489;
+const MKV_TRACKS: u32 = 0x1654AE6B;
+const MKV_TRACK_ENTRY: u32 = 0xAE;
+const MKV_TRACK_TYPE: u32 = 0x83;
+const MKV_CODEC_ID: u32 = 0x86;
+const MKV_DEFAULT_DURATION: u32 = 0x23E383;
+const MKV_VIDEO: u32 = 0xE0;
+const MKV_PIXEL_WIDTH: u32 = 0xB0;
+const MKV_PIXEL_HEIGHT: u32 = 0xBA;
+const MKV_CLUSTER: u32 = 0x1F43B675;
+
+/// Stream details read from the container of a video
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct VideoInfo {
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    /// Displayed width and height, rotation included
+    pub dimensions: Option<(u32, u32)>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub frame_rate: Option<f64>,
+    /// Average bitrate in bits per second
+    pub bitrate: Option<u64>,
+}
+
+/// Read the stream details of an MP4, MOV, WebM or MKV file
+pub fn read_video_info(file_path: &Path) -> io::Result<VideoInfo> {
+    let mut file = File::open(file_path)?;
+    let file_size = file.metadata()?.len();
+
+    let mut head = [0u8; 8];
+    file.read_exact(&mut head)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut info = if u32::from_be_bytes(head[..4].try_into().unwrap()) == EBML_HEADER {
+        read_matroska(&mut file, file_size)?
+    } else if &head[4..] == b"ftyp" || &head[4..] == b"moov" || &head[4..] == b"wide" {
+        read_mp4(&mut file, file_size)?
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Only MP4, MOV, WebM and MKV videos are supported",
+        ));
+    };
+
+    info.bitrate = info
+        .duration
+        .filter(|duration| *duration > 0.0)
+        .map(|duration| (file_size as f64 * 8.0 / duration).round() as u64);
+
+    Ok(info)
+}
+
+/// Video and time a still was captured from
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct FrameSource {
+    pub video_id: String,
+    /// Seconds from the start of the video
+    pub timestamp: f64,
+}
+
+/// File name of a frame captured from `video_file_name`, e.g. `clip_12.500s.png`
+pub fn frame_file_name(video_file_name: &str, timestamp: f64) -> String {
+    let stem = Path::new(video_file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("frame");
+    format!("{}_{:.3}s.png", stem, timestamp)
+}
+
+/// Time of the frame used as poster
+pub fn poster_timestamp(duration: Option<f64>) -> f64 {
+    duration.map_or(0.0, |duration| (duration * 0.1).min(MAX_POSTER_OFFSET))
+}
+
+/// Decode the frame shown at `timestamp` seconds into an image at `output`.
+///
+/// Frames are decoded by ffmpeg, an error explains when it is not installed.
+pub fn extract_frame(video_path: &Path, timestamp: f64, output: &Path) -> io::Result<()> {
+    let result = Command::new(FFMPEG)
+        .args([
+            "-v",
+            "error",
+            "-y",
+            "-ss",
+            &format!("{:.3}", timestamp.max(0.0)),
+            "-i",
+        ])
+        .arg(video_path)
+        .args(["-frames:v", "1", "-an"])
+        .arg(output)
+        .output();
+
+    let output_status = match result {
+        Ok(output_status) => output_status,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "ffmpeg must be installed to extract video frames",
+            ))
+        }
+        Err(e) => return Err(e),
+    };
+
+    if !output_status.status.success() || !output.exists() {
+        let _ = fs::remove_file(output);
+        return Err(io::Error::other(format!(
+            "Failed to extract the frame at {:.3}s: {}",
+            timestamp,
+            String::from_utf8_lossy(&output_status.stderr).trim()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Generate the thumbnails of a video from its poster frame into `<ref_dir>/thumbnails`
+pub fn generate_poster(
+    media_path: &Path,
+    ref_dir: &Path,
+    duration: Option<f64>,
+    format: ThumbnailFormat,
+) -> io::Result<Vec<Thumbnail>> {
+    // The frame is kept out of the ref folder, where it would be taken for the media file
+    let frame_path = std::env::temp_dir().join(format!(
+        "poster-{}.png",
+        ref_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("video")
+    ));
+
+    extract_frame(media_path, poster_timestamp(duration), &frame_path)?;
+    let thumbnails = thumbnail::generate_thumbnails(&frame_path, ref_dir, format);
+    let _ = fs::remove_file(&frame_path);
+
+    thumbnails
+}
+
+/// Readable name of a codec identifier from either container
+fn codec_name(codec: &str) -> String {
+    let name = match codec {
+        "avc1" | "avc3" | "V_MPEG4/ISO/AVC" => "H.264",
+        "hvc1" | "hev1" | "V_MPEGH/ISO/HEVC" => "HEVC",
+        "av01" | "V_AV1" => "AV1",
+        "vp08" | "V_VP8" => "VP8",
+        "vp09" | "V_VP9" => "VP9",
+        "apch" | "apcn" | "apcs" | "apco" | "ap4h" => "ProRes",
+        "mp4v" => "MPEG-4",
+        "mp4a" | "A_AAC" => "AAC",
+        "Opus" | "A_OPUS" => "Opus",
+        "A_VORBIS" => "Vorbis",
+        "fLaC" | "A_FLAC" => "FLAC",
+        "ac-3" | "A_AC3" => "AC-3",
+        "ec-3" | "A_EAC3" => "E-AC-3",
+        ".mp3" | "A_MPEG/L3" => "MP3",
+        "lpcm" | "sowt" | "twos" | "A_PCM/INT/LIT" => "PCM",
+        other => other.trim(),
+    };
+    name.to_string()
+}
+
+fn read_mp4(file: &mut File, file_size: u64) -> io::Result<VideoInfo> {
+    read_moov(file, file_size).map(|moov| parse_moov(&moov))
+}
+
+/// Body of the movie box of an MP4 or MOV file, also used by M4A audio
+pub(crate) fn read_moov(file: &mut File, file_size: u64) -> io::Result<Vec<u8>> {
+    let mut position = 0;
+
+    // Only the movie box is read, media data can be gigabytes
+    while position + 8 <= file_size {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header[..8])?;
+
+        let mut size = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+        let mut header_size = 8;
+        if size == 1 {
+            file.read_exact(&mut header[8..])?;
+            size = u64::from_be_bytes(header[8..].try_into().unwrap());
+            header_size = 16;
+        } else if size == 0 {
+            size = file_size - position;
+        }
+        if size < header_size {
+            break;
+        }
+
+        if &header[4..8] == b"moov" {
+            let body_size = (size - header_size).min(MAX_HEADER_SIZE);
+            let mut moov = vec![0; body_size as usize];
+            file.read_exact(&mut moov)?;
+            return Ok(moov);
+        }
+
+        // Sizes past the end of the file, or overflowing, end the walk
+        position = match position.checked_add(size) {
+            Some(next) if next <= file_size => next,
+            _ => break,
+        };
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The file has no movie header",
+    ))
+}
+
+pub(crate) fn parse_moov(moov: &[u8]) -> VideoInfo {
+    let mut info = VideoInfo::default();
+
+    if let Some(mvhd) = find_box(moov, b"mvhd") {
+        let (timescale, duration) = match mvhd.first() {
+            Some(1) => (read_u32(mvhd, 20), read_u64(mvhd, 24)),
+            _ => (read_u32(mvhd, 12), read_u32(mvhd, 16).map(u64::from)),
+        };
+        info.duration = seconds(duration, timescale);
+    }
+
+    for (kind, trak) in boxes(moov) {
+        if kind != b"trak" {
+            continue;
+        }
+        let Some(mdia) = find_box(trak, b"mdia") else {
+            continue;
+        };
+        let handler = find_box(mdia, b"hdlr").and_then(|hdlr| hdlr.get(8..12));
+        let codec = find_box(mdia, b"minf")
+            .and_then(|minf| find_box(minf, b"stbl"))
+            .and_then(|stbl| find_box(stbl, b"stsd"))
+            .and_then(|stsd| stsd.get(12..16))
+            .map(|fourcc| codec_name(&String::from_utf8_lossy(fourcc)));
+
+        match handler {
+            Some(b"vide") if info.video_codec.is_none() => {
+                info.video_codec = codec;
+                info.dimensions = find_box(trak, b"tkhd").and_then(track_dimensions);
+                info.frame_rate = track_frame_rate(mdia);
+            }
+            Some(b"soun") if info.audio_codec.is_none() => info.audio_codec = codec,
+            _ => {}
+        }
+    }
+
+    info
+}
+
+/// Width and height of a track, swapped when its matrix turns it a quarter
+fn track_dimensions(tkhd: &[u8]) -> Option<(u32, u32)> {
+    let matrix = match tkhd.first()? {
+        1 => 52,
+        _ => 40,
+    };
+    let width = read_u32(tkhd, matrix + 36)? >> 16;
+    let height = read_u32(tkhd, matrix + 40)? >> 16;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let scale_x = read_u32(tkhd, matrix)?;
+    let rotate = read_u32(tkhd, matrix + 4)?;
+    if scale_x == 0 && rotate != 0 {
+        Some((height, width))
+    } else {
+        Some((width, height))
+    }
+}
+
+/// Average frames per second, from the sample count and the media duration
+fn track_frame_rate(mdia: &[u8]) -> Option<f64> {
+    let mdhd = find_box(mdia, b"mdhd")?;
+    let (timescale, duration) = match mdhd.first()? {
+        1 => (read_u32(mdhd, 20), read_u64(mdhd, 24)),
+        _ => (read_u32(mdhd, 12), read_u32(mdhd, 16).map(u64::from)),
+    };
+    let duration = seconds(duration, timescale)?;
+
+    let stts = find_box(find_box(find_box(mdia, b"minf")?, b"stbl")?, b"stts")?;
+    let entries = read_u32(stts, 4)? as usize;
+    let samples: u64 = (0..entries)
+        .map_while(|i| read_u32(stts, 8 + i * 8))
+        .map(u64::from)
+        .sum();
+
+    (samples > 0 && duration > 0.0).then(|| round_rate(samples as f64 / duration))
+}
+
+fn seconds(duration: Option<u64>, timescale: Option<u32>) -> Option<f64> {
+    let timescale = timescale.filter(|timescale| *timescale > 0)?;
+    Some(duration? as f64 / timescale as f64)
+}
+
+/// Frame rates are kept to 3 decimals, enough for 23.976
+fn round_rate(rate: f64) -> f64 {
+    (rate * 1000.0).round() / 1000.0
+}
+
+fn read_matroska(file: &mut File, file_size: u64) -> io::Result<VideoInfo> {
+    let mut info = VideoInfo::default();
+    let mut timecode_scale = 1_000_000u64;
+    let mut duration = None;
+    let mut found_tracks = false;
+    let mut position = 0;
+
+    while position < file_size {
+        file.seek(SeekFrom::Start(position))?;
+        let Some((id, size, header_size)) = read_element_header(file)? else {
+            break;
+        };
+        let body_start = position + header_size;
+        let size = size.unwrap_or(file_size - body_start);
+
+        match id {
+            // The segment holds every other element, descend into it
+            MKV_SEGMENT => {
+                position = body_start;
+                continue;
+            }
+            MKV_INFO => {
+                for (id, value) in ebml_elements(&read_body(file, size)?) {
+                    match id {
+                        MKV_TIMECODE_SCALE => {
+                            timecode_scale = ebml_uint(value).unwrap_or(timecode_scale)
+                        }
+                        MKV_DURATION => duration = ebml_float(value),
+                        _ => {}
+                    }
+                }
+            }
+            MKV_TRACKS => {
+                read_tracks(&read_body(file, size)?, &mut info);
+                found_tracks = true;
+            }
+            MKV_CLUSTER if found_tracks => break,
+            _ => {}
+        }
+
+        position = body_start + size;
+    }
+
+    info.duration = duration.map(|duration| duration * timecode_scale as f64 / 1e9);
+    Ok(info)
+}
+
+fn read_tracks(tracks: &[u8], info: &mut VideoInfo) {
+    for (id, entry) in ebml_elements(tracks) {
+        if id != MKV_TRACK_ENTRY {
+            continue;
+        }
+
+        let mut track_type = None;
+        let mut codec = None;
+        let mut frame_duration = None;
+        let mut dimensions = (None, None);
+
+        for (id, value) in ebml_elements(entry) {
+            match id {
+                MKV_TRACK_TYPE => track_type = ebml_uint(value),
+                MKV_CODEC_ID => codec = Some(codec_name(&String::from_utf8_lossy(value))),
+                MKV_DEFAULT_DURATION => frame_duration = ebml_uint(value),
+                MKV_VIDEO => {
+                    for (id, value) in ebml_elements(value) {
+                        match id {
+                            MKV_PIXEL_WIDTH => dimensions.0 = ebml_uint(value),
+                            MKV_PIXEL_HEIGHT => dimensions.1 = ebml_uint(value),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match track_type {
+            Some(1) if info.video_codec.is_none() => {
+                info.video_codec = codec;
+                info.dimensions = match dimensions {
+                    (Some(width), Some(height)) => Some((width as u32, height as u32)),
+                    _ => None,
+                };
+                info.frame_rate = frame_duration
+                    .filter(|nanoseconds| *nanoseconds > 0)
+                    .map(|nanoseconds| round_rate(1e9 / nanoseconds as f64));
+            }
+            Some(2) if info.audio_codec.is_none() => info.audio_codec = codec,
+            _ => {}
+        }
+    }
+}
+
+fn read_body(file: &mut File, size: u64) -> io::Result<Vec<u8>> {
+    let mut body = vec![0; size.min(MAX_HEADER_SIZE) as usize];
+    file.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// Id, size and header length of the element at the reader position, `None` at the end.
+///
+/// Unknown sizes, used by live streams, are `None`.
+fn read_element_header(reader: &mut impl Read) -> io::Result<Option<(u32, Option<u64>, u64)>> {
+    let mut first = [0u8];
+    if reader.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+    let Some((id, id_length)) = read_vint(first[0], reader, true)? else {
+        return Ok(None);
+    };
+
+    reader.read_exact(&mut first)?;
+    let Some((size, size_length)) = read_vint(first[0], reader, false)? else {
+        return Ok(None);
+    };
+    let unknown = size == (1 << (7 * size_length)) - 1;
+
+    Ok(Some((
+        id as u32,
+        (!unknown).then_some(size),
+        (id_length + size_length) as u64,
+    )))
+}
+
+/// Variable length integer starting with `first`, ids keep their length marker
+fn read_vint(
+    first: u8,
+    reader: &mut impl Read,
+    keep_marker: bool,
+) -> io::Result<Option<(u64, usize)>> {
+    let length = first.leading_zeros() as usize + 1;
+    if length > 8 {
+        return Ok(None);
+    }
+
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        (first & (0xFF >> length)) as u64
+    };
+    let mut rest = [0u8; 7];
+    reader.read_exact(&mut rest[..length - 1])?;
+    for byte in &rest[..length - 1] {
+        value = (value << 8) | *byte as u64;
+    }
+
+    Ok(Some((value, length)))
+}
+
+/// Child elements of a buffered element as (id, body)
+fn ebml_elements(mut data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    std::iter::from_fn(move || {
+        let mut reader = data;
+        let (id, size, header) = read_element_header(&mut reader).ok()??;
+        let header = header as usize;
+        let size = size.map_or(data.len() - header, |size| size as usize);
+        let body = data.get(header..header.checked_add(size)?)?;
+        data = &data[header + size..];
+        Some((id, body))
+    })
+}
+
+fn ebml_uint(value: &[u8]) -> Option<u64> {
+    (value.len() <= 8).then(|| value.iter().fold(0, |acc, byte| (acc << 8) | *byte as u64))
+}
+
+fn ebml_float(value: &[u8]) -> Option<f64> {
+    match value.len() {
+        4 => Some(f32::from_be_bytes(value.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(value.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIDEO_PATH: &str = "resources/test_video.mp4";
+
+    /// EBML element with a one byte size
+    fn element(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.push(0x80 | body.len() as u8);
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn test_read_mp4_info() {
+        let info = read_video_info(Path::new(VIDEO_PATH)).unwrap();
+
+        assert_eq!(info.dimensions, Some((1280, 720)));
+        assert_eq!(info.video_codec.as_deref(), Some("H.264"));
+        assert_eq!(info.audio_codec.as_deref(), Some("AAC"));
+        assert!((info.duration.unwrap() - 9.915).abs() < 0.01);
+        assert!((info.frame_rate.unwrap() - 30.0).abs() < 0.1);
+        assert!(info.bitrate.unwrap() > 700_000);
+
+        assert!(read_video_info(Path::new("resources/test_image.png")).is_err());
+    }
+
+    #[test]
+    fn test_read_moov_bad_sizes() {
+        let base_path = Path::new("test_read_moov_bad_sizes");
+        fs::create_dir_all(base_path).unwrap();
+        let path = base_path.join("broken.mp4");
+
+        // A 64 bit size overflowing the position, then past the end of the file
+        for size in [u64::MAX, 1 << 40] {
+            let mut data = b"\0\0\0\x08free\0\0\0\x01mdat".to_vec();
+            data.extend_from_slice(&size.to_be_bytes());
+            data.extend_from_slice(b"\0\0\0\x08moov");
+            fs::write(&path, &data).unwrap();
+
+            let mut file = File::open(&path).unwrap();
+            let error = read_moov(&mut file, data.len() as u64).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+
+        fs::remove_dir_all(base_path).unwrap();
+    }
+
+    #[test]
+    fn test_read_matroska_info() {
+        let video = element(
+            &[0xE0],
+            &[
+                element(&[0xB0], &[0x07, 0x80]),
+                element(&[0xBA], &[0x04, 0x38]),
+            ]
+            .concat(),
+        );
+        let video_track = element(
+            &[0xAE],
+            &[
+                element(&[0x83], &[1]),
+                element(&[0x86], b"V_VP9"),
+                // 41708333ns per frame, 23.976 fps
+                element(&[0x23, 0xE3, 0x83], &41_708_333u32.to_be_bytes()),
+                video,
+            ]
+            .concat(),
+        );
+        let audio_track = element(
+            &[0xAE],
+            &[element(&[0x83], &[2]), element(&[0x86], b"A_OPUS")].concat(),
+        );
+        let info = element(
+            &[0x15, 0x49, 0xA9, 0x66],
+            &[
+                element(&[0x2A, 0xD7, 0xB1], &[0x0F, 0x42, 0x40]),
+                element(&[0x44, 0x89], &12_500.0f64.to_be_bytes()),
+            ]
+            .concat(),
+        );
+        let tracks = element(
+            &[0x16, 0x54, 0xAE, 0x6B],
+            &[video_track, audio_track].concat(),
+        );
+
+        let mut webm = element(&[0x1A, 0x45, 0xDF, 0xA3], &element(&[0x42, 0x82], b"webm"));
+        // Segment of unknown size, as written by live encoders
+        webm.extend_from_slice(&[0x18, 0x53, 0x80, 0x67, 0xFF]);
+        webm.extend(info);
+        webm.extend(tracks);
+        webm.extend(element(&[0x1F, 0x43, 0xB6, 0x75], &[0; 16]));
+
+        let path = Path::new("test_read_matroska_info.webm");
+        fs::write(path, &webm).unwrap();
+        let info = read_video_info(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(info.dimensions, Some((1920, 1080)));
+        assert_eq!(info.video_codec.as_deref(), Some("VP9"));
+        assert_eq!(info.audio_codec.as_deref(), Some("Opus"));
+        assert_eq!(info.duration, Some(12.5));
+        assert_eq!(info.frame_rate, Some(23.976));
+        assert_eq!(
+            info.bitrate,
+            Some((webm.len() as f64 * 8.0 / 12.5).round() as u64)
+        );
+    }
+
+    #[test]
+    fn test_frame_file_name() {
+        assert_eq!(frame_file_name("clip.mp4", 12.5), "clip_12.500s.png");
+        assert_eq!(frame_file_name("my.film.mkv", 0.0), "my.film_0.000s.png");
+
+        // No frame is left behind for unreadable files, whether ffmpeg is installed or not
+        let output = Path::new("test_extract_frame.png");
+        assert!(extract_frame(Path::new("resources/metadata.note.json"), 1.0, output).is_err());
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_poster_timestamp() {
+        assert_eq!(poster_timestamp(None), 0.0);
+        assert_eq!(poster_timestamp(Some(20.0)), 2.0);
+        assert_eq!(poster_timestamp(Some(3600.0)), MAX_POSTER_OFFSET);
+    }
+}
@@ -0,0 +1,122 @@
+use log::{debug, info};
+use notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+use crate::config::get_collection_path;
+use crate::index::{FolderChange, LibraryIndex};
+
+/// How long the collections directory has to stay quiet before changes are applied
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(750);
+
+/// Keeps the collections directory watched for as long as it is alive
+pub struct LibraryWatcher {
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+/// Watch the collections directory and mirror external changes into the index.
+///
+/// Emits `ref-added` and `ref-updated` with the parsed ref, and `ref-removed` with its id.
+/// Changes made by the app itself already match the index and don't emit anything.
+pub fn watch_collections(handle: &AppHandle) -> Result<LibraryWatcher, notify::Error> {
+    let collections_dir = get_collection_path(handle);
+    let collections_dir = collections_dir.canonicalize().unwrap_or(collections_dir);
+
+    let event_handle = handle.clone();
+    let root = collections_dir.clone();
+
+    let mut debouncer =
+        new_debouncer(
+            DEBOUNCE_DELAY,
+            move |result: DebounceEventResult| match result {
+                Ok(events) => {
+                    let folders = affected_folders(&root, events.iter().map(|event| &event.path));
+                    apply_changes(&event_handle, folders);
+                }
+                Err(e) => eprintln!("Error watching collections directory: {}", e),
+            },
+        )?;
+
+    debouncer
+        .watcher()
+        .watch(&collections_dir, RecursiveMode::Recursive)?;
+
+    info!("Watching {}", collections_dir.display());
+
+    Ok(LibraryWatcher {
+        _debouncer: debouncer,
+    })
+}
+
+/// Reduce changed paths to the ref folders directly under `root` that contain them
+fn affected_folders<'a, I>(root: &Path, paths: I) -> HashSet<PathBuf>
+where
+    I: IntoIterator<Item = &'a PathBuf>,
+{
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let relative = path.strip_prefix(root).ok()?;
+            match relative.components().next()? {
+                Component::Normal(folder) if !folder.to_string_lossy().starts_with('.') => {
+                    Some(root.join(folder))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn apply_changes(handle: &AppHandle, folders: HashSet<PathBuf>) {
+    let index = handle.state::<LibraryIndex>();
+
+    for folder in folders {
+        let emitted = match index.sync_folder(&folder) {
+            Ok(FolderChange::Unchanged) => Ok(()),
+            Ok(FolderChange::Added(ref_data)) => handle.emit_all("ref-added", ref_data),
+            Ok(FolderChange::Updated(ref_data)) => handle.emit_all("ref-updated", ref_data),
+            Ok(FolderChange::Removed(ref_ids)) => ref_ids
+                .into_iter()
+                .try_for_each(|ref_id| handle.emit_all("ref-removed", ref_id)),
+            Ok(FolderChange::Invalid(reason)) => {
+                // Usually a folder still being written, the next event will pick it up
+                debug!("Skipping {}: {}", folder.display(), reason);
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("Error syncing {}: {}", folder.display(), e);
+                Ok(())
+            }
+        };
+
+        if let Err(e) = emitted {
+            eprintln!("Error emitting library change: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_affected_folders() {
+        let root = Path::new("/data/collections");
+        let paths = vec![
+            root.join("REF1").join("metadata.image.json"),
+            root.join("REF1").join("lower_image.png"),
+            root.join("REF2"),
+            root.join(".trash").join("REF3"),
+            PathBuf::from("/elsewhere/REF4"),
+        ];
+
+        let folders = affected_folders(root, &paths);
+
+        assert_eq!(folders.len(), 2);
+        assert!(folders.contains(&root.join("REF1")));
+        assert!(folders.contains(&root.join("REF2")));
+    }
+}
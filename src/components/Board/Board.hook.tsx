@@ -3,12 +3,7 @@ import { open } from '@tauri-apps/api/dialog';
 import { onCleanup, createSignal, createRoot } from 'solid-js';
 import { ProgressionProps, useFileSelectorReturnType } from './Board.types';
 import { SUPPORTED_FILES } from '~/lib/config';
-import {
-  createAudioRef,
-  createDocumentRef,
-  createImageRef,
-  createVideoRef,
-} from '~/lib/commands';
+import { importFiles } from '~/lib/commands';
 import { sep } from '@tauri-apps/api/path';
 import { verifyExtension } from '~/lib/helper';
 
@@ -52,22 +47,7 @@ export const useFileSelector = createRoot(() => {
           return null;
         }
 
-        switch (type) {
-          case 'image':
-            await createImageRef(file, fileName, collection);
-            break;
-          case 'video':
-            await createVideoRef(file, fileName, collection);
-            break;
-          case 'audio':
-            await createAudioRef(file, fileName, collection);
-            break;
-          case 'document':
-            await createDocumentRef(file, fileName, collection);
-            break;
-          default:
-            console.error('Error: Unknown file type');
-        }
+        await importFiles([file], collection);
 
         setProgress({
           total: progress().total,
@@ -1,11 +1,11 @@
-import { Switch, Match, Show } from 'solid-js';
+import { Switch, Match, Show, createSignal } from 'solid-js';
 import { BoardItemProps } from './BoardItem.types.ts';
 import { ImageRef, LinkRef, NoteRef, VideoRef } from '~/lib/types.ts';
 import { showMenu } from 'tauri-plugin-context-menu';
 
 import { Dialog, DialogTrigger } from '../ui/dialog';
 import { Skeleton } from '../ui/skeleton';
-import { ViewBox } from '../ViewBox/ViewBox.tsx';
+import { UnsupportedFormat, ViewBox } from '../ViewBox/ViewBox.tsx';
 import { NoteContent } from './BoardNoteItem.tsx';
 import { createItems } from '~/lib/helper.ts';
 import { getSettings } from '~/resources/settings.resource.ts';
@@ -31,6 +31,14 @@ export const BoardItem = (props: BoardItemProps) => {
 
 // Render an image into the board
 const ImageItem = (props: { mediaInfo: ImageRef }) => {
+  const [hovered, setHovered] = createSignal(false);
+
+  // Animated images play their preview while hovered
+  const background = () =>
+    hovered() && props.mediaInfo.animated_preview
+      ? props.mediaInfo.animated_preview
+      : props?.mediaInfo?.low_res_imagepath;
+
   const showcontext = async (e: MouseEvent) => {
     e.preventDefault();
     showMenu({
@@ -51,10 +59,23 @@ const ImageItem = (props: { mediaInfo: ImageRef }) => {
           class={`cursor-pointer rounded-xl border border-transparent bg-cover bg-center bg-no-repeat shadow-md transition-all duration-300 hover:border-primary hover:shadow-inner hover:shadow-foreground/20 focus-visible:border-primary focus-visible:outline-none `}
           style={{
             'aspect-ratio': `${props?.mediaInfo?.metadata?.dimensions[0]}/${props?.mediaInfo?.metadata?.dimensions[1]}`,
-            'background-image': `url(${props?.mediaInfo?.low_res_imagepath})`,
+            'background-image': `url(${background()})`,
           }}
+          onMouseEnter={() => setHovered(true)}
+          onMouseLeave={() => setHovered(false)}
           tabindex="0"
-        />
+        >
+          <Show
+            when={
+              props.mediaInfo.unsupported_format &&
+              !props.mediaInfo.low_res_imagepath
+            }
+          >
+            <UnsupportedFormat
+              mediaType={props.mediaInfo.metadata.media_type}
+            />
+          </Show>
+        </div>
         {props.mediaInfo.metadata.name === '' ? null : (
           <p class="mt-[10px] h-5 overflow-hidden text-ellipsis whitespace-nowrap text-center text-sm font-medium text-muted/80">
             {props.mediaInfo.metadata.name}
@@ -94,6 +115,7 @@ const VideoItem = (props: { mediaInfo: VideoRef }) => {
           <video
             class="absolute h-full w-full rounded-xl object-cover"
             src={props?.mediaInfo?.video_path}
+            poster={props?.mediaInfo?.poster ?? undefined}
             preload={
               settings()?.appearance.video_ref_autoplay ? 'auto' : 'metadata'
             }
@@ -16,6 +16,28 @@ import { ViewBoxInfo } from './ViewBoxInfo';
 import { NoteEditor } from '../BoardItem/BoardNoteItem';
 import { ViewBoxZoom } from './ViewBoxZoom';
 
+/// RAW and Photoshop files can't be shown by the webview, their largest thumbnail is.
+/// Formats this build can't decode have no path to show.
+const displayPath = (ref: ImageRef) => {
+  if (ref.unsupported_format && !ref.thumbnails?.length) return undefined;
+
+  const mediaType = ref.metadata.media_type;
+  const needsPreview =
+    mediaType.startsWith('image/x-') ||
+    mediaType === 'image/vnd.adobe.photoshop';
+
+  return needsPreview
+    ? ref.thumbnails?.at(-1)?.path ?? ref.image_path
+    : ref.image_path;
+};
+
+// Placeholder of images this build can't decode, the webview can't show them either
+export const UnsupportedFormat = (props: { mediaType: string }) => (
+  <div class="flex h-full w-full items-center justify-center rounded-xl bg-muted/10 p-4 text-center text-sm font-medium text-muted/80">
+    {props.mediaType} is not supported in this build
+  </div>
+);
+
 export const ViewBox: Component<ParentProps & { source: Ref }> = (props) => {
   let videoRef: HTMLVideoElement | undefined;
 
@@ -65,17 +87,26 @@ export const ViewBox: Component<ParentProps & { source: Ref }> = (props) => {
               ></video>
             </Match>
             <Match when={props.source.metadata.ref_type === 'image'}>
-              <ViewBoxZoom>
-                <img
-                  src={(props.source as ImageRef).image_path}
-                  loading="lazy"
-                  class="h-auto w-auto rounded-xl"
-                  style={{
-                    'max-width': `min(100%, ${(props.source as ImageRef).metadata.dimensions[0]}px )`,
-                    'max-height': `min(100%, ${(props.source as ImageRef).metadata.dimensions[1]}px )`,
-                  }}
-                />
-              </ViewBoxZoom>
+              <Show
+                when={displayPath(props.source as ImageRef)}
+                fallback={
+                  <UnsupportedFormat
+                    mediaType={(props.source as ImageRef).metadata.media_type}
+                  />
+                }
+              >
+                <ViewBoxZoom>
+                  <img
+                    src={displayPath(props.source as ImageRef)}
+                    loading="lazy"
+                    class="h-auto w-auto rounded-xl"
+                    style={{
+                      'max-width': `min(100%, ${(props.source as ImageRef).metadata.dimensions[0]}px )`,
+                      'max-height': `min(100%, ${(props.source as ImageRef).metadata.dimensions[1]}px )`,
+                    }}
+                  />
+                </ViewBoxZoom>
+              </Show>
             </Match>
             <Match when={props.source.metadata.ref_type === 'note'}>
               <NoteEditor source={props.source as NoteRef} />
@@ -108,7 +139,7 @@ export const ViewBox: Component<ParentProps & { source: Ref }> = (props) => {
                         onClick={async () => {
                           copied(true);
                           setTimeout(() => copied(false), 800);
-                          await writeText(color);
+                          await writeText(color.hex);
                         }}
                         as="div"
                       >
@@ -117,10 +148,12 @@ export const ViewBox: Component<ParentProps & { source: Ref }> = (props) => {
                           classList={{
                             'border-white border': index() === 0,
                           }}
-                          style={{ background: color }}
+                          style={{ background: color.hex }}
                         />
                         <TooltipContent>
-                          {copy() ? 'Copied!' : color}
+                          {copy()
+                            ? 'Copied!'
+                            : `${color.hex} · ${Math.round(color.percentage)}%`}
                         </TooltipContent>
                       </TooltipTrigger>
                     </Tooltip>
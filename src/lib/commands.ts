@@ -1,93 +1,121 @@
 import { invoke } from '@tauri-apps/api';
-import { createRefDir, deleteRefDir, makepath, refExist } from './helper';
+import { createRefDir, refExist } from './helper';
 import {
   Ref,
   GenerateID,
   NoteRef,
-  ImageRef,
-  VideoRef,
-  DocRef,
-  AudioRef,
   LinkRef,
+  ImportReport,
+  DirectoryImportOptions,
+  TrashEntry,
+  DuplicateGroup,
+  SimilarGroup,
+  Job,
+  ColorMatch,
+  PaletteFormat,
+  ImageRef,
+  Marker,
+  ClipRange,
+  Timeline,
 } from './types';
-import { copyFile } from '@tauri-apps/api/fs';
 import { emit } from '@tauri-apps/api/event';
 
-export const createImageRef = async (
-  imagePath: string,
-  fileName: string,
+/// Copy files into the library and create a ref for each of them
+export const importFiles = async (
+  paths: string[],
   collectionName: string,
-): Promise<Ref | null> => {
+): Promise<ImportReport | null> => {
   try {
-    const mediaID = await generate_id({ lenght: 13, createDir: true });
-    const destPath = await makepath(mediaID, fileName);
-    await copyFile(imagePath, destPath);
-
-    const data: ImageRef = await invoke('generate_image_metadata', {
-      refId: mediaID,
-      fileName: fileName,
+    const report: ImportReport = await invoke('import_files', {
+      paths,
       collection: collectionName,
     });
 
-    if (data) {
-      emit('ref_added', data);
+    for (const ref of report.imported) {
+      emit('ref_added', ref);
     }
 
-    return data;
+    for (const failure of report.failed) {
+      console.error(`Failed to import ${failure.path}: ${failure.error}`);
+    }
+
+    return report;
   } catch (e) {
     console.error(e);
     return null;
   }
 };
 
-export const createVideoRef = async (
-  videoPath: string,
-  fileName: string,
-  collectionName: string,
-): Promise<Ref | null> => {
+/// Save the frame of a video at `timestamp` seconds as a new image ref
+export const captureVideoFrame = async (
+  refID: string,
+  timestamp: number,
+): Promise<ImageRef | null> => {
   try {
-    const mediaID = await generate_id({ lenght: 13, createDir: true });
-    const destPath = await makepath(mediaID, fileName);
-    await copyFile(videoPath, destPath);
-
-    const data: VideoRef = await invoke('generate_video_metadata', {
-      refId: mediaID,
-      fileName: fileName,
-      collection: collectionName,
+    const imageRef: ImageRef = await invoke('capture_video_frame', {
+      refId: refID,
+      timestamp,
     });
-
-    if (data) {
-      emit('ref_added', data);
-    }
-
-    return data;
+    emit('ref_added', imageRef);
+    return imageRef;
   } catch (e) {
     console.error(e);
     return null;
   }
 };
 
-export const createAudioRef = async (
-  audioPath: string,
-  fileName: string,
+/// Import every matching file of a directory in the background.
+/// Progress comes through the `import-progress` and `import-finished` events.
+export const importDirectory = async (
+  path: string,
   collectionName: string,
-): Promise<Ref | null> => {
+  options?: DirectoryImportOptions,
+): Promise<string | null> => {
   try {
-    const mediaID = await generate_id({ lenght: 13, createDir: true });
-    const destPath = await makepath(mediaID, fileName);
-    await copyFile(audioPath, destPath);
-
-    const data: AudioRef = await invoke('generate_audio_metadata', {
-      refId: mediaID,
-      fileName: fileName,
+    return await invoke('import_directory', {
+      path,
       collection: collectionName,
+      options: options ?? null,
     });
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
 
-    if (data) {
-      emit('ref_added', data);
-    }
+/// Stop a running directory import, files already imported are kept
+export const cancelImport = async (jobID: string) => {
+  try {
+    await invoke('cancel_import', { jobId: jobID });
+  } catch (e) {
+    console.error(e);
+  }
+};
 
-    return data;
+/// List the background jobs, updates come through the `job-updated` event
+export const listJobs = async (): Promise<Job[]> => {
+  try {
+    return await invoke('list_jobs');
+  } catch (e) {
+    console.error(e);
+    return [];
+  }
+};
+
+/// Stop a queued or running background job
+export const cancelJob = async (jobID: string): Promise<Job | null> => {
+  try {
+    return await invoke('cancel_job', { jobId: jobID });
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
+
+/// Run a failed or cancelled background job again
+export const retryJob = async (jobID: string): Promise<Job | null> => {
+  try {
+    return await invoke('retry_job', { jobId: jobID });
   } catch (e) {
     console.error(e);
     return null;
@@ -115,33 +143,6 @@ export const createNoteRef = async (
   }
 };
 
-export const createDocumentRef = async (
-  docPath: string,
-  fileName: string,
-  collectionName: string,
-): Promise<Ref | null> => {
-  try {
-    const mediaID = await generate_id({ lenght: 13, createDir: true });
-    const destPath = await makepath(mediaID, fileName);
-    await copyFile(docPath, destPath);
-
-    const data: DocRef = await invoke('generate_audio_metadata', {
-      refId: mediaID,
-      fileName: fileName,
-      collection: collectionName,
-    });
-
-    if (data) {
-      emit('ref_added', data);
-    }
-
-    return data;
-  } catch (e) {
-    console.error(e);
-    return null;
-  }
-};
-
 export const createLinkRef = async (url: string, collectionName: string) => {
   const linkID = await generate_id({ lenght: 13, createDir: true });
 
@@ -176,16 +177,152 @@ export const renameRef = async (
   }
 };
 
-// Delete a ref with its metadata
+// Move a ref with its metadata to the trash
 export const deleteRef = async (collectionID: string) => {
   try {
     await invoke('remove_ref', { refId: collectionID });
-    await deleteRefDir(collectionID);
   } catch (e) {
     console.error(e);
   }
 };
 
+/// List the refs currently in the trash
+export const listTrash = async (): Promise<TrashEntry[]> => {
+  try {
+    return await invoke('list_trash');
+  } catch (e) {
+    console.error(e);
+    return [];
+  }
+};
+
+/// Move a trashed ref back into its collection
+export const restoreRef = async (refID: string): Promise<Ref | null> => {
+  try {
+    const data: Ref = await invoke('restore_ref', { refId: refID });
+
+    if (data) {
+      emit('ref_added', data);
+    }
+
+    return data;
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
+
+/// Permanently delete trashed refs, the whole trash when no id is given
+export const purgeTrash = async (refIDs?: string[]) => {
+  try {
+    await invoke('purge_trash', { refIds: refIDs ?? null });
+  } catch (e) {
+    console.error(e);
+  }
+};
+
+/// List the refs sharing the exact same file, oldest ref first in each group
+export const findDuplicates = async (): Promise<DuplicateGroup[]> => {
+  try {
+    return await invoke('find_duplicates');
+  } catch (e) {
+    console.error(e);
+    return [];
+  }
+};
+
+/// Cluster images that look alike, a higher distance groups looser matches
+export const findSimilarImages = async (
+  maxDistance?: number,
+): Promise<SimilarGroup[]> => {
+  try {
+    return await invoke('find_similar_images', {
+      maxDistance: maxDistance ?? null,
+    });
+  } catch (e) {
+    console.error(e);
+    return [];
+  }
+};
+
+/// Read the embedded metadata of images never read, or of the given ones.
+/// Returns the amount of refs updated.
+export const backfillExif = async (refIDs?: string[]): Promise<number> => {
+  try {
+    return await invoke('backfill_exif', { refIds: refIDs ?? null });
+  } catch (e) {
+    console.error(e);
+    return 0;
+  }
+};
+
+/// Extract the colors of images again, every image when no id is given.
+/// Updated refs come back through the `ref-updated` event.
+export const reextractColors = async (refIDs?: string[]): Promise<Job[]> => {
+  try {
+    return await invoke('reextract_colors', { refIds: refIDs ?? null });
+  } catch (e) {
+    console.error(e);
+    return [];
+  }
+};
+
+/// Find images containing a color, the tolerance is a CIEDE2000 distance
+export const searchByColor = async (
+  hex: string,
+  tolerance?: number,
+): Promise<ColorMatch[]> => {
+  try {
+    return await invoke('search_by_color', {
+      hex,
+      tolerance: tolerance ?? null,
+    });
+  } catch (e) {
+    console.error(e);
+    return [];
+  }
+};
+
+/// Find images with a palette close to the palette of a ref
+export const findSimilarPalettes = async (
+  refID: string,
+  tolerance?: number,
+): Promise<ColorMatch[]> => {
+  try {
+    return await invoke('find_similar_palettes', {
+      refId: refID,
+      tolerance: tolerance ?? null,
+    });
+  } catch (e) {
+    console.error(e);
+    return [];
+  }
+};
+
+/// List the images along the hue wheel of their dominant color
+export const sortByHue = async (): Promise<Ref[]> => {
+  try {
+    return await invoke('sort_by_hue');
+  } catch (e) {
+    console.error(e);
+    return [];
+  }
+};
+
+/// Merge the palettes of images into a swatch file, returns the written path
+export const exportPalette = async (
+  refIDs: string[],
+  format: PaletteFormat,
+  path: string,
+): Promise<string | null> => {
+  try {
+    return await invoke('export_palette', { refIds: refIDs, format, path });
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
+
 /// Add a tag to a ref
 export const addTag = async (id: string, path: string, tag: string) => {
   try {
@@ -195,6 +332,96 @@ export const addTag = async (id: string, path: string, tag: string) => {
   }
 };
 
+/// Add a marker to a video or audio ref, returns its updated timeline
+export const addMarker = async (
+  refID: string,
+  timestamp: number,
+  label: string,
+  note?: string,
+): Promise<Timeline | null> => {
+  try {
+    return await invoke('add_marker', {
+      refId: refID,
+      timestamp,
+      label,
+      note: note ?? null,
+    });
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
+
+export const updateMarker = async (
+  refID: string,
+  marker: Marker,
+): Promise<Timeline | null> => {
+  try {
+    return await invoke('update_marker', { refId: refID, marker });
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
+
+export const removeMarker = async (
+  refID: string,
+  markerID: string,
+): Promise<Timeline | null> => {
+  try {
+    return await invoke('remove_marker', { refId: refID, markerId: markerID });
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
+
+/// Add a named in/out range to a video or audio ref, returns its updated timeline
+export const addClipRange = async (
+  refID: string,
+  name: string,
+  start: number,
+  end: number,
+  note?: string,
+): Promise<Timeline | null> => {
+  try {
+    return await invoke('add_clip_range', {
+      refId: refID,
+      name,
+      start,
+      end,
+      note: note ?? null,
+    });
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
+
+export const updateClipRange = async (
+  refID: string,
+  range: ClipRange,
+): Promise<Timeline | null> => {
+  try {
+    return await invoke('update_clip_range', { refId: refID, range });
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
+
+export const removeClipRange = async (
+  refID: string,
+  rangeID: string,
+): Promise<Timeline | null> => {
+  try {
+    return await invoke('remove_clip_range', { refId: refID, rangeId: rangeID });
+  } catch (e) {
+    console.error(e);
+    return null;
+  }
+};
+
 /// Remove the tag of a ref
 export const removeTag = async (id: string, path: string, tag: string) => {
   try {
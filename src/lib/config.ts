@@ -31,14 +31,35 @@ export const breakpoints_6 = createMasonryBreakpoints(() => [
 export const SUPPORTED_FILES = [
   {
     name: 'image',
-    extensions: ['png', 'jpeg', 'webp', 'gif', 'jpg'],
+    extensions: [
+      'png',
+      'jpeg',
+      'webp',
+      'gif',
+      'jpg',
+      'avif',
+      'heic',
+      'heif',
+      'jxl',
+      'svg',
+      'psd',
+      'arw',
+      'cr2',
+      'dng',
+      'nef',
+      'nrw',
+      'pef',
+      'raf',
+      'sr2',
+      'srw',
+    ],
   },
   {
     name: 'video',
-    extensions: ['mp4', 'avi', 'webm'],
+    extensions: ['mp4', 'mov', 'mkv', 'avi', 'webm'],
   },
   {
     name: 'audio',
-    extensions: ['mp3', 'wav', 'opus', 'aac', 'm4a', 'ogg'],
+    extensions: ['mp3', 'wav', 'opus', 'aac', 'm4a', 'ogg', 'flac'],
   },
 ];
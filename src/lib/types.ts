@@ -1,20 +1,32 @@
 import { ContextMenu } from 'tauri-plugin-context-menu';
 
+export interface Thumbnail {
+  size: number;
+  path: string;
+}
+
 export interface ImageRef {
   image_path: string;
   low_res_imagepath: string;
+  thumbnails?: Thumbnail[];
+  animated_preview?: string | null;
+  unsupported_format?: boolean;
   metapath: string;
   metadata: ImageMetadata;
 }
 
 export interface VideoRef {
   video_path: string;
+  thumbnails?: Thumbnail[];
+  poster?: string | null;
   metadata: VideoMetadata;
   metapath: string;
 }
 
 export interface AudioRef {
   audio_path: string;
+  thumbnails?: Thumbnail[];
+  cover?: string | null;
   metadata: AudioMetadata;
   metapath: string;
 }
@@ -37,6 +49,96 @@ export interface DocRef {
   metadata: DocMetadata;
 }
 
+export interface PaletteColor {
+  hex: string;
+  /// Share of the image covered by the color, from 0 to 100
+  percentage: number;
+}
+
+export interface GpsPosition {
+  latitude: number;
+  longitude: number;
+  altitude?: number | null;
+}
+
+/// Camera, IPTC and XMP metadata embedded in an image
+export interface ExifData {
+  camera_make?: string | null;
+  camera_model?: string | null;
+  lens?: string | null;
+  captured_at?: string | null;
+  exposure_time?: string | null;
+  f_number?: number | null;
+  iso?: number | null;
+  focal_length?: number | null;
+  orientation?: number | null;
+  gps?: GpsPosition | null;
+  author?: string | null;
+  copyright?: string | null;
+  keywords: string[];
+}
+
+export interface VideoInfo {
+  /// Seconds
+  duration?: number | null;
+  dimensions?: [number, number] | null;
+  video_codec?: string | null;
+  audio_codec?: string | null;
+  frame_rate?: number | null;
+  /// Bits per second
+  bitrate?: number | null;
+}
+
+export interface AudioInfo {
+  title?: string | null;
+  artist?: string | null;
+  album?: string | null;
+  genres?: string[];
+  bpm?: number | null;
+  /// Seconds
+  duration?: number | null;
+  sample_rate?: number | null;
+  channels?: number | null;
+}
+
+/// Video and time a still was captured from
+export interface FrameSource {
+  video_id: string;
+  /// Seconds from the start of the video
+  timestamp: number;
+}
+
+/// Point of interest in a video or audio ref
+export interface Marker {
+  id: string;
+  /// Seconds from the start of the media
+  timestamp: number;
+  label: string;
+  note: string;
+}
+
+/// Named in/out range of a video or audio ref, in seconds
+export interface ClipRange {
+  id: string;
+  name: string;
+  start: number;
+  end: number;
+  note: string;
+}
+
+export interface Timeline {
+  markers: Marker[];
+  ranges: ClipRange[];
+}
+
+export interface Animation {
+  frame_count: number;
+  /// Loop count stored in the file, 0 loops forever
+  loop_count: number;
+  /// Duration of one loop in milliseconds
+  duration: number;
+}
+
 export interface ImageMetadata {
   id: string;
   name: string;
@@ -45,8 +147,13 @@ export interface ImageMetadata {
   media_type: string;
   dimensions: [number, number];
   file_size: string;
+  content_hash?: string | null;
+  perceptual_hash?: string | null;
   collection: string;
-  colors: string[];
+  colors: PaletteColor[];
+  exif?: ExifData | null;
+  animation?: Animation | null;
+  source_video?: FrameSource | null;
   created_at: string;
   updated_at: string;
   note_text: string;
@@ -61,7 +168,11 @@ export interface VideoMetadata {
   media_type: string;
   dimensions: [number, number];
   file_size: string;
+  content_hash?: string | null;
+  video?: VideoInfo | null;
   collection: string;
+  colors?: PaletteColor[];
+  timeline?: Timeline;
   created_at: string;
   updated_at: string;
   note_text: string;
@@ -75,7 +186,11 @@ export interface AudioMetadata {
   ref_type: 'audio';
   media_type: string;
   file_size: string;
+  content_hash?: string | null;
+  audio?: AudioInfo | null;
   collection: string;
+  colors?: PaletteColor[];
+  timeline?: Timeline;
   created_at: string;
   updated_at: string;
   note_text: string;
@@ -109,6 +224,7 @@ export interface DocMetadata {
   id: string;
   name: string;
   ref_type: 'doc';
+  content_hash?: string | null;
   collection: string;
   created_at: string;
   updated_at: string;
@@ -151,6 +267,99 @@ export interface changeNoteEvent {
   path: string;
 }
 
+export interface TrashEntry {
+  ref_id: string;
+  original_path: string;
+  deleted_at: string;
+  item: Ref | null;
+}
+
+export interface ImportFailure {
+  path: string;
+  error: string;
+}
+
+export interface ImportReport {
+  imported: Ref[];
+  failed: ImportFailure[];
+}
+
+export interface DirectoryImportOptions {
+  include?: string[];
+  exclude?: string[];
+  extensions?: string[];
+  min_size?: number;
+  max_size?: number;
+  subfolders?: 'ignore' | 'collection' | 'tags';
+  move_files?: boolean;
+  workers?: number;
+}
+
+export interface ImportProgress {
+  job_id: string;
+  seen: number;
+  imported: number;
+  skipped: number;
+  failed: number;
+  finished: boolean;
+  cancelled: boolean;
+}
+
+export interface ImportResult extends ImportProgress {
+  failures: ImportFailure[];
+}
+
+export interface DuplicateGroup {
+  content_hash: string;
+  refs: Ref[];
+}
+
+export type PaletteFormat = 'ase' | 'gpl' | 'procreate' | 'css' | 'json';
+
+export interface ColorMatch {
+  ref: Ref;
+  /// Distance weighted by coverage, lower is closer
+  distance: number;
+  /// Share of the image close to the searched color, from 0 to 100
+  coverage: number;
+}
+
+export interface SimilarGroup {
+  refs: Ref[];
+}
+
+/// Payload of the `colors-added` event
+export interface ColorsExtracted {
+  ref_id: string;
+  colors: PaletteColor[];
+}
+
+export type JobKind =
+  | {
+      kind: 'extract_colors';
+      ref_id: string;
+      media_path: string;
+      metadata_path: string;
+    }
+  | { kind: 'link_snapshot'; ref_id: string; url: string };
+
+export type JobStatus =
+  | 'queued'
+  | 'running'
+  | 'completed'
+  | 'failed'
+  | 'cancelled';
+
+export type Job = JobKind & {
+  id: string;
+  status: JobStatus;
+  progress: number;
+  attempts: number;
+  error: string | null;
+  created_at: string;
+  updated_at: string;
+};
+
 export interface AppSettings {
   appearance: AppearanceSettings;
   behavior: BehaviorSettings;
@@ -163,9 +372,23 @@ interface AppearanceSettings {
 
 interface BehaviorSettings {
   sort_by: BehaviorSettingsSortBy;
+  trash_retention_days: number;
+  thumbnail_format: 'webp' | 'jpeg';
+  palette: PaletteOptions;
+  import_keywords_as_tags: boolean;
+  import_genres_as_tags: boolean;
+}
+
+interface PaletteOptions {
+  color_count: number;
+  algorithm: 'kmeans' | 'median_cut' | 'octree';
+  ignore_white: boolean;
+  ignore_black: boolean;
+  ignore_transparent: boolean;
 }
 
 enum BehaviorSettingsSortBy {
   creation_time,
   modification_time,
+  capture_time,
 }
@@ -44,11 +44,37 @@ export async function setupListeners(root: RootState): Promise<UnlistenFn[]> {
     unlisteners.push(deleteRefListener);
 
     const refAddedListener = await listen('ref_added', (event) => {
-      root.addRef(event.payload as Ref);
+      const ref = event.payload as Ref;
+      // Importing a duplicate hands back the ref that is already there
+      if (!root.ref.some((r) => r.metadata.id === ref.metadata.id)) {
+        root.addRef(ref);
+      }
     });
 
     unlisteners.push(refAddedListener);
 
+    // Changes made to the collections directory outside of the app
+    const externalRefAddedListener = await listen('ref-added', (event) => {
+      const ref = event.payload as Ref;
+      if (!root.ref.some((r) => r.metadata.id === ref.metadata.id)) {
+        root.addRef(ref);
+      }
+    });
+
+    unlisteners.push(externalRefAddedListener);
+
+    const externalRefUpdatedListener = await listen('ref-updated', (event) => {
+      root.replaceRef(event.payload as Ref);
+    });
+
+    unlisteners.push(externalRefUpdatedListener);
+
+    const externalRefRemovedListener = await listen('ref-removed', (event) => {
+      root.deleteRef(event.payload as string);
+    });
+
+    unlisteners.push(externalRefRemovedListener);
+
     const refNameChangedListener = await listen('ref_name_changed', (event) => {
       const values = event.payload as changeNameEvent;
       renameRef(values.id, values.name, values.path);
@@ -71,7 +71,7 @@ const Settings = () => {
                 <Label for="sort-by">Sort items by</Label>
                 <Select
                   id="sort-by"
-                  options={['Creation_Time', 'Last_Modified']}
+                  options={['Creation_Time', 'Last_Modified', 'Capture_Time']}
                   placeholder="Sort By ..."
                   value={settings()?.behavior.sort_by}
                   itemComponent={(props) => (
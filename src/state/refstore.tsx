@@ -7,6 +7,7 @@ import { getUpdatedAtTimestamp } from '~/lib/helper';
 export interface RootState {
   readonly ref: Ref[];
   addRef: (ref: Ref) => void;
+  replaceRef: (ref: Ref) => void;
   deleteRef: (id: string) => void;
   mutateTag: (id: string, tags: string, type: 'add' | 'remove') => void;
   mutateName: (id: string, name: string) => void;
@@ -59,6 +60,10 @@ export const RefProvider: ParentComponent = (props) => {
     setRef(produce((refs) => refs.unshift(ref)));
   };
 
+  const replaceRef = (ref: Ref) => {
+    setRef((meta) => meta.metadata.id === ref.metadata.id, ref);
+  };
+
   const mutateName = (id: string, name: string) => {
     setRef((meta) => meta.metadata.id === id, 'metadata', 'name', name);
   };
@@ -94,6 +99,7 @@ export const RefProvider: ParentComponent = (props) => {
       return ref;
     },
     addRef,
+    replaceRef,
     deleteRef,
     mutateTag,
     mutateName,